@@ -1,14 +1,15 @@
 use crate::error::Error;
-use crate::manifest::{Inheritable, Manifest, Root};
+use crate::manifest::{Flavor, Inheritable, Manifest, Root};
 use cargo_subcommand::{Artifact, ArtifactType, CrateType, Profile, Subcommand};
-use ndk_build::apk::{Apk, ApkConfig};
+use ndk_build::apk::{ActivityType, Apk, ApkConfig, UnalignedApk};
 use ndk_build::cargo::{cargo_ndk, VersionCode};
 use ndk_build::dylibs::get_libs_search_paths;
 use ndk_build::error::NdkError;
-use ndk_build::manifest::{IntentFilter, MetaData};
-use ndk_build::ndk::{Key, Ndk};
+use ndk_build::manifest::MetaData;
+use ndk_build::ndk::{Key, KeyPassword, Ndk, UninstallResult, UninstallStatus};
 use ndk_build::target::Target;
 use std::path::PathBuf;
+use std::process::Command;
 
 pub struct ApkBuilder<'a> {
     cmd: &'a Subcommand,
@@ -17,20 +18,43 @@ pub struct ApkBuilder<'a> {
     build_dir: PathBuf,
     build_targets: Vec<Target>,
     device_serial: Option<String>,
+    flavor: Option<String>,
+    offline: bool,
 }
 
 impl<'a> ApkBuilder<'a> {
     pub fn from_subcommand(
         cmd: &'a Subcommand,
         device_serial: Option<String>,
+        flavor: Option<String>,
+        offline: bool,
     ) -> Result<Self, Error> {
         println!(
             "Using package `{}` in `{}`",
             cmd.package(),
             cmd.manifest().display()
         );
-        let ndk = Ndk::from_env()?;
+        let mut ndk = Ndk::from_env()?;
+        ndk.require_version(ndk_build::ndk::MIN_NDK_VERSION)?;
         let mut manifest = Manifest::parse_from_toml(cmd.manifest())?;
+        if let Some(version) = &manifest.build_tools_version {
+            ndk.set_build_tools_version(version)?;
+        }
+        for (name, flavor) in &manifest.flavors {
+            if let Some(signing) = &flavor.signing {
+                if !manifest.signing.contains_key(signing) {
+                    return Err(Error::UnknownSigningConfig {
+                        flavor: name.clone(),
+                        signing: signing.clone(),
+                    });
+                }
+            }
+        }
+        if let Some(flavor) = &flavor {
+            if !manifest.flavors.contains_key(flavor) {
+                return Err(Error::UnknownFlavor(flavor.clone()));
+            }
+        }
         let workspace_manifest: Option<Root> = cmd
             .workspace_manifest()
             .map(Root::parse_from_toml)
@@ -92,7 +116,7 @@ impl<'a> ApkBuilder<'a> {
             panic!("version_code should not be set in TOML");
         }
 
-        let target_sdk_version = *manifest
+        manifest
             .android_manifest
             .sdk
             .target_sdk_version
@@ -104,28 +128,18 @@ impl<'a> ApkBuilder<'a> {
             .debuggable
             .get_or_insert_with(|| *cmd.profile() == Profile::Dev);
 
-        let activity = &mut manifest.android_manifest.application.activity;
-
-        // Add a default `MAIN` action to launch the activity, if the user didn't supply it by hand.
-        if activity
-            .intent_filter
-            .iter()
-            .all(|i| i.actions.iter().all(|f| f != "android.intent.action.MAIN"))
-        {
-            activity.intent_filter.push(IntentFilter {
-                actions: vec!["android.intent.action.MAIN".to_string()],
-                categories: vec!["android.intent.category.LAUNCHER".to_string()],
-                data: vec![],
-            });
+        let application = &mut manifest.android_manifest.application;
+        if manifest.dex.is_some() {
+            application.has_code = true;
         }
 
-        // Export the sole Rust activity on Android S and up, if the user didn't explicitly do so.
-        // Without this, apps won't start on S+.
-        // https://developer.android.com/about/versions/12/behavior-changes-12#exported
-        if target_sdk_version >= 31 {
-            activity.exported.get_or_insert(true);
+        let activity = &mut application.activity;
+        if activity.name == ActivityType::Native.activity_name() {
+            activity.name = manifest.activity_type.activity_name().to_string();
         }
 
+        manifest.android_manifest.add_launcher_defaults();
+
         Ok(Self {
             cmd,
             ndk,
@@ -133,17 +147,44 @@ impl<'a> ApkBuilder<'a> {
             build_dir,
             build_targets,
             device_serial,
+            flavor,
+            offline,
         })
     }
 
+    /// `cargo` configured for the NDK, passing `--offline` when requested
+    fn cargo(&self, target: Target) -> Result<Command, NdkError> {
+        let mut cargo = cargo_ndk(
+            &self.ndk,
+            target,
+            self.min_sdk_version(),
+            self.cmd.target_dir(),
+        )?;
+        if self.offline {
+            cargo.arg("--offline");
+        }
+        Ok(cargo)
+    }
+
+    fn flavor(&self) -> Option<&Flavor> {
+        self.flavor
+            .as_ref()
+            .map(|flavor| &self.manifest.flavors[flavor])
+    }
+
+    /// Staging and output directory of the APK for `artifact`, separate per flavor so that
+    /// flavors don't overwrite each other's manifest and libraries.
+    fn artifact_build_dir(&self, artifact: &Artifact) -> PathBuf {
+        let build_dir = self.build_dir.join(artifact.build_dir());
+        match &self.flavor {
+            Some(flavor) => build_dir.join(flavor),
+            None => build_dir,
+        }
+    }
+
     pub fn check(&self) -> Result<(), Error> {
         for target in &self.build_targets {
-            let mut cargo = cargo_ndk(
-                &self.ndk,
-                *target,
-                self.min_sdk_version(),
-                self.cmd.target_dir(),
-            )?;
+            let mut cargo = self.cargo(*target)?;
             cargo.arg("check");
             if self.cmd.target().is_none() {
                 let triple = target.rust_triple();
@@ -151,25 +192,48 @@ impl<'a> ApkBuilder<'a> {
             }
             self.cmd.args().apply(&mut cargo);
             if !cargo.status()?.success() {
-                return Err(NdkError::CmdFailed(cargo).into());
+                return Err(NdkError::CmdFailed(Box::new(cargo)).into());
             }
         }
         Ok(())
     }
 
-    pub fn build(&self, artifact: &Artifact) -> Result<Apk, Error> {
-        // Set artifact specific manifest default values.
-        let mut manifest = self.manifest.android_manifest.clone();
+    /// Manifest package of `artifact` when built as `flavor`
+    fn package_name(&self, artifact: &Artifact, flavor: Option<&Flavor>) -> String {
+        let mut package = flavor
+            .and_then(|flavor| flavor.package.clone())
+            .unwrap_or_else(|| self.manifest.android_manifest.package.clone());
 
-        if manifest.package.is_empty() {
+        if package.is_empty() {
             let name = artifact.name.replace('-', "_");
-            manifest.package = match artifact.r#type {
+            package = match artifact.r#type {
                 ArtifactType::Lib => format!("rust.{}", name),
                 ArtifactType::Bin => format!("rust.{}", name),
                 ArtifactType::Example => format!("rust.example.{}", name),
             };
         }
 
+        if let Some(suffix) = flavor.and_then(|flavor| flavor.package_suffix.as_ref()) {
+            package.push_str(suffix);
+        }
+
+        package
+    }
+
+    /// Configuration of the APK of `artifact` for the selected flavor
+    fn apk_config(&self, artifact: &Artifact) -> Result<ApkConfig, Error> {
+        // Set artifact specific manifest default values.
+        let mut manifest = self.manifest.android_manifest.clone();
+        let flavor = self.flavor();
+
+        manifest.package = self.package_name(artifact, flavor);
+
+        if let Some(suffix) = flavor.and_then(|flavor| flavor.version_name_suffix.as_ref()) {
+            if let Some(version_name) = &mut manifest.version_name {
+                version_name.push_str(suffix);
+            }
+        }
+
         if manifest.application.label.is_empty() {
             manifest.application.label = artifact.name.to_string();
         }
@@ -183,76 +247,194 @@ impl<'a> ApkBuilder<'a> {
 
         let is_debug_profile = *self.cmd.profile() == Profile::Dev;
 
-        let assets = self
+        let assets = flavor
+            .and_then(|flavor| flavor.assets.as_ref())
+            .or(self.manifest.assets.as_ref())
+            .map(|assets| {
+                assets
+                    .paths()
+                    .iter()
+                    .map(|path| dunce::simplified(&crate_path.join(path)).to_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let resources = flavor
+            .and_then(|flavor| flavor.resources.as_ref())
+            .or(self.manifest.resources.as_ref())
+            .map(|res| dunce::simplified(&crate_path.join(res)).to_owned());
+        let icon = self
             .manifest
-            .assets
+            .launcher_icon
             .as_ref()
-            .map(|assets| dunce::simplified(&crate_path.join(assets)).to_owned());
-        let resources = self
+            .map(|icon| dunce::simplified(&crate_path.join(icon)).to_owned());
+        let manifest_overlay = self
             .manifest
-            .resources
+            .manifest_overlay
             .as_ref()
-            .map(|res| dunce::simplified(&crate_path.join(res)).to_owned());
-        let runtime_libs = self
-            .manifest
-            .runtime_libs
-            .as_ref()
-            .map(|libs| dunce::simplified(&crate_path.join(libs)).to_owned());
-        let apk_name = self
+            .map(|overlay| dunce::simplified(&crate_path.join(overlay)).to_owned());
+        let mut apk_name = self
             .manifest
             .apk_name
             .clone()
             .unwrap_or_else(|| artifact.name.to_string());
+        if let Some(flavor) = &self.flavor {
+            apk_name = format!("{}-{}", apk_name, flavor);
+        }
 
-        let config = ApkConfig {
+        let strip_overrides = self
+            .manifest
+            .strip_overrides
+            .iter()
+            .map(|(triple, strip)| Ok((Target::from_rust_triple(triple)?, *strip)))
+            .collect::<Result<_, NdkError>>()?;
+        Ok(ApkConfig {
             ndk: self.ndk.clone(),
-            build_dir: self.build_dir.join(artifact.build_dir()),
+            build_dir: self.artifact_build_dir(artifact),
+            staging_dir: None,
+            clean: true,
             apk_name,
             assets,
             resources,
+            icon,
             manifest,
+            manifest_overlay,
+            manifest_placeholders: flavor
+                .map(|flavor| flavor.manifest_placeholders.clone())
+                .unwrap_or_default(),
+            application_id: None,
             disable_aapt_compression: is_debug_profile,
+            compression_level: self.manifest.compression_level,
+            no_compress_extensions: self.manifest.no_compress_extensions.clone(),
+            uncompressed_libs: self.manifest.uncompressed_libs,
+            aapt: self.manifest.aapt,
+            extra_aapt_args: self.manifest.extra_aapt_args.clone(),
+            page_alignment: self.manifest.page_alignment,
+            alignment: ndk_build::apk::DEFAULT_ALIGNMENT,
             strip: self.manifest.strip,
+            strip_overrides,
+            objcopy_override: None,
+            signer: self.manifest.signer,
+            activity_type: self.manifest.activity_type,
             reverse_port_forward: self.manifest.reverse_port_forward.clone(),
-        };
-        let mut apk = config.create_apk()?;
+            port_forward: self.manifest.port_forward.clone(),
+            on_event: None,
+            dry_run: false,
+        })
+    }
 
-        for target in &self.build_targets {
-            let triple = target.rust_triple();
-            let build_dir = self.cmd.build_dir(Some(triple));
-            let artifact = self.cmd.artifact(artifact, Some(triple), CrateType::Cdylib);
-
-            let mut cargo = cargo_ndk(
-                &self.ndk,
-                *target,
-                self.min_sdk_version(),
-                self.cmd.target_dir(),
-            )?;
-            cargo.arg("build");
-            if self.cmd.target().is_none() {
-                cargo.arg("--target").arg(triple);
-            }
-            self.cmd.args().apply(&mut cargo);
+    /// Compiles the selected artifacts for `target`, returning the paths to search for the
+    /// libraries they link against.
+    fn build_target(&self, target: Target) -> Result<Vec<PathBuf>, Error> {
+        let triple = target.rust_triple();
+        let build_dir = self.cmd.build_dir(Some(triple));
 
-            if !cargo.status()?.success() {
-                return Err(NdkError::CmdFailed(cargo).into());
-            }
+        let mut cargo = self.cargo(target)?;
+        cargo.arg("build");
+        if self.cmd.target().is_none() {
+            cargo.arg("--target").arg(triple);
+        }
+        self.cmd.args().apply(&mut cargo);
 
-            let mut libs_search_paths =
-                get_libs_search_paths(self.cmd.target_dir(), triple, self.cmd.profile().as_ref())?;
-            libs_search_paths.push(build_dir.join("deps"));
+        if !cargo.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(cargo)).into());
+        }
 
-            let libs_search_paths = libs_search_paths
-                .iter()
-                .map(|path| path.as_path())
-                .collect::<Vec<_>>();
+        let mut libs_search_paths =
+            get_libs_search_paths(self.cmd.target_dir(), triple, self.cmd.profile().as_ref())?;
+        libs_search_paths.push(build_dir.join("deps"));
+        Ok(libs_search_paths)
+    }
 
-            apk.add_lib_recursively(&artifact, *target, libs_search_paths.as_slice())?;
+    /// Adds the libraries of `artifact` built for `target` to `apk`
+    fn add_libs(
+        &self,
+        apk: &mut UnalignedApk<'_>,
+        artifact: &Artifact,
+        target: Target,
+        libs_search_paths: &[PathBuf],
+    ) -> Result<(), NdkError> {
+        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+        let lib = self
+            .cmd
+            .artifact(artifact, Some(target.rust_triple()), CrateType::Cdylib);
+        let libs_search_paths = libs_search_paths
+            .iter()
+            .map(|path| path.as_path())
+            .collect::<Vec<_>>();
 
-            if let Some(runtime_libs) = &runtime_libs {
-                apk.add_runtime_libs(runtime_libs, *target, libs_search_paths.as_slice())?;
-            }
+        apk.add_lib_recursively(&lib, target, libs_search_paths.as_slice())?;
+
+        if let Some(runtime_libs) = &self.manifest.runtime_libs {
+            let runtime_libs = dunce::simplified(&crate_path.join(runtime_libs)).to_owned();
+            apk.add_runtime_libs(&runtime_libs, target, libs_search_paths.as_slice())?;
+        }
+        Ok(())
+    }
+
+    /// Adds the `.dex` from the manifest to `apk`, if any
+    fn add_dex(&self, apk: &mut UnalignedApk<'_>) -> Result<(), NdkError> {
+        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+        if let Some(dex) = &self.manifest.dex {
+            apk.add_dex(&crate_path.join(dex))?;
+        }
+        Ok(())
+    }
+
+    /// Builds a single APK holding the libraries of every build target
+    pub fn build(&self, artifact: &Artifact) -> Result<Apk, Error> {
+        let config = self.apk_config(artifact)?;
+        let mut apk = config.create_apk()?;
+        for &target in &self.build_targets {
+            let libs_search_paths = self.build_target(target)?;
+            self.add_libs(&mut apk, artifact, target, &libs_search_paths)?;
         }
+        self.add_dex(&mut apk)?;
+
+        let signing_key = self.signing_key()?;
+        let unsigned = apk.add_pending_libs_and_align()?;
+
+        println!(
+            "Signing `{}` with keystore `{}`",
+            config.apk().display(),
+            signing_key.path.display()
+        );
+        Ok(unsigned.sign(signing_key)?)
+    }
+
+    /// Builds one APK per build target, named `<apk_name>-<flavor>-<abi>.apk`, so that every
+    /// flavor×ABI combination is a separate artifact.
+    pub fn build_per_abi(&self, artifact: &Artifact) -> Result<Vec<Apk>, Error> {
+        let config = self.apk_config(artifact)?;
+        let libs_search_paths = self
+            .build_targets
+            .iter()
+            .map(|&target| self.build_target(target))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let signing_key = self.signing_key()?;
+        for &target in &self.build_targets {
+            println!(
+                "Signing `{}` with keystore `{}`",
+                config.for_abi(target).apk().display(),
+                signing_key.path.display()
+            );
+        }
+        let mut libs_search_paths = libs_search_paths.iter();
+        Ok(config.build_split_apks(
+            &self.build_targets,
+            |apk, target| {
+                self.add_libs(apk, artifact, target, libs_search_paths.next().unwrap())?;
+                self.add_dex(apk)
+            },
+            &signing_key,
+        )?)
+    }
+
+    /// Key to sign with: the keystore from the environment, the `signing` entry of the flavor
+    /// or profile, or the debug keystore for the `dev` profile.
+    fn signing_key(&self) -> Result<Key, Error> {
+        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+        let is_debug_profile = *self.cmd.profile() == Profile::Dev;
 
         let profile_name = match self.cmd.profile() {
             Profile::Dev => "dev",
@@ -267,10 +449,18 @@ impl<'a> ApkBuilder<'a> {
         let password_env = format!("{}_PASSWORD", keystore_env);
 
         let path = std::env::var_os(&keystore_env).map(PathBuf::from);
-        let password = std::env::var(&password_env).ok();
-
-        let signing_key = match (path, password) {
-            (Some(path), Some(password)) => Key { path, password },
+        // Read when signing and written to the signer's standard input, keeping the password off
+        // its command line
+        let password =
+            std::env::var_os(&password_env).map(|_| KeyPassword::Env(password_env.clone()));
+
+        Ok(match (path, password) {
+            (Some(path), Some(password)) => Key {
+                path,
+                password,
+                alias: None,
+                key_password: None,
+            },
             (Some(path), None) if is_debug_profile => {
                 eprintln!(
                     "{} not specified, falling back to default password",
@@ -278,7 +468,9 @@ impl<'a> ApkBuilder<'a> {
                 );
                 Key {
                     path,
-                    password: ndk_build::ndk::DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned(),
+                    password: ndk_build::ndk::DEFAULT_DEV_KEYSTORE_PASSWORD.into(),
+                    alias: None,
+                    key_password: None,
                 }
             }
             (Some(path), None) => {
@@ -286,10 +478,21 @@ impl<'a> ApkBuilder<'a> {
                 return Err(Error::MissingReleaseKey(profile_name.to_owned()));
             }
             (None, _) => {
-                if let Some(msk) = self.manifest.signing.get(profile_name) {
+                let signing_name = self
+                    .flavor()
+                    .and_then(|flavor| flavor.signing.as_deref())
+                    .unwrap_or(profile_name);
+                if let Some(msk) = self.manifest.signing.get(signing_name) {
+                    // Password files are relative to the manifest, like the keystore
+                    let password = |spec: &str| match KeyPassword::parse(spec) {
+                        KeyPassword::File(path) => KeyPassword::File(crate_path.join(path)),
+                        password => password,
+                    };
                     Key {
                         path: crate_path.join(&msk.path),
-                        password: msk.keystore_password.clone(),
+                        password: password(&msk.keystore_password),
+                        alias: msk.key_alias.clone(),
+                        key_password: msk.key_password.as_deref().map(password),
                     }
                 } else if is_debug_profile {
                     self.ndk.debug_key()?
@@ -297,21 +500,13 @@ impl<'a> ApkBuilder<'a> {
                     return Err(Error::MissingReleaseKey(profile_name.to_owned()));
                 }
             }
-        };
-
-        let unsigned = apk.add_pending_libs_and_align()?;
-
-        println!(
-            "Signing `{}` with keystore `{}`",
-            config.apk().display(),
-            signing_key.path.display()
-        );
-        Ok(unsigned.sign(signing_key)?)
+        })
     }
 
     pub fn run(&self, artifact: &Artifact, no_logcat: bool) -> Result<(), Error> {
         let apk = self.build(artifact)?;
         apk.reverse_port_forwarding(self.device_serial.as_deref())?;
+        apk.port_forwarding(self.device_serial.as_deref())?;
         apk.install(self.device_serial.as_deref())?;
         apk.start(self.device_serial.as_deref())?;
         let uid = apk.uidof(self.device_serial.as_deref())?;
@@ -330,14 +525,43 @@ impl<'a> ApkBuilder<'a> {
         Ok(())
     }
 
+    /// Uninstalls `artifact` from the device for the base configuration and every flavor,
+    /// skipping packages that are not installed.
+    pub fn uninstall(&self, artifact: &Artifact) -> Result<(), Error> {
+        let mut packages = vec![self.package_name(artifact, None)];
+        for flavor in self.manifest.flavors.values() {
+            let package = self.package_name(artifact, Some(flavor));
+            if !packages.contains(&package) {
+                packages.push(package);
+            }
+        }
+        let packages = packages.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let mut result = Ok(());
+        for UninstallResult { package, status } in self
+            .ndk
+            .uninstall_all(self.device_serial.as_deref(), &packages)?
+        {
+            match status {
+                Ok(UninstallStatus::Uninstalled) => println!("Uninstalled `{}`", package),
+                Ok(UninstallStatus::NotInstalled) => println!("`{}` is not installed", package),
+                Err(e) => {
+                    eprintln!("Failed to uninstall `{}`: {}", package, e);
+                    result = Err(e.into());
+                }
+            }
+        }
+        result
+    }
+
     pub fn gdb(&self, artifact: &Artifact) -> Result<(), Error> {
         let apk = self.build(artifact)?;
         apk.install(self.device_serial.as_deref())?;
 
-        let target_dir = self.build_dir.join(artifact.build_dir());
+        let target_dir = self.artifact_build_dir(artifact);
         self.ndk.ndk_gdb(
             target_dir,
-            "android.app.NativeActivity",
+            &self.manifest.android_manifest.application.activity.name,
             self.device_serial.as_deref(),
         )?;
         Ok(())
@@ -345,12 +569,7 @@ impl<'a> ApkBuilder<'a> {
 
     pub fn default(&self, cargo_cmd: &str, cargo_args: &[String]) -> Result<(), Error> {
         for target in &self.build_targets {
-            let mut cargo = cargo_ndk(
-                &self.ndk,
-                *target,
-                self.min_sdk_version(),
-                self.cmd.target_dir(),
-            )?;
+            let mut cargo = self.cargo(*target)?;
             cargo.arg(cargo_cmd);
             self.cmd.args().apply(&mut cargo);
 
@@ -364,7 +583,7 @@ impl<'a> ApkBuilder<'a> {
             }
 
             if !cargo.status()?.success() {
-                return Err(NdkError::CmdFailed(cargo).into());
+                return Err(NdkError::CmdFailed(Box::new(cargo)).into());
             }
         }
         Ok(())
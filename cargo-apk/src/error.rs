@@ -16,6 +16,10 @@ pub enum Error {
     Io(#[from] IoError),
     #[error("Configure a release keystore via `[package.metadata.android.signing.{0}]`")]
     MissingReleaseKey(String),
+    #[error("Flavor `{0}` is not defined in `[package.metadata.android.flavors]`")]
+    UnknownFlavor(String),
+    #[error("Flavor `{flavor}` refers to undefined signing config `[package.metadata.android.signing.{signing}]`")]
+    UnknownSigningConfig { flavor: String, signing: String },
     #[error("`workspace=false` is unsupported")]
     InheritedFalse,
     #[error("`workspace=true` requires a workspace")]
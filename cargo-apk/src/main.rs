@@ -27,6 +27,13 @@ struct Args {
     /// Use device with the given serial (see `adb devices`)
     #[clap(short, long)]
     device: Option<String>,
+    /// Build the given flavor from `[package.metadata.android.flavors]`. Can be passed multiple
+    /// times to `build` to produce an APK per flavor and ABI
+    #[clap(long)]
+    flavor: Vec<String>,
+    /// Run without accessing the network by passing `--offline` to every `cargo` invocation
+    #[clap(long)]
+    offline: bool,
 }
 
 #[derive(clap::Subcommand)]
@@ -65,6 +72,11 @@ enum ApkSubCmd {
         #[clap(short, long)]
         no_logcat: bool,
     },
+    /// Uninstall the apk of a binary or example of the local package, for every flavor
+    Uninstall {
+        #[clap(flatten)]
+        args: Args,
+    },
     /// Start a gdb session attached to an adb device with symbols loaded
     Gdb {
         #[clap(flatten)]
@@ -129,6 +141,14 @@ fn split_apk_and_cargo_args(input: Vec<String>) -> (Args, Vec<String>) {
     (args, split_args.cargo_args)
 }
 
+/// Selects the sole `--flavor` for subcommands that operate on a single APK
+fn single_flavor(flavors: Vec<String>) -> Result<Option<String>, Error> {
+    if flavors.len() > 1 {
+        return Err(Error::invalid_args());
+    }
+    Ok(flavors.into_iter().next())
+}
+
 fn iterator_single_item<T>(mut iter: impl Iterator<Item = T>) -> Option<T> {
     let first_item = iter.next()?;
     if iter.next().is_some() {
@@ -146,14 +166,33 @@ fn main() -> anyhow::Result<()> {
     match cmd {
         ApkSubCmd::Check { args } => {
             let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
+            let builder = ApkBuilder::from_subcommand(
+                &cmd,
+                args.device,
+                single_flavor(args.flavor)?,
+                args.offline,
+            )?;
             builder.check()?;
         }
         ApkSubCmd::Build { args } => {
             let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
-            for artifact in cmd.artifacts() {
-                builder.build(artifact)?;
+            let flavors = if args.flavor.is_empty() {
+                vec![None]
+            } else {
+                args.flavor.into_iter().map(Some).collect()
+            };
+            for flavor in flavors {
+                // Flavors produce an APK per ABI, the base configuration a single APK for all
+                let per_abi = flavor.is_some();
+                let builder =
+                    ApkBuilder::from_subcommand(&cmd, args.device.clone(), flavor, args.offline)?;
+                for artifact in cmd.artifacts() {
+                    if per_abi {
+                        builder.build_per_abi(artifact)?;
+                    } else {
+                        builder.build(artifact)?;
+                    }
+                }
             }
         }
         ApkSubCmd::Ndk {
@@ -163,18 +202,44 @@ fn main() -> anyhow::Result<()> {
             let (args, cargo_args) = split_apk_and_cargo_args(cargo_args);
 
             let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
+            let builder = ApkBuilder::from_subcommand(
+                &cmd,
+                args.device,
+                single_flavor(args.flavor)?,
+                args.offline,
+            )?;
             builder.default(&cargo_cmd, &cargo_args)?;
         }
         ApkSubCmd::Run { args, no_logcat } => {
             let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
+            let builder = ApkBuilder::from_subcommand(
+                &cmd,
+                args.device,
+                single_flavor(args.flavor)?,
+                args.offline,
+            )?;
             let artifact = iterator_single_item(cmd.artifacts()).ok_or(Error::invalid_args())?;
             builder.run(artifact, no_logcat)?;
         }
+        ApkSubCmd::Uninstall { args } => {
+            let cmd = Subcommand::new(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(
+                &cmd,
+                args.device,
+                single_flavor(args.flavor)?,
+                args.offline,
+            )?;
+            let artifact = iterator_single_item(cmd.artifacts()).ok_or(Error::invalid_args())?;
+            builder.uninstall(artifact)?;
+        }
         ApkSubCmd::Gdb { args } => {
             let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
+            let builder = ApkBuilder::from_subcommand(
+                &cmd,
+                args.device,
+                single_flavor(args.flavor)?,
+                args.offline,
+            )?;
             let artifact = iterator_single_item(cmd.artifacts()).ok_or(Error::invalid_args())?;
             builder.gdb(artifact)?;
         }
@@ -302,6 +367,8 @@ fn test_split_apk_and_cargo_args() {
                     ..args_default.subcommand_args
                 },
                 device: Some("adb:test".to_string()),
+                flavor: vec![],
+                offline: false,
             },
             vec!["--no-deps".to_string(), "--unrecognized".to_string()]
         )
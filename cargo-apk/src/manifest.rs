@@ -1,5 +1,5 @@
 use crate::error::Error;
-use ndk_build::apk::StripConfig;
+use ndk_build::apk::{Aapt, ActivityType, PageAlignment, Signer, StripConfig};
 use ndk_build::manifest::AndroidManifest;
 use ndk_build::target::Target;
 use serde::Deserialize;
@@ -15,18 +15,51 @@ pub enum Inheritable<T> {
     Inherited { workspace: bool },
 }
 
+/// A single assets folder, or a list of folders that are merged in order
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Assets {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl Assets {
+    pub(crate) fn paths(&self) -> &[PathBuf] {
+        match self {
+            Self::One(path) => std::slice::from_ref(path),
+            Self::Many(paths) => paths,
+        }
+    }
+}
+
 pub(crate) struct Manifest {
     pub(crate) version: Inheritable<String>,
     pub(crate) apk_name: Option<String>,
     pub(crate) android_manifest: AndroidManifest,
     pub(crate) build_targets: Vec<Target>,
-    pub(crate) assets: Option<PathBuf>,
+    pub(crate) assets: Option<Assets>,
     pub(crate) resources: Option<PathBuf>,
+    pub(crate) launcher_icon: Option<PathBuf>,
+    pub(crate) manifest_overlay: Option<PathBuf>,
     pub(crate) runtime_libs: Option<PathBuf>,
+    pub(crate) activity_type: ActivityType,
+    pub(crate) dex: Option<PathBuf>,
     /// Maps profiles to keystores
     pub(crate) signing: HashMap<String, Signing>,
+    /// Maps flavor names to their configuration
+    pub(crate) flavors: HashMap<String, Flavor>,
     pub(crate) reverse_port_forward: HashMap<String, String>,
+    pub(crate) port_forward: HashMap<String, String>,
     pub(crate) strip: StripConfig,
+    pub(crate) strip_overrides: HashMap<String, StripConfig>,
+    pub(crate) aapt: Aapt,
+    pub(crate) build_tools_version: Option<String>,
+    pub(crate) signer: Signer,
+    pub(crate) extra_aapt_args: Vec<String>,
+    pub(crate) no_compress_extensions: Vec<String>,
+    pub(crate) compression_level: Option<u32>,
+    pub(crate) page_alignment: PageAlignment,
+    pub(crate) uncompressed_libs: bool,
 }
 
 impl Manifest {
@@ -49,10 +82,25 @@ impl Manifest {
             build_targets: metadata.build_targets,
             assets: metadata.assets,
             resources: metadata.resources,
+            launcher_icon: metadata.launcher_icon,
+            manifest_overlay: metadata.manifest_overlay,
             runtime_libs: metadata.runtime_libs,
+            activity_type: metadata.activity_type,
+            dex: metadata.dex,
             signing: metadata.signing,
+            flavors: metadata.flavors,
             reverse_port_forward: metadata.reverse_port_forward,
+            port_forward: metadata.port_forward,
             strip: metadata.strip,
+            strip_overrides: metadata.strip_overrides,
+            aapt: metadata.aapt,
+            build_tools_version: metadata.build_tools_version,
+            signer: metadata.signer,
+            extra_aapt_args: metadata.extra_aapt_args,
+            no_compress_extensions: metadata.no_compress_extensions,
+            compression_level: metadata.compression_level,
+            page_alignment: metadata.page_alignment,
+            uncompressed_libs: metadata.uncompressed_libs,
         })
     }
 }
@@ -100,21 +148,81 @@ struct AndroidMetadata {
     android_manifest: AndroidManifest,
     #[serde(default)]
     build_targets: Vec<Target>,
-    assets: Option<PathBuf>,
+    assets: Option<Assets>,
     resources: Option<PathBuf>,
+    /// PNG added as the `@mipmap/ic_launcher` launcher icon
+    launcher_icon: Option<PathBuf>,
+    /// `AndroidManifest.xml` merged on top of the generated manifest
+    manifest_overlay: Option<PathBuf>,
     runtime_libs: Option<PathBuf>,
+    #[serde(default)]
+    activity_type: ActivityType,
+    /// Prebuilt `classes.dex` to include in the APK
+    dex: Option<PathBuf>,
     /// Maps profiles to keystores
     #[serde(default)]
     signing: HashMap<String, Signing>,
+    /// Maps flavor names to their configuration
+    #[serde(default)]
+    flavors: HashMap<String, Flavor>,
     /// Set up reverse port forwarding before launching the application
     #[serde(default)]
     reverse_port_forward: HashMap<String, String>,
+    /// Set up port forwarding from the host to the device before launching the application
+    #[serde(default)]
+    port_forward: HashMap<String, String>,
     #[serde(default)]
     strip: StripConfig,
+    /// Maps Rust target triples to the `strip` setting for their libraries
+    #[serde(default)]
+    strip_overrides: HashMap<String, StripConfig>,
+    /// Tool used to compile resources and package the manifest
+    #[serde(default)]
+    aapt: Aapt,
+    /// Build tools to use instead of the latest installed version
+    build_tools_version: Option<String>,
+    /// Tool the APK is signed with
+    #[serde(default)]
+    signer: Signer,
+    /// Raw arguments appended to the `aapt package` or `aapt2 link` invocation
+    #[serde(default)]
+    extra_aapt_args: Vec<String>,
+    /// Extensions of files that are stored uncompressed
+    #[serde(default)]
+    no_compress_extensions: Vec<String>,
+    /// DEFLATE level of compressed entries in release builds
+    compression_level: Option<u32>,
+    #[serde(default)]
+    page_alignment: PageAlignment,
+    /// Store native libraries uncompressed, with `android:extractNativeLibs="false"`
+    #[serde(default)]
+    uncompressed_libs: bool,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
 pub(crate) struct Signing {
     pub(crate) path: PathBuf,
     pub(crate) keystore_password: String,
+    pub(crate) key_alias: Option<String>,
+    pub(crate) key_password: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct Flavor {
+    /// Name of the `[package.metadata.android.signing.<name>]` entry used to sign this flavor,
+    /// instead of the entry for the current profile
+    pub(crate) signing: Option<String>,
+    /// Replaces the manifest `package`
+    pub(crate) package: Option<String>,
+    /// Appended to the (possibly replaced) manifest `package`
+    pub(crate) package_suffix: Option<String>,
+    /// Appended to the manifest `versionName`
+    pub(crate) version_name_suffix: Option<String>,
+    /// Values substituted for `${key}` in the generated manifest
+    #[serde(default)]
+    pub(crate) manifest_placeholders: HashMap<String, String>,
+    /// Replaces the top-level `assets` folders
+    pub(crate) assets: Option<Assets>,
+    /// Replaces the top-level `resources` folder
+    pub(crate) resources: Option<PathBuf>,
 }
@@ -0,0 +1,274 @@
+use crate::apk::{
+    aapt2_compile, dex_entry_name, jarsigner_sign_args, stage_lib, strip_for_target, DryRun,
+    StripConfig,
+};
+use crate::error::NdkError;
+use crate::manifest::AndroidManifest;
+use crate::ndk::{status_with_stdin, Key, Ndk};
+use crate::target::Target;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Version of `bundletool` that the `BundleConfig.pb` of generated bundles claims compatibility
+/// with.
+const BUNDLETOOL_VERSION: &str = "1.15.6";
+
+/// Configuration of an [Android App Bundle](https://developer.android.com/guide/app-bundle),
+/// the publishing format required by Google Play. Bundles consist of a single `base` module.
+pub struct AabConfig {
+    pub ndk: Ndk,
+    /// Output directory of the final bundle
+    pub build_dir: PathBuf,
+    pub aab_name: String,
+    pub assets: Option<PathBuf>,
+    pub resources: Option<PathBuf>,
+    pub manifest: AndroidManifest,
+    pub strip: StripConfig,
+    /// See [`ApkConfig::strip_overrides`](crate::apk::ApkConfig::strip_overrides)
+    pub strip_overrides: HashMap<Target, StripConfig>,
+    /// See [`ApkConfig::objcopy_override`](crate::apk::ApkConfig::objcopy_override)
+    pub objcopy_override: Option<PathBuf>,
+}
+
+impl AabConfig {
+    fn build_tool(&self, tool: &'static str) -> Result<Command, NdkError> {
+        let mut cmd = self.ndk.build_tool(tool)?;
+        cmd.current_dir(self.staging_dir());
+        Ok(cmd)
+    }
+
+    /// Retrieves the directory that the module layout of the bundle is assembled in
+    pub fn staging_dir(&self) -> PathBuf {
+        self.build_dir.join("aab-staging")
+    }
+
+    fn base_dir(&self) -> PathBuf {
+        self.staging_dir().join("base")
+    }
+
+    fn unsigned_aab(&self) -> PathBuf {
+        self.staging_dir()
+            .join(format!("{}-unsigned.aab", self.aab_name))
+    }
+
+    /// Retrieves the path of the bundle that will be written when [`UnsignedAab::sign`] is
+    /// invoked
+    #[inline]
+    pub fn aab(&self) -> PathBuf {
+        self.build_dir.join(format!("{}.aab", self.aab_name))
+    }
+
+    /// Compiles the manifest and resources to the protobuf format with `aapt2`, and lays them
+    /// out as the `base` module.
+    pub fn create_aab(&self) -> Result<UnzippedAab<'_>, NdkError> {
+        let staging_dir = self.staging_dir();
+        let base_dir = self.base_dir();
+        // Don't carry over libraries or resources of a previous build
+        if base_dir.exists() {
+            fs::remove_dir_all(&base_dir)
+                .map_err(|e| NdkError::IoPathError(base_dir.clone(), e))?;
+        }
+        fs::create_dir_all(&base_dir)?;
+        self.manifest.write_to(&staging_dir)?;
+
+        let target_sdk_version = self
+            .manifest
+            .sdk
+            .target_sdk_version
+            .unwrap_or_else(|| self.ndk.default_target_platform());
+
+        let compiled_resources = if let Some(res) = &self.resources {
+            let compiled = staging_dir.join("resources.zip");
+            aapt2_compile(
+                self.build_tool(bin!("aapt2"))?,
+                res,
+                &compiled,
+                &DryRun::default(),
+            )?;
+            Some(compiled)
+        } else {
+            None
+        };
+
+        let mut aapt2 = self.build_tool(bin!("aapt2"))?;
+        aapt2
+            .arg("link")
+            .arg("--proto-format")
+            .arg("--output-to-dir")
+            .arg("-o")
+            .arg(&base_dir)
+            .arg("--manifest")
+            .arg("AndroidManifest.xml")
+            .arg("-I")
+            .arg(self.ndk.android_jar(target_sdk_version)?);
+        if let Some(assets) = &self.assets {
+            aapt2.arg("-A").arg(assets);
+        }
+        if let Some(compiled) = &compiled_resources {
+            aapt2.arg(compiled);
+        }
+        if !aapt2.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(aapt2)));
+        }
+
+        // Modules keep their manifest in a `manifest` subdirectory
+        let manifest_dir = base_dir.join("manifest");
+        fs::create_dir_all(&manifest_dir)?;
+        fs::rename(
+            base_dir.join("AndroidManifest.xml"),
+            manifest_dir.join("AndroidManifest.xml"),
+        )?;
+
+        Ok(UnzippedAab {
+            config: self,
+            dex_count: 0,
+        })
+    }
+}
+
+/// A bundle whose `base` module is being assembled in [`AabConfig::staging_dir`]
+pub struct UnzippedAab<'a> {
+    config: &'a AabConfig,
+    dex_count: usize,
+}
+
+impl<'a> UnzippedAab<'a> {
+    pub fn config(&self) -> &AabConfig {
+        self.config
+    }
+
+    /// Adds a native library to `base/lib/<abi>`, treating its debug symbols according to
+    /// [`AabConfig::strip`].
+    pub fn add_lib(&mut self, path: &Path, target: Target) -> Result<(), NdkError> {
+        if !path.exists() {
+            return Err(NdkError::PathNotFound(path.into()));
+        }
+        let out = self
+            .config
+            .base_dir()
+            .join("lib")
+            .join(target.android_abi())
+            .join(path.file_name().unwrap());
+        fs::create_dir_all(out.parent().unwrap())?;
+        stage_lib(
+            &self.config.ndk,
+            strip_for_target(self.config.strip, &self.config.strip_overrides, target),
+            self.config.objcopy_override.as_deref(),
+            path,
+            target,
+            &out,
+            &DryRun::default(),
+        )
+    }
+
+    /// Adds a `.dex` to `base/dex`, as `classes.dex` followed by `classes2.dex`, `classes3.dex`,
+    /// ... on subsequent calls.
+    pub fn add_dex(&mut self, path: &Path) -> Result<(), NdkError> {
+        if !path.exists() {
+            return Err(NdkError::PathNotFound(path.into()));
+        }
+        let dex_dir = self.config.base_dir().join("dex");
+        fs::create_dir_all(&dex_dir)?;
+        fs::copy(path, dex_dir.join(dex_entry_name(self.dex_count)))?;
+        self.dex_count += 1;
+        Ok(())
+    }
+
+    /// Zips the `base` module together with a `BundleConfig.pb` into an unsigned bundle.
+    pub fn zip(self) -> Result<UnsignedAab<'a>, NdkError> {
+        let staging_dir = self.config.staging_dir();
+        fs::write(staging_dir.join("BundleConfig.pb"), bundle_config())?;
+
+        let unsigned = self.config.unsigned_aab();
+        if unsigned.exists() {
+            fs::remove_file(&unsigned)?;
+        }
+
+        let mut entries = BTreeSet::new();
+        entries.insert("BundleConfig.pb".to_string());
+        collect_entries(&staging_dir, &self.config.base_dir(), &mut entries)?;
+
+        // A bundle is a regular zip; `aapt add` stores every entry at its path relative to the
+        // working directory
+        let mut aapt = self.config.build_tool(bin!("aapt"))?;
+        aapt.arg("add").arg(&unsigned).args(&entries);
+        if !aapt.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(aapt)));
+        }
+
+        Ok(UnsignedAab(self.config))
+    }
+}
+
+/// Collects the paths of all files in `dir`, relative to `root` and with UNIX separators.
+fn collect_entries(
+    root: &Path,
+    dir: &Path,
+    entries: &mut BTreeSet<String>,
+) -> Result<(), NdkError> {
+    for entry in fs::read_dir(dir).map_err(|e| NdkError::IoPathError(dir.to_owned(), e))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_entries(root, &path, entries)?;
+        } else {
+            let relative = path.strip_prefix(root).unwrap();
+            entries.insert(relative.to_str().unwrap().replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+/// Encodes a `BundleConfig` protobuf message that only sets `bundletool.version`.
+fn bundle_config() -> Vec<u8> {
+    // message Bundletool { string version = 2; }
+    let mut bundletool = vec![0x12, BUNDLETOOL_VERSION.len() as u8];
+    bundletool.extend_from_slice(BUNDLETOOL_VERSION.as_bytes());
+    // message BundleConfig { Bundletool bundletool = 1; }
+    let mut bundle_config = vec![0x0a, bundletool.len() as u8];
+    bundle_config.extend(bundletool);
+    bundle_config
+}
+
+pub struct UnsignedAab<'a>(&'a AabConfig);
+
+impl<'a> UnsignedAab<'a> {
+    /// Signs the bundle with `jarsigner`, as `apksigner` does not support bundles.
+    pub fn sign(self, key: Key) -> Result<Aab, NdkError> {
+        let alias = key.resolve_alias(&self.0.ndk)?;
+        let aab = self.0.aab();
+
+        let mut jarsigner = self.0.ndk.jarsigner()?;
+        let stdin = jarsigner_sign_args(&mut jarsigner, &key, &alias, &self.0.unsigned_aab(), &aab);
+
+        if !status_with_stdin(&mut jarsigner, &stdin)? {
+            return Err(NdkError::CmdFailed(Box::new(jarsigner)));
+        }
+
+        Ok(Aab { path: aab })
+    }
+}
+
+pub struct Aab {
+    path: PathBuf,
+}
+
+impl Aab {
+    /// Retrieves the path of the signed bundle
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_bundle_config() {
+        let mut expected = vec![0x0a, 0x08, 0x12, 0x06];
+        expected.extend_from_slice(b"1.15.6");
+        assert_eq!(bundle_config(), expected);
+    }
+}
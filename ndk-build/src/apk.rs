@@ -1,13 +1,23 @@
 use crate::error::NdkError;
-use crate::manifest::AndroidManifest;
-use crate::ndk::{Key, Ndk};
+use crate::manifest::{merge_manifest, AndroidManifest, KNOWN_GL_TEXTURE_FORMATS};
+use crate::ndk::{
+    run_with_timeout, status_with_stdin, uninstall_args, BuildToolsCapability, Key, KeyPassword,
+    Ndk, UninstallStatus,
+};
+use crate::png;
 use crate::target::Target;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsStr;
 use std::fs;
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::pin::Pin;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
 /// The options for how to treat debug symbols that are present in any `.so`
 /// files that are added to the APK.
@@ -24,7 +34,8 @@ pub enum StripConfig {
     /// Removes debug symbols from the library before copying it into the APK
     Strip,
     /// Splits the library into into an ELF (`.so`) and DWARF (`.dwarf`). Only the
-    /// `.so` is copied into the APK
+    /// `.so` is copied into the APK. Libraries without debug sections are copied as-is, with a
+    /// warning.
     Split,
 }
 
@@ -34,27 +45,329 @@ impl Default for StripConfig {
     }
 }
 
+/// The Java activity class that hosts the native code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityType {
+    /// The platform's [`android.app.NativeActivity`](https://developer.android.com/reference/android/app/NativeActivity),
+    /// which requires no Java code in the APK
+    Native,
+    /// [`GameActivity`](https://developer.android.com/games/agdk/game-activity) from the Android
+    /// Game Development Kit. It is not part of the platform, so a `.dex` containing its classes
+    /// must be added with [`UnalignedApk::add_dex`]
+    GameActivity,
+}
+
+impl Default for ActivityType {
+    fn default() -> Self {
+        Self::Native
+    }
+}
+
+impl ActivityType {
+    /// Fully qualified name of the activity class
+    pub fn activity_name(self) -> &'static str {
+        match self {
+            Self::Native => "android.app.NativeActivity",
+            Self::GameActivity => "com.google.androidgamesdk.GameActivity",
+        }
+    }
+}
+
+/// Progress of an APK build, reported to [`ApkConfig::on_event`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildEvent {
+    /// The generated `AndroidManifest.xml` was written to the staging directory
+    ManifestWritten(PathBuf),
+    /// The manifest, resources and assets were packaged into the unaligned APK
+    ResourcesPackaged(PathBuf),
+    /// A native library was staged for `target`
+    LibAdded { path: PathBuf, target: Target },
+    /// A `.dex` was staged under the given entry name
+    DexAdded(String),
+    /// A file was staged with [`UnalignedApk::add_file`] under the given entry name
+    FileAdded(String),
+    /// The APK was aligned with `zipalign`
+    Aligned(PathBuf),
+    /// The APK was signed
+    Signed(PathBuf),
+    /// A command was not run because of [`ApkConfig::dry_run`]
+    DryRun(String),
+}
+
+/// Reports commands instead of running them, see [`ApkConfig::dry_run`]
+#[derive(Clone, Default)]
+pub(crate) struct DryRun {
+    enabled: bool,
+    on_event: Option<Arc<dyn Fn(BuildEvent) + Send + Sync>>,
+}
+
+impl DryRun {
+    /// Reports `cmd` as [`BuildEvent::DryRun`], or on stdout without an event handler, when dry
+    /// runs are enabled. Returns whether `cmd` must not be run.
+    pub(crate) fn skip(&self, cmd: &Command) -> bool {
+        if self.enabled {
+            let cmd = format!("{:?}", cmd);
+            match &self.on_event {
+                Some(on_event) => on_event(BuildEvent::DryRun(cmd)),
+                None => println!("{}", cmd),
+            }
+        }
+        self.enabled
+    }
+}
+
+/// Page size that uncompressed native libraries are aligned to in the APK, so that they can be
+/// memory-mapped directly from it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+pub enum PageAlignment {
+    /// 4 KiB pages (`zipalign -p`)
+    #[serde(rename = "4k")]
+    Align4K,
+    /// [16 KiB pages](https://developer.android.com/guide/practices/page-sizes), supported by
+    /// Android 15 and up and required by Google Play (`zipalign -P 16`). Native libraries are
+    /// stored uncompressed for the alignment to take effect, see
+    /// [`ApkConfig::uncompressed_libs`]. Requires build-tools 35.0.0 or newer.
+    #[serde(rename = "16k")]
+    Align16K,
+}
+
+impl Default for PageAlignment {
+    fn default() -> Self {
+        Self::Align4K
+    }
+}
+
+/// Launcher icon size in pixels of every density, see
+/// <https://developer.android.com/training/multiscreen/screendensities#TaskProvideAltBmp>
+const LAUNCHER_ICON_SIZES: [(&str, u32); 5] = [
+    ("mdpi", 48),
+    ("hdpi", 72),
+    ("xhdpi", 96),
+    ("xxhdpi", 144),
+    ("xxxhdpi", 192),
+];
+
+/// The byte alignment of uncompressed APK entries that `zipalign` is commonly invoked with.
+pub const DEFAULT_ALIGNMENT: u16 = 4;
+
+/// Highest [`ApkConfig::compression_level`].
+pub const MAX_COMPRESSION_LEVEL: u32 = crate::deflate::MAX_LEVEL;
+
+/// Appends the `zipalign` arguments for aligning `input` to `output`, after validating that
+/// `alignment` is a power of two.
+fn zipalign_args(
+    cmd: &mut Command,
+    alignment: u16,
+    page_alignment: PageAlignment,
+    input: &Path,
+    output: &Path,
+) -> Result<(), NdkError> {
+    if !alignment.is_power_of_two() {
+        return Err(NdkError::InvalidAlignment(alignment));
+    }
+    match page_alignment {
+        PageAlignment::Align4K => cmd.arg("-p"),
+        PageAlignment::Align16K => cmd.arg("-P").arg("16"),
+    };
+    cmd.arg("-f")
+        .arg("-v")
+        .arg(alignment.to_string())
+        .arg(input)
+        .arg(output);
+    Ok(())
+}
+
+/// The Android Asset Packaging Tool used to compile resources and package the manifest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aapt {
+    /// The legacy `aapt package`
+    Aapt1,
+    /// `aapt2 compile` followed by `aapt2 link`, which supports newer resource features
+    Aapt2,
+}
+
+impl Default for Aapt {
+    fn default() -> Self {
+        Self::Aapt1
+    }
+}
+
+/// Compiles the resource directory `res` into a zip of `.flat` files at `out` with
+/// `aapt2 compile`.
+pub(crate) fn aapt2_compile(
+    mut aapt2: Command,
+    res: &Path,
+    out: &Path,
+    dry_run: &DryRun,
+) -> Result<(), NdkError> {
+    aapt2
+        .arg("compile")
+        .arg("--dir")
+        .arg(res)
+        .arg("-o")
+        .arg(out);
+    if !dry_run.skip(&aapt2) && !aapt2.status()?.success() {
+        return Err(NdkError::CmdFailed(Box::new(aapt2)));
+    }
+    Ok(())
+}
+
+/// Environment variable that, when set, overrides [`AndroidManifest::version_code`] in
+/// [`ApkConfig::create_apk`]. Must be a positive integer.
+pub const VERSION_CODE_ENV: &str = "VERSION_CODE";
+/// Environment variable that, when set, overrides [`AndroidManifest::version_name`] in
+/// [`ApkConfig::create_apk`].
+pub const VERSION_NAME_ENV: &str = "VERSION_NAME";
+
+#[derive(Clone)]
 pub struct ApkConfig {
     pub ndk: Ndk,
+    /// Output directory of the final APK
     pub build_dir: PathBuf,
+    /// Directory for intermediate files such as the generated manifest, staged libraries and the
+    /// unaligned APK. Defaults to a `staging` subdirectory of [`ApkConfig::build_dir`]
+    pub staging_dir: Option<PathBuf>,
+    /// Remove the output of previous builds from the staging directory before building, see
+    /// [`ApkConfig::clean`]
+    pub clean: bool,
     pub apk_name: String,
-    pub assets: Option<PathBuf>,
+    /// Folders merged into the `assets/` of the APK. Files of later folders replace those with
+    /// the same path in earlier ones.
+    pub assets: Vec<PathBuf>,
     pub resources: Option<PathBuf>,
+    /// PNG used as the launcher icon, added as `@mipmap/ic_launcher` and referenced from
+    /// `android:icon` unless [`ApkConfig::resources`] already contain an `ic_launcher` mipmap.
+    /// It is scaled to the launcher icon size of every density from `mipmap-mdpi` (48px) to
+    /// `mipmap-xxxhdpi` (192px).
+    pub icon: Option<PathBuf>,
     pub manifest: AndroidManifest,
+    /// `AndroidManifest.xml` merged on top of the one generated from [`ApkConfig::manifest`],
+    /// see [`merge_manifest`]
+    pub manifest_overlay: Option<PathBuf>,
+    /// Values substituted for `${key}` placeholders in the generated manifest
+    pub manifest_placeholders: HashMap<String, String>,
+    /// Package name the APK is installed under, replacing [`AndroidManifest::package`] at
+    /// packaging time (like Gradle's `applicationId`). The manifest package is retained as the
+    /// namespace that relative class names are resolved against.
+    pub application_id: Option<String>,
     pub disable_aapt_compression: bool,
+    /// DEFLATE level (`0..=9`) of compressed entries. `0` stores all entries like
+    /// [`ApkConfig::disable_aapt_compression`], and `1..=9` recompress the entries that `aapt`
+    /// compressed at that level before the APK is aligned, trading build time for size at the
+    /// higher levels. Stored entries such as native libraries and `resources.arsc` are left
+    /// untouched and aligned as usual. `None` keeps the compression of `aapt`.
+    pub compression_level: Option<u32>,
+    /// Extensions of files that are stored uncompressed (`-0 <ext>`), for example already
+    /// compressed `png` or `ogg` assets, like Gradle's `aaptOptions.noCompress`
+    pub no_compress_extensions: Vec<String>,
+    /// Stores native libraries uncompressed, independent of
+    /// [`ApkConfig::disable_aapt_compression`], and sets `android:extractNativeLibs="false"` so
+    /// that they are loaded directly from the APK instead of being extracted on installation
+    pub uncompressed_libs: bool,
+    pub aapt: Aapt,
+    /// Additional arguments appended to the `aapt package` (or `aapt2 link`) invocation, for
+    /// options that aren't modeled by [`ApkConfig`]. Not passed to `aapt add`.
+    pub extra_aapt_args: Vec<String>,
+    pub page_alignment: PageAlignment,
+    /// Byte alignment of uncompressed entries, usually [`DEFAULT_ALIGNMENT`]. Must be a power of
+    /// two.
+    pub alignment: u16,
+    /// How debug symbols of libraries are treated, unless overridden for the target in
+    /// [`ApkConfig::strip_overrides`]
     pub strip: StripConfig,
+    /// Per-target replacements of [`ApkConfig::strip`], e.g. to keep the debug symbols for the
+    /// ABI that is being debugged only
+    pub strip_overrides: HashMap<Target, StripConfig>,
+    /// `objcopy` used verbatim to strip and split libraries according to [`ApkConfig::strip`],
+    /// instead of the one in the NDK toolchain
+    pub objcopy_override: Option<PathBuf>,
+    /// Tool that the APK is signed with by [`UnsignedApk::sign_with`]
+    pub signer: Signer,
+    pub activity_type: ActivityType,
     pub reverse_port_forward: HashMap<String, String>,
+    /// Host to device forwards set up by [`Apk::port_forwarding`], as `adb forward` specs
+    pub port_forward: HashMap<String, String>,
+    /// Invoked as the build progresses, e.g. to report progress without parsing the output of
+    /// the build tools
+    pub on_event: Option<Arc<dyn Fn(BuildEvent) + Send + Sync>>,
+    /// Report the `aapt`, `strip`, `zipalign` and signer commands as [`BuildEvent::DryRun`] (or
+    /// on stdout without [`ApkConfig::on_event`]) instead of running them, while still staging
+    /// files and advancing through the build. [`Apk`]s created from this config inherit the
+    /// setting, see [`Apk::set_dry_run`].
+    pub dry_run: bool,
 }
 
 impl ApkConfig {
     fn build_tool(&self, tool: &'static str) -> Result<Command, NdkError> {
         let mut cmd = self.ndk.build_tool(tool)?;
-        cmd.current_dir(&self.build_dir);
+        cmd.current_dir(self.staging_dir());
         Ok(cmd)
     }
 
+    /// The [`StripConfig`] of `target`, see [`ApkConfig::strip_overrides`]
+    pub fn strip_for(&self, target: Target) -> StripConfig {
+        strip_for_target(self.strip, &self.strip_overrides, target)
+    }
+
+    fn emit(&self, event: BuildEvent) {
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+
+    fn dry_runner(&self) -> DryRun {
+        DryRun {
+            enabled: self.dry_run,
+            on_event: self.on_event.clone(),
+        }
+    }
+
+    /// Whether native libraries are stored uncompressed, as requested by
+    /// [`ApkConfig::uncompressed_libs`] or required by [`PageAlignment::Align16K`]
+    fn uncompressed_libs(&self) -> bool {
+        self.uncompressed_libs || self.page_alignment == PageAlignment::Align16K
+    }
+
+    /// Retrieves the directory that intermediate files are staged in
+    pub fn staging_dir(&self) -> PathBuf {
+        self.staging_dir
+            .clone()
+            .unwrap_or_else(|| self.build_dir.join("staging"))
+    }
+
+    /// Removes what previous builds wrote to [`ApkConfig::staging_dir`], so that libraries and
+    /// `.dex` files that are no longer added don't end up in the next APK. Only the
+    /// intermediates produced by this crate are removed; other files in the staging directory
+    /// and the [`ApkConfig::assets`] and [`ApkConfig::resources`] directories are kept, even
+    /// when located inside of it.
+    pub fn clean(&self) -> Result<(), NdkError> {
+        let keep = self
+            .assets
+            .iter()
+            .chain(&self.resources)
+            .chain(&self.icon)
+            .chain(&self.manifest_overlay)
+            .map(PathBuf::as_path)
+            .collect::<Vec<_>>();
+        remove_staging_outputs(&self.staging_dir(), &self.apk_name, &keep)
+    }
+
+    /// Whether all entries are stored without compression
+    fn store_uncompressed(&self) -> bool {
+        self.disable_aapt_compression || self.compression_level == Some(0)
+    }
+
+    /// Retrieves the package name the APK is installed under, see [`ApkConfig::application_id`]
+    pub fn application_id(&self) -> &str {
+        self.application_id
+            .as_deref()
+            .unwrap_or(&self.manifest.package)
+    }
+
     fn unaligned_apk(&self) -> PathBuf {
-        self.build_dir
+        self.staging_dir()
             .join(format!("{}-unaligned.apk", self.apk_name))
     }
 
@@ -65,15 +378,98 @@ impl ApkConfig {
         self.build_dir.join(format!("{}.apk", self.apk_name))
     }
 
-    pub fn create_apk(&self) -> Result<UnalignedApk, NdkError> {
-        std::fs::create_dir_all(&self.build_dir)?;
-        self.manifest.write_to(&self.build_dir)?;
+    /// Configuration of the APK that only holds the libraries of `target`, named
+    /// `<apk_name>-<abi>` and staged in its own subdirectory of [`ApkConfig::staging_dir`].
+    pub fn for_abi(&self, target: Target) -> Self {
+        let mut config = self.clone();
+        config.staging_dir = Some(self.staging_dir().join(target.android_abi()));
+        config.apk_name = split_apk_name(&self.apk_name, target);
+        config
+    }
 
-        let target_sdk_version = self
-            .manifest
-            .sdk
-            .target_sdk_version
-            .unwrap_or_else(|| self.ndk.default_target_platform());
+    /// Builds and signs one APK per target with [`ApkConfig::for_abi`], instead of a single APK
+    /// holding the libraries of all targets. `add_libs` adds the libraries of each target, and
+    /// is invoked before the APK is aligned.
+    pub fn build_split_apks(
+        &self,
+        targets: &[Target],
+        mut add_libs: impl FnMut(&mut UnalignedApk<'_>, Target) -> Result<(), NdkError>,
+        key: &Key,
+    ) -> Result<Vec<Apk>, NdkError> {
+        targets
+            .iter()
+            .map(|&target| {
+                let config = self.for_abi(target);
+                let mut apk = config.create_apk()?;
+                add_libs(&mut apk, target)?;
+                apk.add_pending_libs_and_align()?.sign(key.clone())
+            })
+            .collect()
+    }
+
+    /// Ensures a resource reference such as `@drawable/banner` resolves to a file in
+    /// [`ApkConfig::resources`]. Framework references (`@android:...`) are not checked.
+    fn check_resource_exists(&self, reference: &str) -> Result<(), NdkError> {
+        let (kind, name) = match reference.strip_prefix('@').and_then(|r| r.split_once('/')) {
+            Some((kind, _)) if kind.starts_with("android:") => return Ok(()),
+            Some(parts) => parts,
+            None => return Err(NdkError::ResourceNotFound(reference.to_owned())),
+        };
+
+        let found = self
+            .resources
+            .as_ref()
+            .map_or(false, |res| resource_exists(res, kind, name));
+
+        if found {
+            Ok(())
+        } else {
+            Err(NdkError::ResourceNotFound(reference.to_owned()))
+        }
+    }
+
+    /// Stages a copy of [`ApkConfig::resources`] with the [`ApkConfig::icon`] added, returning
+    /// the resource directory to package and whether `@mipmap/ic_launcher` is available.
+    fn prepare_resources(&self) -> Result<(Option<PathBuf>, bool), NdkError> {
+        let has_launcher_icon = self
+            .resources
+            .as_ref()
+            .map_or(false, |res| resource_exists(res, "mipmap", "ic_launcher"));
+        let icon = match &self.icon {
+            Some(icon) if !has_launcher_icon => icon,
+            _ => return Ok((self.resources.clone(), has_launcher_icon)),
+        };
+        let staged = self.staging_dir().join("res");
+        if staged.exists() {
+            fs::remove_dir_all(&staged).map_err(|e| NdkError::IoPathError(staged.clone(), e))?;
+        }
+        if let Some(res) = &self.resources {
+            copy_dir(res, &staged)?;
+        }
+        write_launcher_icons(icon, &staged)?;
+        Ok((Some(staged), true))
+    }
+
+    /// Returns the single folder of [`ApkConfig::assets`] to pass to `aapt`, which only accepts
+    /// one. Multiple folders are merged into the staging directory first.
+    fn prepare_assets(&self) -> Result<Option<PathBuf>, NdkError> {
+        match self.assets.as_slice() {
+            [] => Ok(None),
+            [assets] => Ok(Some(assets.clone())),
+            all => {
+                let staged = self.staging_dir().join("assets");
+                merge_dirs(all, &staged)?;
+                Ok(Some(staged))
+            }
+        }
+    }
+
+    fn package_aapt1(
+        &self,
+        android_jar: &Path,
+        resources: Option<&Path>,
+        target_sdk_version: u32,
+    ) -> Result<(), NdkError> {
         let mut aapt = self.build_tool(bin!("aapt"))?;
         aapt.arg("package")
             .arg("-f")
@@ -82,34 +478,499 @@ impl ApkConfig {
             .arg("-M")
             .arg("AndroidManifest.xml")
             .arg("-I")
-            .arg(self.ndk.android_jar(target_sdk_version)?);
+            .arg(android_jar);
 
-        if self.disable_aapt_compression {
+        if let Some(application_id) = &self.application_id {
+            aapt.arg("--rename-manifest-package").arg(application_id);
+        }
+
+        if self.store_uncompressed() {
             aapt.arg("-0").arg("");
+        } else if target_sdk_version >= 30 {
+            // Installation of APKs targeting API level 30 fails when `resources.arsc` is
+            // compressed; `zipalign` then aligns it like any other uncompressed entry. `aapt2`
+            // never compresses it.
+            aapt.arg("-0").arg("resources.arsc");
         }
+        no_compress_args(&mut aapt, &self.no_compress_extensions);
 
-        if let Some(res) = &self.resources {
+        if let Some(res) = resources {
             aapt.arg("-S").arg(res);
         }
 
-        if let Some(assets) = &self.assets {
+        if let Some(assets) = self.prepare_assets()? {
             aapt.arg("-A").arg(assets);
         }
 
-        if !aapt.status()?.success() {
-            return Err(NdkError::CmdFailed(aapt));
+        aapt.args(&self.extra_aapt_args);
+
+        if !self.dry_runner().skip(&aapt) && !aapt.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(aapt)));
+        }
+
+        Ok(())
+    }
+
+    fn package_aapt2(&self, android_jar: &Path, resources: Option<&Path>) -> Result<(), NdkError> {
+        let compiled_resources = if let Some(res) = resources {
+            let compiled = self.staging_dir().join("resources.zip");
+            aapt2_compile(
+                self.build_tool(bin!("aapt2"))?,
+                res,
+                &compiled,
+                &self.dry_runner(),
+            )?;
+            Some(compiled)
+        } else {
+            None
+        };
+
+        let mut aapt2 = self.build_tool(bin!("aapt2"))?;
+        aapt2
+            .arg("link")
+            .arg("-o")
+            .arg(self.unaligned_apk())
+            .arg("--manifest")
+            .arg("AndroidManifest.xml")
+            .arg("-I")
+            .arg(android_jar);
+
+        if let Some(application_id) = &self.application_id {
+            aapt2.arg("--rename-manifest-package").arg(application_id);
+        }
+
+        if self.store_uncompressed() {
+            aapt2.arg("--no-compress");
+        }
+        no_compress_args(&mut aapt2, &self.no_compress_extensions);
+
+        if let Some(assets) = self.prepare_assets()? {
+            aapt2.arg("-A").arg(assets);
+        }
+
+        aapt2.args(&self.extra_aapt_args);
+
+        if let Some(compiled) = &compiled_resources {
+            aapt2.arg(compiled);
+        }
+
+        if !self.dry_runner().skip(&aapt2) && !aapt2.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(aapt2)));
+        }
+
+        Ok(())
+    }
+
+    pub fn create_apk(&self) -> Result<UnalignedApk<'_>, NdkError> {
+        if let Some(level) = self
+            .compression_level
+            .filter(|&level| level > MAX_COMPRESSION_LEVEL)
+        {
+            return Err(NdkError::InvalidCompressionLevel(level));
+        }
+
+        let application = &self.manifest.application;
+        for banner in application
+            .banner
+            .iter()
+            .chain(&application.activity.banner)
+        {
+            self.check_resource_exists(banner)?;
+        }
+
+        for format in &self.manifest.supports_gl_texture {
+            if !KNOWN_GL_TEXTURE_FORMATS.contains(&format.as_str()) {
+                eprintln!(
+                    "Warning: `{}` is not a known GL texture compression format for `supports-gl-texture`",
+                    format
+                );
+            }
+        }
+
+        if let Some(smallest_width) = self
+            .manifest
+            .supports_screens
+            .as_ref()
+            .and_then(|screens| screens.requires_smallest_width_dp)
+        {
+            if smallest_width == 0 {
+                return Err(NdkError::InvalidSmallestWidth);
+            }
+            eprintln!(
+                "Warning: `android:requiresSmallestWidthDp` ({}dp) excludes all devices with a \
+                smaller screen from installing the app on Google Play.",
+                smallest_width
+            );
+        }
+
+        let mut manifest = self.manifest.clone();
+        if let Ok(version_code) = std::env::var(VERSION_CODE_ENV) {
+            manifest.version_code = Some(parse_version_code(&version_code)?);
+        }
+        if let Ok(version_name) = std::env::var(VERSION_NAME_ENV) {
+            manifest.version_name = Some(version_name);
+        }
+        if self.uncompressed_libs() {
+            if manifest.application.extract_native_libs == Some(true) {
+                eprintln!(
+                    "Warning: native libraries are stored uncompressed, overriding \
+                    `android:extractNativeLibs=\"true\"`"
+                );
+            }
+            manifest.application.extract_native_libs = Some(false);
+        }
+
+        std::fs::create_dir_all(&self.build_dir)?;
+        let staging_dir = self.staging_dir();
+        if self.clean {
+            self.clean()?;
+        }
+        std::fs::create_dir_all(&staging_dir)?;
+        let (resources, has_launcher_icon) = self.prepare_resources()?;
+        if has_launcher_icon && self.icon.is_some() && manifest.application.icon.is_none() {
+            manifest.application.icon = Some("@mipmap/ic_launcher".to_string());
+        }
+        manifest.write_to_with_placeholders(&staging_dir, &self.manifest_placeholders)?;
+        if let Some(overlay) = &self.manifest_overlay {
+            let path = staging_dir.join("AndroidManifest.xml");
+            let generated =
+                fs::read_to_string(&path).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+            let overlay = fs::read_to_string(overlay)
+                .map_err(|e| NdkError::IoPathError(overlay.clone(), e))?;
+            fs::write(&path, merge_manifest(&generated, &overlay)?)
+                .map_err(|e| NdkError::IoPathError(path, e))?;
+        }
+        self.emit(BuildEvent::ManifestWritten(
+            staging_dir.join("AndroidManifest.xml"),
+        ));
+
+        let target_sdk_version = self
+            .manifest
+            .sdk
+            .target_sdk_version
+            .unwrap_or_else(|| self.ndk.default_target_platform());
+
+        if self.manifest.shared_user_id.is_some() && target_sdk_version >= 29 {
+            eprintln!(
+                "Warning: `android:sharedUserId` is deprecated since API level 29 \
+                (https://developer.android.com/guide/topics/manifest/manifest-element#uid) \
+                and may be unsupported by future Android versions."
+            );
+        }
+
+        let android_jar = self.ndk.android_jar(target_sdk_version)?;
+        match self.aapt {
+            Aapt::Aapt1 => {
+                self.package_aapt1(&android_jar, resources.as_deref(), target_sdk_version)?
+            }
+            Aapt::Aapt2 => self.package_aapt2(&android_jar, resources.as_deref())?,
         }
+        self.emit(BuildEvent::ResourcesPackaged(self.unaligned_apk()));
 
         Ok(UnalignedApk {
             config: self,
-            pending_libs: HashSet::default(),
+            pending_libs: BTreeSet::default(),
+            dex_count: 0,
+        })
+    }
+}
+
+/// Removes the entries that [`ApkConfig::create_apk`] and [`UnalignedApk`] write to
+/// `staging_dir`, skipping any that is, contains or is contained in one of `keep`.
+fn remove_staging_outputs(
+    staging_dir: &Path,
+    apk_name: &str,
+    keep: &[&Path],
+) -> Result<(), NdkError> {
+    let entries = match fs::read_dir(staging_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(NdkError::IoPathError(staging_dir.to_owned(), e)),
+    };
+    let unaligned = format!("{}-unaligned.apk", apk_name);
+    let canonical = |path: &Path| dunce::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let keep = keep.iter().map(|path| canonical(path)).collect::<Vec<_>>();
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+        let owned = matches!(
+            name.as_ref(),
+            "lib" | "res" | "assets" | "resources.zip" | "AndroidManifest.xml"
+        ) || name == unaligned
+            || (name.starts_with("classes") && name.ends_with(".dex"));
+        let path = canonical(&path);
+        if !owned
+            || keep
+                .iter()
+                .any(|keep| keep.starts_with(&path) || path.starts_with(keep))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .map_err(|e| NdkError::IoPathError(path, e))?;
+    }
+    Ok(())
+}
+
+/// Whether `res` contains a resource `name` of `kind`, in any configuration (`kind-*`) directory.
+fn resource_exists(res: &Path, kind: &str, name: &str) -> bool {
+    fs::read_dir(res)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|dir| {
+            let dir_name = dir.file_name();
+            let dir_name = dir_name.to_string_lossy();
+            dir_name == kind || dir_name.starts_with(&format!("{}-", kind))
         })
+        .filter_map(|dir| fs::read_dir(dir.path()).ok())
+        .flatten()
+        .filter_map(|file| file.ok())
+        .any(|file| file.path().file_stem() == Some(OsStr::new(name)))
+}
+
+/// Whether `data` starts with the PNG signature and header chunk.
+fn is_png_data(data: &[u8]) -> bool {
+    data.len() >= 16 && data[..8] == *b"\x89PNG\r\n\x1a\n" && data[12..16] == *b"IHDR"
+}
+
+/// Writes `icon` scaled to the launcher icon size of every density as
+/// `mipmap-<density>/ic_launcher.png` into the resource directory `res`.
+fn write_launcher_icons(icon: &Path, res: &Path) -> Result<(), NdkError> {
+    let image = fs::read(icon)
+        .ok()
+        .and_then(|bytes| png::decode(&bytes))
+        .ok_or_else(|| NdkError::InvalidIcon(icon.to_owned()))?;
+    let largest = LAUNCHER_ICON_SIZES.last().unwrap().1;
+    if image.width < largest || image.height < largest {
+        eprintln!(
+            "Warning: icon `{}` is {}x{} pixels, scaling it up to {}x{} for `mipmap-xxxhdpi`",
+            icon.display(),
+            image.width,
+            image.height,
+            largest,
+            largest
+        );
+    }
+    for (density, size) in LAUNCHER_ICON_SIZES {
+        let dir = res.join(format!("mipmap-{}", density));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("ic_launcher.png");
+        fs::write(&path, png::encode(&image.resize(size, size)))
+            .map_err(|e| NdkError::IoPathError(path, e))?;
+    }
+    Ok(())
+}
+
+/// Recursively copies the contents of `from` into `to`, replacing existing files. Fails with
+/// [`NdkError::MergeConflict`] when a file would replace a folder or vice versa.
+fn copy_dir(from: &Path, to: &Path) -> Result<(), NdkError> {
+    if to.exists() && !to.is_dir() {
+        return Err(NdkError::MergeConflict(to.to_owned()));
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from).map_err(|e| NdkError::IoPathError(from.to_owned(), e))? {
+        let entry = entry?;
+        let path = entry.path();
+        let dest = to.join(entry.file_name());
+        if path.is_dir() {
+            copy_dir(&path, &dest)?;
+        } else if dest.is_dir() {
+            return Err(NdkError::MergeConflict(dest));
+        } else {
+            fs::copy(&path, &dest).map_err(|e| NdkError::IoPathError(path, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Merges the contents of `dirs` into a fresh `to`, in order.
+fn merge_dirs(dirs: &[PathBuf], to: &Path) -> Result<(), NdkError> {
+    if to.exists() {
+        fs::remove_dir_all(to).map_err(|e| NdkError::IoPathError(to.to_owned(), e))?;
+    }
+    fs::create_dir_all(to)?;
+    for dir in dirs {
+        copy_dir(dir, to)?;
+    }
+    Ok(())
+}
+
+/// Appends `-0 <ext>` for every extension, which both `aapt` and `aapt2 link` accept.
+fn no_compress_args(aapt: &mut Command, extensions: &[String]) {
+    for extension in extensions {
+        aapt.arg("-0").arg(extension);
+    }
+}
+
+pub(crate) fn strip_for_target(
+    strip: StripConfig,
+    overrides: &HashMap<Target, StripConfig>,
+    target: Target,
+) -> StripConfig {
+    overrides.get(&target).copied().unwrap_or(strip)
+}
+
+/// Resolves the `objcopy` that [`stage_lib`] strips and splits libraries with: `objcopy_override`
+/// when set, otherwise the one of the NDK.
+fn objcopy_bin(
+    ndk: &Ndk,
+    objcopy_override: Option<&Path>,
+    target: Target,
+) -> Result<PathBuf, NdkError> {
+    match objcopy_override {
+        Some(objcopy) => Ok(objcopy.to_owned()),
+        None => ndk.objcopy_bin(target),
+    }
+}
+
+/// Copies the library at `path` to `out`, treating its debug symbols according to `strip`.
+pub(crate) fn stage_lib(
+    ndk: &Ndk,
+    strip: StripConfig,
+    objcopy_override: Option<&Path>,
+    path: &Path,
+    target: Target,
+    out: &Path,
+    dry_run: &DryRun,
+) -> Result<(), NdkError> {
+    match strip {
+        StripConfig::Default => {
+            std::fs::copy(path, out)?;
+        }
+        StripConfig::Strip => {
+            let mut cmd = if objcopy_override.is_some() {
+                let mut cmd = Command::new(objcopy_bin(ndk, objcopy_override, target)?);
+                cmd.arg("--strip-debug");
+                cmd.arg(path);
+                cmd.arg(out);
+                cmd
+            } else {
+                let mut cmd = Command::new(ndk.strip_bin(target)?);
+                cmd.arg("--strip-debug");
+                cmd.arg(path);
+                cmd.arg("-o");
+                cmd.arg(out);
+                cmd
+            };
+
+            if !dry_run.skip(&cmd) && !cmd.status()?.success() {
+                return Err(NdkError::CmdFailed(Box::new(cmd)));
+            }
+        }
+        StripConfig::Split => {
+            let elf = fs::read(path).map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+            if crate::readelf::has_debug_sections(&elf) == Some(false) {
+                eprintln!(
+                    "Warning: `{}` has no debug sections to split off, not writing a `.dwarf`. \
+                    Check that the `strip` and `split-debuginfo` settings of the cargo profile \
+                    keep the debug info.",
+                    path.display()
+                );
+                fs::copy(path, out).map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+                return Ok(());
+            }
+
+            let obj_copy = objcopy_bin(ndk, objcopy_override, target)?;
+
+            {
+                let mut cmd = Command::new(&obj_copy);
+                cmd.arg("--strip-debug");
+                cmd.arg(path);
+                cmd.arg(out);
+
+                if !dry_run.skip(&cmd) && !cmd.status()?.success() {
+                    return Err(NdkError::CmdFailed(Box::new(cmd)));
+                }
+            }
+
+            let dwarf_path = out.with_extension("dwarf");
+
+            {
+                let mut cmd = Command::new(&obj_copy);
+                cmd.arg("--only-keep-debug");
+                cmd.arg(path);
+                cmd.arg(&dwarf_path);
+
+                if !dry_run.skip(&cmd) && !cmd.status()?.success() {
+                    return Err(NdkError::CmdFailed(Box::new(cmd)));
+                }
+            }
+
+            let mut cmd = Command::new(obj_copy);
+            cmd.arg(format!("--add-gnu-debuglink={}", dwarf_path.display()));
+            cmd.arg(out);
+
+            if !dry_run.skip(&cmd) && !cmd.status()?.success() {
+                return Err(NdkError::CmdFailed(Box::new(cmd)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Name of the per-ABI APK of `target`, see [`ApkConfig::for_abi`]
+fn split_apk_name(apk_name: &str, target: Target) -> String {
+    format!("{}-{}", apk_name, target.android_abi())
+}
+
+/// Name of the `index`th (starting at `0`) `.dex` in an APK: `classes.dex`, `classes2.dex`, ...
+pub(crate) fn dex_entry_name(index: usize) -> String {
+    match index {
+        0 => "classes.dex".to_string(),
+        n => format!("classes{}.dex", n + 1),
+    }
+}
+
+/// Copies `path` into `staging_dir` as the `index`th `.dex`, returning its entry name.
+fn stage_dex(staging_dir: &Path, index: usize, path: &Path) -> Result<String, NdkError> {
+    let name = dex_entry_name(index);
+    fs::copy(path, staging_dir.join(&name))
+        .map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+    Ok(name)
+}
+
+/// Copies `path` to `apk_path` relative to the staging directory, returning the entry name with
+/// UNIX path separators. `apk_path` must be relative and can't contain `..` components, so that
+/// the file can't escape the staging directory.
+fn stage_file(staging_dir: &Path, path: &Path, apk_path: &str) -> Result<String, NdkError> {
+    let name = apk_path.replace('\\', "/");
+    if Path::new(apk_path).is_absolute()
+        || name
+            .split('/')
+            .any(|component| component.is_empty() || component == "..")
+    {
+        return Err(NdkError::InvalidApkPath(apk_path.to_owned()));
     }
+    let out = staging_dir.join(&name);
+    fs::create_dir_all(out.parent().unwrap())?;
+    fs::copy(path, &out).map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+    Ok(name)
+}
+
+/// Number of `.dex` files at the root of an existing APK, so that [`UnalignedApk::add_dex`]
+/// continues the numbering instead of replacing them.
+fn dex_count(apk: &[u8]) -> Result<usize, NdkError> {
+    let entries = crate::zip::read_entries(apk)?;
+    Ok((0..)
+        .take_while(|&index| {
+            let name = dex_entry_name(index);
+            entries.iter().any(|entry| entry.name == name)
+        })
+        .count())
 }
 
 pub struct UnalignedApk<'a> {
     config: &'a ApkConfig,
-    pending_libs: HashSet<String>,
+    /// Paths of staged entries relative to the staging directory, sorted so that they are added
+    /// to the APK in a stable order across builds
+    pending_libs: BTreeSet<String>,
+    /// Number of `.dex` files added with [`UnalignedApk::add_dex`]
+    dex_count: usize,
 }
 
 impl<'a> UnalignedApk<'a> {
@@ -117,65 +978,160 @@ impl<'a> UnalignedApk<'a> {
         self.config
     }
 
-    pub fn add_lib(&mut self, path: &Path, target: Target) -> Result<(), NdkError> {
+    /// Validates `path` and prepares the staging directory for it, returning the staged path and
+    /// the path of the library inside the APK.
+    fn prepare_lib(&self, path: &Path, target: Target) -> Result<(PathBuf, String), NdkError> {
         if !path.exists() {
             return Err(NdkError::PathNotFound(path.into()));
         }
         let abi = target.android_abi();
         let lib_path = Path::new("lib").join(abi).join(path.file_name().unwrap());
-        let out = self.config.build_dir.join(&lib_path);
+        let out = self.config.staging_dir().join(&lib_path);
         std::fs::create_dir_all(out.parent().unwrap())?;
 
-        match self.config.strip {
-            StripConfig::Default => {
-                std::fs::copy(path, out)?;
-            }
-            StripConfig::Strip | StripConfig::Split => {
-                let obj_copy = self.config.ndk.toolchain_bin("objcopy", target)?;
+        // Pass UNIX path separators to `aapt` on non-UNIX systems, ensuring the resulting separator
+        // is compatible with the target device instead of the host platform.
+        // Otherwise, it results in a runtime error when loading the NativeActivity `.so` library.
+        let lib_path_unix = lib_path.to_str().unwrap().replace('\\', "/");
 
-                {
-                    let mut cmd = Command::new(&obj_copy);
-                    cmd.arg("--strip-debug");
-                    cmd.arg(path);
-                    cmd.arg(&out);
+        Ok((out, lib_path_unix))
+    }
 
-                    if !cmd.status()?.success() {
-                        return Err(NdkError::CmdFailed(cmd));
-                    }
-                }
+    pub fn add_lib(&mut self, path: &Path, target: Target) -> Result<(), NdkError> {
+        let (out, lib_path_unix) = self.prepare_lib(path, target)?;
 
-                if self.config.strip == StripConfig::Split {
-                    let dwarf_path = out.with_extension("dwarf");
+        stage_lib(
+            &self.config.ndk,
+            self.config.strip_for(target),
+            self.config.objcopy_override.as_deref(),
+            path,
+            target,
+            &out,
+            &self.config.dry_runner(),
+        )?;
 
-                    {
-                        let mut cmd = Command::new(&obj_copy);
-                        cmd.arg("--only-keep-debug");
-                        cmd.arg(path);
-                        cmd.arg(&dwarf_path);
+        self.pending_libs.insert(lib_path_unix);
+        self.config.emit(BuildEvent::LibAdded {
+            path: path.to_owned(),
+            target,
+        });
 
-                        if !cmd.status()?.success() {
-                            return Err(NdkError::CmdFailed(cmd));
-                        }
-                    }
+        Ok(())
+    }
 
-                    let mut cmd = Command::new(obj_copy);
-                    cmd.arg(format!("--add-gnu-debuglink={}", dwarf_path.display()));
-                    cmd.arg(out);
+    /// Same as calling [`UnalignedApk::add_lib`] for every path, but strips the libraries on
+    /// multiple threads. When any library fails to be staged, the error of the first such library
+    /// in `paths` is returned and none of the libraries are added.
+    pub fn add_libs(&mut self, paths: &[&Path], target: Target) -> Result<(), NdkError> {
+        let jobs = paths
+            .iter()
+            .map(|path| {
+                let (out, lib_path_unix) = self.prepare_lib(path, target)?;
+                Ok((path.to_path_buf(), out, lib_path_unix))
+            })
+            .collect::<Result<Vec<_>, NdkError>>()?;
 
-                    if !cmd.status()?.success() {
-                        return Err(NdkError::CmdFailed(cmd));
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(jobs.len());
+        let jobs = Arc::new(jobs);
+        let next_job = Arc::new(AtomicUsize::new(0));
+        let workers = (0..threads)
+            .map(|_| {
+                let ndk = self.config.ndk.clone();
+                let strip = self.config.strip_for(target);
+                let objcopy_override = self.config.objcopy_override.clone();
+                let dry_run = self.config.dry_runner();
+                let jobs = Arc::clone(&jobs);
+                let next_job = Arc::clone(&next_job);
+                std::thread::spawn(move || {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next_job.fetch_add(1, Ordering::Relaxed);
+                        let (path, out, _) = match jobs.get(index) {
+                            Some(job) => job,
+                            None => break results,
+                        };
+                        results.push((
+                            index,
+                            stage_lib(
+                                &ndk,
+                                strip,
+                                objcopy_override.as_deref(),
+                                path,
+                                target,
+                                out,
+                                &dry_run,
+                            ),
+                        ));
                     }
-                }
-            }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Failed to join strip thread"))
+            .collect::<Vec<_>>();
+        results.sort_by_key(|(index, _)| *index);
+        for (_, result) in results {
+            result?;
         }
 
-        // Pass UNIX path separators to `aapt` on non-UNIX systems, ensuring the resulting separator
-        // is compatible with the target device instead of the host platform.
-        // Otherwise, it results in a runtime error when loading the NativeActivity `.so` library.
-        let lib_path_unix = lib_path.to_str().unwrap().replace('\\', "/");
+        for (path, _, lib_path_unix) in jobs.iter() {
+            self.pending_libs.insert(lib_path_unix.clone());
+            self.config.emit(BuildEvent::LibAdded {
+                path: path.clone(),
+                target,
+            });
+        }
 
-        self.pending_libs.insert(lib_path_unix);
+        Ok(())
+    }
+
+    /// Stages a prebuilt `.dex` (e.g. compiled with `d8`), for example containing the
+    /// [`ActivityType::GameActivity`] classes, at the root of the APK. The first call adds
+    /// `classes.dex`, subsequent calls add `classes2.dex`, `classes3.dex`, ... for multidex.
+    ///
+    /// Like native libraries, the entries are only stored uncompressed with
+    /// [`ApkConfig::disable_aapt_compression`].
+    pub fn add_dex(&mut self, path: &Path) -> Result<(), NdkError> {
+        if !path.exists() {
+            return Err(NdkError::PathNotFound(path.into()));
+        }
+        let name = stage_dex(&self.config.staging_dir(), self.dex_count, path)?;
+        self.config.emit(BuildEvent::DexAdded(name.clone()));
+        self.pending_libs.insert(name);
+        self.dex_count += 1;
+        Ok(())
+    }
+
+    /// Stages an arbitrary file, such as a `META-INF/` entry or a configuration file at the root of
+    /// the APK, to be added at `apk_path`. `apk_path` is relative to the root of the APK and may
+    /// use either path separator, but can't contain `..` components.
+    ///
+    /// Like native libraries, the entry is only stored uncompressed with
+    /// [`ApkConfig::disable_aapt_compression`] or a matching
+    /// [`ApkConfig::no_compress_extensions`].
+    pub fn add_file(&mut self, path: &Path, apk_path: &str) -> Result<(), NdkError> {
+        if !path.exists() {
+            return Err(NdkError::PathNotFound(path.into()));
+        }
+        let name = stage_file(&self.config.staging_dir(), path, apk_path)?;
+        self.config.emit(BuildEvent::FileAdded(name.clone()));
+        self.pending_libs.insert(name);
+        Ok(())
+    }
 
+    /// Stages several `.dex` files in order with [`UnalignedApk::add_dex`]. Nothing is added
+    /// unless all of them exist.
+    pub fn add_dexes(&mut self, paths: &[&Path]) -> Result<(), NdkError> {
+        if let Some(missing) = paths.iter().find(|path| !path.exists()) {
+            return Err(NdkError::PathNotFound(missing.to_path_buf()));
+        }
+        for path in paths {
+            self.add_dex(path)?;
+        }
         Ok(())
     }
 
@@ -196,13 +1152,34 @@ impl<'a> UnalignedApk<'a> {
         Ok(())
     }
 
+    /// Adds all staged entries to the APK in sorted order, so that they are added in a stable
+    /// order regardless of the order they were staged in, and aligns it with `zipalign`.
     pub fn add_pending_libs_and_align(self) -> Result<UnsignedApk<'a>, NdkError> {
+        if self.config.activity_type == ActivityType::GameActivity
+            && !self.pending_libs.contains("classes.dex")
+        {
+            return Err(NdkError::MissingDex(
+                self.config.activity_type.activity_name(),
+            ));
+        }
+
+        if self.config.page_alignment == PageAlignment::Align16K {
+            self.config
+                .ndk
+                .require(BuildToolsCapability::ZipalignPageAlignment)?;
+        }
+
         let mut aapt = self.config.build_tool(bin!("aapt"))?;
         aapt.arg("add");
 
-        if self.config.disable_aapt_compression {
+        if self.config.store_uncompressed() {
             aapt.arg("-0").arg("");
+        } else if self.config.uncompressed_libs() {
+            // Compressed libraries are extracted on installation, which also makes the page
+            // alignment moot
+            aapt.arg("-0").arg("so");
         }
+        no_compress_args(&mut aapt, &self.config.no_compress_extensions);
 
         aapt.arg(self.config.unaligned_apk());
 
@@ -210,50 +1187,635 @@ impl<'a> UnalignedApk<'a> {
             aapt.arg(lib_path_unix);
         }
 
-        if !aapt.status()?.success() {
-            return Err(NdkError::CmdFailed(aapt));
+        let dry_run = self.config.dry_runner();
+        if !dry_run.skip(&aapt) && !aapt.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(aapt)));
         }
 
-        let mut zipalign = self.config.build_tool(bin!("zipalign"))?;
-        zipalign
-            .arg("-f")
-            .arg("-v")
-            .arg("4")
-            .arg(self.config.unaligned_apk())
-            .arg(self.config.apk());
+        // `zipalign` below aligns the recompressed entries again after they moved
+        let recompress = !self.config.store_uncompressed() && !dry_run.enabled;
+        if let Some(level) = self.config.compression_level.filter(|_| recompress) {
+            let unaligned_apk = self.config.unaligned_apk();
+            let bytes = fs::read(&unaligned_apk)
+                .map_err(|e| NdkError::IoPathError(unaligned_apk.clone(), e))?;
+            fs::write(&unaligned_apk, crate::zip::recompress(&bytes, level)?)
+                .map_err(|e| NdkError::IoPathError(unaligned_apk, e))?;
+        }
+
+        let mut zipalign = self.config.build_tool(bin!("zipalign"))?;
+        zipalign_args(
+            &mut zipalign,
+            self.config.alignment,
+            self.config.page_alignment,
+            &self.config.unaligned_apk(),
+            &self.config.apk(),
+        )?;
 
-        if !zipalign.status()?.success() {
-            return Err(NdkError::CmdFailed(zipalign));
+        if !dry_run.skip(&zipalign) && !zipalign.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(zipalign)));
         }
+        self.config.emit(BuildEvent::Aligned(self.config.apk()));
 
         Ok(UnsignedApk(self.config))
     }
 }
 
+/// The [APK signature schemes](https://source.android.com/docs/security/features/apksigning)
+/// that `apksigner` should sign with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SigningScheme {
+    /// JAR signing, the only scheme verified by Android 6.0 (API level 23) and older
+    pub v1: bool,
+    /// APK Signature Scheme v2, verified since Android 7.0 (API level 24)
+    pub v2: bool,
+    /// APK Signature Scheme v3, which supports key rotation, verified since Android 9 (API level 28)
+    pub v3: bool,
+    /// APK Signature Scheme v4, a separate `.idsig` file used by incremental installs
+    pub v4: bool,
+}
+
+impl SigningScheme {
+    /// The schemes needed for an APK to install on `min_sdk_version` and up: v1 is only
+    /// enabled when devices that lack v2 verification are supported.
+    pub fn for_min_sdk(min_sdk_version: u32) -> Self {
+        Self {
+            v1: min_sdk_version < 24,
+            v2: true,
+            v3: true,
+            v4: false,
+        }
+    }
+}
+
+/// Appends the `apksigner sign` arguments for signing `apk` with `key`.
+fn apksigner_sign_args(cmd: &mut Command, key: &Key, scheme: SigningScheme, apk: &Path) {
+    cmd.arg("sign");
+    apksigner_key_args(cmd, key);
+    apksigner_scheme_args(cmd, scheme);
+    cmd.arg(apk);
+}
+
+/// Appends the `apksigner sign` arguments for rotating from [`Lineage::old_key`] to
+/// [`Lineage::new_key`]. The old signer comes first, followed by `--next-signer` and the new
+/// one.
+fn apksigner_lineage_args(cmd: &mut Command, lineage: &Lineage, scheme: SigningScheme, apk: &Path) {
+    cmd.arg("sign");
+    apksigner_key_args(cmd, &lineage.old_key);
+    cmd.arg("--next-signer");
+    apksigner_key_args(cmd, &lineage.new_key);
+    cmd.arg("--lineage").arg(&lineage.file);
+    apksigner_scheme_args(cmd, scheme);
+    cmd.arg(apk);
+}
+
+/// Appends the arguments that select the signer `key` of `apksigner`.
+fn apksigner_key_args(cmd: &mut Command, key: &Key) {
+    cmd.arg("--ks")
+        .arg(&key.path)
+        .arg("--ks-pass")
+        .arg(key.password.apksigner_arg());
+    if let Some(alias) = &key.alias {
+        cmd.arg("--ks-key-alias").arg(alias);
+    }
+    if let Some(key_password) = &key.key_password {
+        cmd.arg("--key-pass").arg(key_password.apksigner_arg());
+    }
+}
+
+/// Appends one `--vN-signing-enabled` per scheme.
+fn apksigner_scheme_args(cmd: &mut Command, scheme: SigningScheme) {
+    for (flag, enabled) in [
+        ("--v1-signing-enabled", scheme.v1),
+        ("--v2-signing-enabled", scheme.v2),
+        ("--v3-signing-enabled", scheme.v3),
+        ("--v4-signing-enabled", scheme.v4),
+    ] {
+        cmd.arg(flag).arg(enabled.to_string());
+    }
+}
+
+/// A signing certificate lineage for [key rotation] with the v3 scheme, as created by
+/// `apksigner rotate --out <file> --old-signer ... --new-signer ...`.
+///
+/// [key rotation]: https://source.android.com/docs/security/features/apksigning/v3#key-rotation
+#[derive(Clone)]
+pub struct Lineage {
+    /// The lineage file
+    pub file: PathBuf,
+    /// The key the app was signed with until now
+    pub old_key: Key,
+    /// The key that replaces [`Lineage::old_key`]
+    pub new_key: Key,
+}
+
+/// Appends the `jarsigner` arguments for signing `unsigned` into `signed` with the key `alias`,
+/// returning the passwords that have to be written to its standard input, in order.
+pub(crate) fn jarsigner_sign_args<'k>(
+    cmd: &mut Command,
+    key: &'k Key,
+    alias: &str,
+    unsigned: &Path,
+    signed: &Path,
+) -> Vec<&'k str> {
+    cmd.arg("-keystore").arg(&key.path);
+    let mut stdin = Vec::new();
+    stdin.extend(key.password.jdk_tool_arg(cmd, "-storepass"));
+    if let Some(key_password) = &key.key_password {
+        stdin.extend(key_password.jdk_tool_arg(cmd, "-keypass"));
+    }
+    cmd.arg("-signedjar").arg(signed).arg(unsigned).arg(alias);
+    stdin
+}
+
+/// Tool that [`UnsignedApk`] is signed with
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Signer {
+    /// `apksigner` of the selected build tools
+    Apksigner,
+    /// `apksigner`, or the JDK's `jarsigner` when the build tools don't ship `apksigner`
+    ApksignerOrJarsigner,
+    /// The JDK's `jarsigner`, which only produces the v1 (JAR) signature. Apps targeting
+    /// API level 30 or higher don't install without a v2 signature.
+    Jarsigner,
+}
+
+impl Default for Signer {
+    fn default() -> Self {
+        Self::Apksigner
+    }
+}
+
 pub struct UnsignedApk<'a>(&'a ApkConfig);
 
 impl<'a> UnsignedApk<'a> {
+    /// Signs the APK with the [`SigningScheme::for_min_sdk`] schemes of the manifest's
+    /// `min_sdk_version`.
     pub fn sign(self, key: Key) -> Result<Apk, NdkError> {
+        // Without a `min_sdk_version` the APK installs on every API level
+        let min_sdk_version = self.0.manifest.sdk.min_sdk_version.unwrap_or(1);
+        self.sign_with(key, SigningScheme::for_min_sdk(min_sdk_version))
+    }
+
+    /// Signs the APK with the given `scheme`s, each mapped onto `--vN-signing-enabled`.
+    ///
+    /// With [`SigningScheme::v4`] the `.apk.idsig` is written next to the APK, which requires
+    /// build-tools 30.0.0 or newer.
+    ///
+    /// When signing with `jarsigner` according to [`ApkConfig::signer`], only the v1 signature
+    /// is produced and the APK is aligned again afterwards.
+    ///
+    /// [`KeyPassword::Env`] passwords are read here and written to the standard input of the
+    /// signer, failing with [`NdkError::MissingEnvVar`] if the variable is not set.
+    pub fn sign_with(self, key: Key, scheme: SigningScheme) -> Result<Apk, NdkError> {
+        let key = key.resolve_env()?;
+        let use_jarsigner = match self.0.signer {
+            Signer::Apksigner => false,
+            Signer::ApksignerOrJarsigner => self.0.build_tool(bat!("apksigner")).is_err(),
+            Signer::Jarsigner => true,
+        };
+        if use_jarsigner {
+            return self.sign_with_jarsigner(key, scheme);
+        }
+
         let mut apksigner = self.0.build_tool(bat!("apksigner"))?;
-        apksigner
-            .arg("sign")
-            .arg("--ks")
-            .arg(&key.path)
-            .arg("--ks-pass")
-            .arg(format!("pass:{}", &key.password))
-            .arg(self.0.apk());
-        if !apksigner.status()?.success() {
-            return Err(NdkError::CmdFailed(apksigner));
+        apksigner_sign_args(&mut apksigner, &key, scheme, &self.0.apk());
+        self.run_apksigner(apksigner, &[&key], scheme)
+    }
+
+    /// Signs the APK with [`Lineage::new_key`], proving through the lineage that it replaces
+    /// [`Lineage::old_key`], so that devices with the app signed by the old key accept the
+    /// update. Rotation is part of the v3 scheme, which is always enabled. Always signs with
+    /// `apksigner`, regardless of [`ApkConfig::signer`].
+    pub fn sign_with_lineage(
+        self,
+        lineage: &Lineage,
+        scheme: SigningScheme,
+    ) -> Result<Apk, NdkError> {
+        if !lineage.file.exists() {
+            return Err(NdkError::PathNotFound(lineage.file.clone()));
+        }
+        let lineage = Lineage {
+            file: lineage.file.clone(),
+            old_key: lineage.old_key.resolve_env()?,
+            new_key: lineage.new_key.resolve_env()?,
+        };
+        let scheme = SigningScheme { v3: true, ..scheme };
+        let mut apksigner = self.0.build_tool(bat!("apksigner"))?;
+        apksigner_lineage_args(&mut apksigner, &lineage, scheme, &self.0.apk());
+        self.run_apksigner(apksigner, &[&lineage.old_key, &lineage.new_key], scheme)
+    }
+
+    /// Runs `apksigner sign`, with the arguments for signing with `keys` already appended.
+    fn run_apksigner(
+        self,
+        mut apksigner: Command,
+        keys: &[&Key],
+        scheme: SigningScheme,
+    ) -> Result<Apk, NdkError> {
+        if scheme.v4 {
+            self.0
+                .ndk
+                .require(BuildToolsCapability::ApksignerV4Signing)?;
         }
+
+        let apk = self.0.apk();
+        let dry_run = self.0.dry_runner();
+        // `apksigner` reads one line per `stdin` password, in the order of the arguments
+        let stdin = keys
+            .iter()
+            .flat_map(|key| {
+                key.password
+                    .stdin()
+                    .into_iter()
+                    .chain(key.key_password.as_ref().and_then(KeyPassword::stdin))
+            })
+            .collect::<Vec<_>>();
+        if !dry_run.skip(&apksigner) && !status_with_stdin(&mut apksigner, &stdin)? {
+            return Err(NdkError::CmdFailed(Box::new(apksigner)));
+        }
+
+        self.0.emit(BuildEvent::Signed(apk.clone()));
+
+        let mut signed = Apk::from_config(self.0);
+        if scheme.v4 {
+            let mut idsig = apk.into_os_string();
+            idsig.push(".idsig");
+            signed.idsig = Some(idsig.into());
+        }
+        Ok(signed)
+    }
+
+    fn sign_with_jarsigner(self, key: Key, scheme: SigningScheme) -> Result<Apk, NdkError> {
+        if scheme.v4 {
+            return Err(NdkError::UnsupportedJarsignerScheme);
+        }
+        if scheme.v2 || scheme.v3 {
+            eprintln!("Warning: `jarsigner` only produces a v1 signature, skipping v2 and v3");
+        }
+        let mut jarsigner = self
+            .0
+            .ndk
+            .jarsigner()
+            .map_err(|_| NdkError::NoSignerFound)?;
+        let alias = key.resolve_alias(&self.0.ndk)?;
+
+        // `jarsigner` rewrites the archive, so sign the aligned APK into the staging directory
+        // and align the result again
+        let apk = self.0.apk();
+        let signed = self.0.unaligned_apk();
+        let stdin = jarsigner_sign_args(&mut jarsigner, &key, &alias, &apk, &signed);
+        let dry_run = self.0.dry_runner();
+        if !dry_run.skip(&jarsigner) && !status_with_stdin(&mut jarsigner, &stdin)? {
+            return Err(NdkError::CmdFailed(Box::new(jarsigner)));
+        }
+
+        let mut zipalign = self.0.build_tool(bin!("zipalign"))?;
+        zipalign_args(
+            &mut zipalign,
+            self.0.alignment,
+            self.0.page_alignment,
+            &signed,
+            &apk,
+        )?;
+        if !dry_run.skip(&zipalign) && !zipalign.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(zipalign)));
+        }
+
+        self.0.emit(BuildEvent::Signed(apk));
         Ok(Apk::from_config(self.0))
     }
+
+    /// Same as [`UnsignedApk::sign`], but additionally produces the v4 signature (`.apk.idsig`)
+    /// needed by [`Apk::install`] to install incrementally.
+    pub fn sign_v4(self, key: Key) -> Result<Apk, NdkError> {
+        let min_sdk_version = self.0.manifest.sdk.min_sdk_version.unwrap_or(1);
+        let scheme = SigningScheme {
+            v4: true,
+            ..SigningScheme::for_min_sdk(min_sdk_version)
+        };
+        self.sign_with(key, scheme)
+    }
+}
+
+/// Additional flags for [`Apk::install_with`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstallOptions {
+    /// Grant all runtime permissions requested in the manifest (`-g`)
+    pub grant_permissions: bool,
+    /// Allow replacing an installation with a lower `versionCode` (`-d`)
+    pub allow_downgrade: bool,
+    /// Allow installing an APK with `android:testOnly="true"` (`-t`)
+    pub allow_test_packages: bool,
+    /// Only install for this user, e.g. a work profile (`--user`). The user must exist on the
+    /// device.
+    pub user: Option<u32>,
+    /// Clear the data of the app after installing, see [`Apk::clear_data`]. `install -r`
+    /// preserves it otherwise.
+    pub clear_data: bool,
+}
+
+/// Appends the `adb` arguments for (re)installing the APK at `path`.
+fn install_args(adb: &mut Command, options: &InstallOptions, incremental: bool, path: &Path) {
+    adb.arg("install");
+    if incremental {
+        adb.arg("--incremental");
+    }
+    adb.arg("-r");
+    install_option_args(adb, options);
+    adb.arg(path);
+}
+
+/// Appends the `adb install-multiple` arguments for installing `apks` as a single package, see
+/// [`Ndk::install_multiple`].
+pub(crate) fn install_multiple_args(adb: &mut Command, options: &InstallOptions, apks: &[&Path]) {
+    adb.arg("install-multiple").arg("-r");
+    install_option_args(adb, options);
+    adb.args(apks);
+}
+
+/// Appends the flags of `options` that `adb install` and `adb install-multiple` share.
+fn install_option_args(adb: &mut Command, options: &InstallOptions) {
+    if options.grant_permissions {
+        adb.arg("-g");
+    }
+    if options.allow_downgrade {
+        adb.arg("-d");
+    }
+    if options.allow_test_packages {
+        adb.arg("-t");
+    }
+    if let Some(user) = options.user {
+        adb.arg("--user").arg(user.to_string());
+    }
+}
+
+/// Appends the `adb` arguments for clearing the data of `package`.
+fn clear_data_args(adb: &mut Command, package: &str, user: Option<u32>) {
+    adb.arg("shell").arg("pm").arg("clear");
+    if let Some(user) = user {
+        adb.arg("--user").arg(user.to_string());
+    }
+    adb.arg(package);
+}
+
+/// Parses the output of `pm clear` into whether the data was cleared, or `None` when it failed
+/// for a reason other than the package not being installed. Older releases only print `Failed`
+/// for missing packages, newer ones throw an exception stating that the package does not exist.
+fn parse_pm_clear(output: &str) -> Option<bool> {
+    let output = output.trim();
+    if output.starts_with("Success") {
+        Some(true)
+    } else if output == "Failed"
+        || output.contains("does not exist")
+        || output.contains("Unknown package")
+    {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Result of [`Apk::verify`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verification {
+    /// The signature schemes that verified
+    pub schemes: SigningScheme,
+    /// SHA-256 digests of the signer certificates, as lowercase hex
+    pub signers: Vec<String>,
+}
+
+/// Information about a built APK, read back with `aapt dump badging` by [`Apk::badging`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Badging {
+    pub package_name: String,
+    pub version_code: Option<u32>,
+    pub version_name: Option<String>,
+    pub min_sdk_version: Option<u32>,
+    pub target_sdk_version: Option<u32>,
+    /// Activity started by the launcher
+    pub launchable_activity: Option<String>,
+    /// Names of all requested permissions
+    pub permissions: Vec<String>,
+    /// Whether `android:debuggable` is set, which `run-as` and debuggers require
+    pub debuggable: bool,
+}
+
+/// Part of an APK that [`Apk::size_report`] accounts entries to
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SizeCategory {
+    /// `classes*.dex`
+    Dex,
+    /// Native libraries of the given ABI, in `lib/<abi>`
+    Lib(String),
+    /// `resources.arsc` and `res/`
+    Resources,
+    /// `assets/`
+    Assets,
+    /// The manifest, signature files and anything else
+    Other,
+}
+
+impl SizeCategory {
+    fn of_entry(name: &str) -> Self {
+        if let Some(lib) = name.strip_prefix("lib/") {
+            match lib.split_once('/') {
+                Some((abi, _)) => Self::Lib(abi.to_owned()),
+                None => Self::Other,
+            }
+        } else if name.starts_with("assets/") {
+            Self::Assets
+        } else if name.starts_with("res/") || name == "resources.arsc" {
+            Self::Resources
+        } else if name.starts_with("classes") && name.ends_with(".dex") && !name.contains('/') {
+            Self::Dex
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Summed sizes of the entries in a [`SizeCategory`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SectionSize {
+    /// Bytes taken up in the APK
+    pub compressed: u64,
+    /// Bytes after extraction
+    pub uncompressed: u64,
+}
+
+/// Result of [`Apk::size_report`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// Size of the APK file, including zip headers and the signing block
+    pub total: u64,
+    pub sections: BTreeMap<SizeCategory, SectionSize>,
+}
+
+impl SizeReport {
+    fn from_zip(bytes: &[u8]) -> Result<Self, NdkError> {
+        let mut report = Self {
+            total: bytes.len() as u64,
+            ..Default::default()
+        };
+        for entry in crate::zip::read_entries(bytes)? {
+            let section = report
+                .sections
+                .entry(SizeCategory::of_entry(&entry.name))
+                .or_default();
+            section.compressed += entry.compressed_size;
+            section.uncompressed += entry.uncompressed_size;
+        }
+        Ok(report)
+    }
+}
+
+/// Finds the value of `key='value'` in a line of `aapt dump badging`.
+fn badging_attribute<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!(" {}='", key))? + key.len() + 3;
+    let len = line[start..].find('\'')?;
+    Some(&line[start..start + len])
+}
+
+/// Parses the output of `aapt dump badging`.
+fn parse_badging(output: &str) -> Badging {
+    let mut badging = Badging::default();
+    let quoted = |value: &str| value.trim().trim_matches('\'').to_owned();
+    for line in output.lines() {
+        if line.trim() == "application-debuggable" {
+            badging.debuggable = true;
+            continue;
+        }
+        let (kind, rest) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        // Prefix with a space so that the first attribute is found like all others
+        let rest = format!(" {}", rest.trim_start());
+        match kind {
+            "package" => {
+                badging.package_name = badging_attribute(&rest, "name")
+                    .unwrap_or_default()
+                    .to_owned();
+                badging.version_code =
+                    badging_attribute(&rest, "versionCode").and_then(|v| v.parse().ok());
+                badging.version_name = badging_attribute(&rest, "versionName")
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_owned);
+            }
+            "sdkVersion" => badging.min_sdk_version = quoted(&rest).parse().ok(),
+            "targetSdkVersion" => badging.target_sdk_version = quoted(&rest).parse().ok(),
+            "launchable-activity" => {
+                badging.launchable_activity = badging_attribute(&rest, "name").map(str::to_owned)
+            }
+            "uses-permission" => badging
+                .permissions
+                .extend(badging_attribute(&rest, "name").map(str::to_owned)),
+            _ => {}
+        }
+    }
+    badging
+}
+
+/// An extra passed to the intent launched by [`Apk::start_with`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntentExtra {
+    /// `--es <key> <value>`
+    String(String, String),
+    /// `--ez <key> <value>`
+    Bool(String, bool),
+    /// `--ei <key> <value>`
+    Int(String, i32),
+    /// `--el <key> <value>`
+    Long(String, i64),
+}
+
+/// The intent launched by [`Apk::start_with`]. `-a` and `-c` are only passed to `am start` when
+/// [`StartOptions::action`] and [`StartOptions::category`] are set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StartOptions {
+    pub action: Option<String>,
+    pub category: Option<String>,
+    pub extras: Vec<IntentExtra>,
+    /// Launch the activity as this user, e.g. a work profile (`--user`). The user must exist on
+    /// the device.
+    pub user: Option<u32>,
+}
+
+impl StartOptions {
+    /// The `android.intent.action.MAIN` intent launched by [`Apk::start`]
+    pub fn main() -> Self {
+        Self {
+            action: Some("android.intent.action.MAIN".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Future that resolves to the result of a blocking operation running on its own thread, as
+/// returned by [`Apk::install_async`] and [`Apk::start_async`]. It does not depend on a
+/// particular async runtime.
+pub struct BlockingTask<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+}
+
+struct TaskState<T> {
+    result: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T: Send + 'static> BlockingTask<T> {
+    fn spawn(f: impl FnOnce() -> T + Send + 'static) -> Self {
+        let state = Arc::new(Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }));
+        let task_state = Arc::clone(&state);
+        std::thread::spawn(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            let mut state = task_state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        Self { state }
+    }
+}
+
+impl<T> Future for BlockingTask<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Apk {
     path: PathBuf,
+    /// The v4 signature, when signed with [`SigningScheme::v4`]
+    idsig: Option<PathBuf>,
+    /// The application id the APK is installed under
     package_name: String,
+    /// The manifest package, that relative activity names are resolved against
+    manifest_package: String,
+    activity_name: String,
+    min_sdk_version: Option<u32>,
     ndk: Ndk,
     reverse_port_forward: HashMap<String, String>,
+    port_forward: HashMap<String, String>,
+    /// Limit for the duration of each `adb` command that talks to the device
+    timeout: Option<Duration>,
+    dry_run: DryRun,
 }
 
 impl Apk {
@@ -261,83 +1823,2320 @@ impl Apk {
         let ndk = config.ndk.clone();
         Self {
             path: config.apk(),
-            package_name: config.manifest.package.clone(),
+            idsig: None,
+            package_name: config.application_id().to_owned(),
+            manifest_package: config.manifest.package.clone(),
+            activity_name: config.manifest.application.activity.name.clone(),
+            min_sdk_version: config.manifest.sdk.min_sdk_version,
             ndk,
             reverse_port_forward: config.reverse_port_forward.clone(),
+            port_forward: config.port_forward.clone(),
+            timeout: None,
+            dry_run: config.dry_runner(),
         }
     }
 
+    /// Reads the package name, versions, launchable activity and permissions back from the
+    /// built APK with `aapt dump badging`.
+    pub fn badging(&self) -> Result<Badging, NdkError> {
+        let mut aapt = self.ndk.build_tool(bin!("aapt"))?;
+        aapt.arg("dump").arg("badging").arg(&self.path);
+        let output = aapt.output()?;
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(aapt)));
+        }
+        Ok(parse_badging(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Breaks the size of the built APK down into dex files, native libraries per ABI,
+    /// resources and assets, by reading the zip central directory.
+    pub fn size_report(&self) -> Result<SizeReport, NdkError> {
+        let bytes =
+            fs::read(&self.path).map_err(|e| NdkError::IoPathError(self.path.clone(), e))?;
+        SizeReport::from_zip(&bytes)
+    }
+
+    /// Whether the built APK is debuggable, read back with [`Apk::badging`].
+    pub fn is_debuggable(&self) -> Result<bool, NdkError> {
+        Ok(self.badging()?.debuggable)
+    }
+
+    /// Sets an environment variable on every `adb` command spawned for this APK, see
+    /// [`Ndk::set_adb_env`].
+    pub fn set_adb_env(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.ndk.set_adb_env(key, value);
+    }
+
+    /// Kills `adb` and fails with [`NdkError::Timeout`] when installing, starting, looking up
+    /// the uid, port forwarding, transferring files or running instrumentation takes longer than
+    /// `timeout`, e.g. because the device went to sleep. There is no timeout by default.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Reports the `adb` commands that change the state of the device, i.e. of installing,
+    /// starting, uninstalling, port forwarding, pushing files and running instrumentation, instead
+    /// of running them, like [`ApkConfig::dry_run`] which this defaults to. These methods then
+    /// succeed without an effect; [`Apk::clear_data`] reports that data was cleared,
+    /// [`Apk::start_and_wait`] returns pid `0` and [`Apk::instrument`] returns no tests. Queries,
+    /// such as [`Apk::pidof`] or [`Apk::verify`], still run.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run.enabled = dry_run;
+    }
+
+    /// Re-enters the [`UnalignedApk`] state with the contents of this APK, to add or replace
+    /// entries after it was signed. The existing signature is invalidated by this; the APK must
+    /// go through [`UnalignedApk::add_pending_libs_and_align`] and [`UnsignedApk::sign`] again,
+    /// which redoes the alignment before re-signing.
+    pub fn into_unaligned(self, config: &ApkConfig) -> Result<UnalignedApk<'_>, NdkError> {
+        std::fs::create_dir_all(config.staging_dir())?;
+        let unaligned = config.unaligned_apk();
+        std::fs::copy(&self.path, &unaligned)
+            .map_err(|e| NdkError::IoPathError(unaligned.clone(), e))?;
+        let contents = fs::read(&unaligned).map_err(|e| NdkError::IoPathError(unaligned, e))?;
+        Ok(UnalignedApk {
+            config,
+            pending_libs: BTreeSet::default(),
+            dex_count: dex_count(&contents)?,
+        })
+    }
+
+    /// Forwards connections to the `from` socket on the device to the `to` host socket with
+    /// `adb reverse`, for every entry in [`ApkConfig::reverse_port_forward`]. Specs are parsed
+    /// into [`Forward`]s before invoking `adb`.
     pub fn reverse_port_forwarding(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
-        for (from, to) in &self.reverse_port_forward {
+        for (from, to) in parse_forwards(&self.reverse_port_forward)? {
             println!("Reverse port forwarding from {} to {}", from, to);
             let mut adb = self.ndk.adb(device_serial)?;
 
-            adb.arg("reverse").arg(from).arg(to);
+            adb.arg("reverse").arg(from.to_string()).arg(to.to_string());
 
-            if !adb.status()?.success() {
-                return Err(NdkError::CmdFailed(adb));
+            if !self.dry_run.skip(&adb)
+                && !run_with_timeout(&mut adb, self.timeout)?.status.success()
+            {
+                return Err(NdkError::CmdFailed(Box::new(adb)));
             }
         }
 
         Ok(())
     }
 
+    /// Forwards connections to the `local` host socket to the `remote` socket on the device with
+    /// `adb forward`, for every entry in [`ApkConfig::port_forward`]. Specs are parsed into
+    /// [`Forward`]s before invoking `adb`.
+    pub fn port_forwarding(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+        for (local, remote) in parse_forwards(&self.port_forward)? {
+            println!("Port forwarding from {} to {}", local, remote);
+            let mut adb = self.ndk.adb(device_serial)?;
+
+            adb.arg("forward")
+                .arg(local.to_string())
+                .arg(remote.to_string());
+
+            if !self.dry_run.skip(&adb)
+                && !run_with_timeout(&mut adb, self.timeout)?.status.success()
+            {
+                return Err(NdkError::CmdFailed(Box::new(adb)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the path of the signed APK
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Retrieves the path of the v4 signature, if the APK was signed with
+    /// [`SigningScheme::v4`]
+    pub fn idsig(&self) -> Option<&Path> {
+        self.idsig.as_deref()
+    }
+
+    /// Installs the APK, replacing an existing installation.
+    ///
+    /// An APK with a v4 signature is installed with `adb install --incremental`, falling back
+    /// to a regular install when the device does not support incremental installs. Use
+    /// [`Ndk::wait_for_device`] first when the device may still be booting.
     pub fn install(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
-        let mut adb = self.ndk.adb(device_serial)?;
+        self.install_with(&InstallOptions::default(), device_serial)
+    }
+
+    /// Same as [`Apk::install`], with additional `adb install` flags.
+    ///
+    /// [`InstallOptions::grant_permissions`] is dropped with a warning when the manifest
+    /// `min_sdk_version` is below 23, as runtime permissions (and `-g`) don't exist before
+    /// API level 23.
+    pub fn install_with(
+        &self,
+        options: &InstallOptions,
+        device_serial: Option<&str>,
+    ) -> Result<(), NdkError> {
+        let mut options = options.clone();
+        if options.grant_permissions && self.min_sdk_version.map_or(false, |sdk| sdk < 23) {
+            eprintln!(
+                "Warning: not granting runtime permissions, which require API level 23 but \
+                `min_sdk_version` is {}",
+                self.min_sdk_version.unwrap()
+            );
+            options.grant_permissions = false;
+        }
+        if let Some(user) = options.user {
+            self.ndk.require_user(device_serial, user)?;
+        }
 
-        adb.arg("install").arg("-r").arg(&self.path);
-        if !adb.status()?.success() {
-            return Err(NdkError::CmdFailed(adb));
+        self.install_package(&options, device_serial)?;
+        if options.clear_data {
+            self.clear_data_of_user(options.user, device_serial)?;
         }
         Ok(())
     }
 
+    fn install_package(
+        &self,
+        options: &InstallOptions,
+        device_serial: Option<&str>,
+    ) -> Result<(), NdkError> {
+        if self.idsig.as_deref().map_or(false, Path::exists) {
+            let mut adb = self.ndk.adb(device_serial)?;
+            // `adb` picks up the `.idsig` next to the APK
+            install_args(&mut adb, options, true, &self.path);
+            if self.dry_run.skip(&adb) || run_with_timeout(&mut adb, self.timeout)?.status.success()
+            {
+                return Ok(());
+            }
+            eprintln!("Warning: incremental install failed, falling back to a regular install");
+        }
+
+        let mut adb = self.ndk.adb(device_serial)?;
+        install_args(&mut adb, options, false, &self.path);
+        if !self.dry_run.skip(&adb) && !run_with_timeout(&mut adb, self.timeout)?.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        Ok(())
+    }
+
+    /// Deletes all data of the app with `pm clear`, returning `false` when it isn't installed
+    /// (yet).
+    pub fn clear_data(&self, device_serial: Option<&str>) -> Result<bool, NdkError> {
+        self.clear_data_of_user(None, device_serial)
+    }
+
+    fn clear_data_of_user(
+        &self,
+        user: Option<u32>,
+        device_serial: Option<&str>,
+    ) -> Result<bool, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        clear_data_args(&mut adb, &self.package_name, user);
+        if self.dry_run.skip(&adb) {
+            return Ok(true);
+        }
+        adb.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = run_with_timeout(&mut adb, self.timeout)?;
+        // Depending on the release, `pm` reports a missing package on stdout or stderr
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        parse_pm_clear(&text).ok_or(NdkError::CmdFailed(Box::new(adb)))
+    }
+
+    /// Same as [`Apk::install`], without blocking the calling thread.
+    pub fn install_async(&self, device_serial: Option<&str>) -> BlockingTask<Result<(), NdkError>> {
+        let apk = self.clone();
+        let device_serial = device_serial.map(str::to_owned);
+        BlockingTask::spawn(move || apk.install(device_serial.as_deref()))
+    }
+
+    /// Launches the activity declared in the manifest.
     pub fn start(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+        self.start_activity(&self.activity_name, device_serial)
+    }
+
+    /// Launches `activity` of this package, which is either fully-qualified or starts with `.` to
+    /// be relative to the manifest package.
+    pub fn start_activity(
+        &self,
+        activity: &str,
+        device_serial: Option<&str>,
+    ) -> Result<(), NdkError> {
+        self.start_component(
+            &self.component(activity),
+            &StartOptions::main(),
+            device_serial,
+        )
+    }
+
+    /// Launches the activity declared in the manifest with a custom intent.
+    pub fn start_with(
+        &self,
+        options: &StartOptions,
+        device_serial: Option<&str>,
+    ) -> Result<(), NdkError> {
+        self.start_component(&self.component(&self.activity_name), options, device_serial)
+    }
+
+    fn start_component(
+        &self,
+        component: &str,
+        options: &StartOptions,
+        device_serial: Option<&str>,
+    ) -> Result<(), NdkError> {
+        if let Some(user) = options.user {
+            self.ndk.require_user(device_serial, user)?;
+        }
         let mut adb = self.ndk.adb(device_serial)?;
-        adb.arg("shell")
-            .arg("am")
-            .arg("start")
-            .arg("-a")
-            .arg("android.intent.action.MAIN")
-            .arg("-n")
-            .arg(format!("{}/android.app.NativeActivity", self.package_name));
+        am_start_args(&mut adb, component, options, false);
 
-        if !adb.status()?.success() {
-            return Err(NdkError::CmdFailed(adb));
+        if !self.dry_run.skip(&adb) && !run_with_timeout(&mut adb, self.timeout)?.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
         }
 
         Ok(())
     }
 
-    pub fn uidof(&self, device_serial: Option<&str>) -> Result<u32, NdkError> {
+    /// Same as [`Apk::start`], but waits for the launch to complete with `am start -W` and
+    /// returns the pid of the app. If the app is already running, its task is brought to the
+    /// front and the pid of the existing process is returned.
+    pub fn start_and_wait(&self, device_serial: Option<&str>) -> Result<u32, NdkError> {
         let mut adb = self.ndk.adb(device_serial)?;
-        adb.arg("shell")
-            .arg("pm")
-            .arg("list")
-            .arg("package")
-            .arg("-U")
-            .arg(&self.package_name);
+        am_start_args(
+            &mut adb,
+            &self.component(&self.activity_name),
+            &StartOptions::main(),
+            true,
+        );
+        if self.dry_run.skip(&adb) {
+            return Ok(0);
+        }
+        adb.stdout(Stdio::piped());
+        let output = run_with_timeout(&mut adb, self.timeout)?;
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        check_am_start_wait(&String::from_utf8_lossy(&output.stdout))
+            .map_err(NdkError::ActivityStartFailed)?;
+
+        self.pidof(device_serial)
+    }
+
+    /// Same as [`Apk::start`], without blocking the calling thread.
+    pub fn start_async(&self, device_serial: Option<&str>) -> BlockingTask<Result<(), NdkError>> {
+        let apk = self.clone();
+        let device_serial = device_serial.map(str::to_owned);
+        BlockingTask::spawn(move || apk.start(device_serial.as_deref()))
+    }
+
+    /// The `{package}/{activity}` component name of `activity`, see [`Apk::start_activity`]
+    fn component(&self, activity: &str) -> String {
+        format!(
+            "{}/{}",
+            self.package_name,
+            qualify_activity(&self.manifest_package, activity)
+        )
+    }
+
+    /// Polls `dumpsys activity activities` until `activity` (fully-qualified, or prefixed with
+    /// `.` to be relative to the package) of this package is the resumed foreground activity,
+    /// or `timeout` elapses. A `dumpsys` that hangs is killed once `timeout` elapses as well.
+    pub fn wait_for_activity(
+        &self,
+        activity: &str,
+        timeout: Duration,
+        device_serial: Option<&str>,
+    ) -> Result<(), NdkError> {
+        let expected = self.component(activity);
+        let start = Instant::now();
+        let mut foreground = None;
+        loop {
+            let mut adb = self.ndk.adb(device_serial)?;
+            adb.arg("shell")
+                .arg("dumpsys")
+                .arg("activity")
+                .arg("activities")
+                .stdout(Stdio::piped());
+            let output =
+                match run_with_timeout(&mut adb, Some(timeout.saturating_sub(start.elapsed()))) {
+                    Ok(output) => output,
+                    Err(NdkError::Timeout { .. }) => {
+                        return Err(NdkError::ActivityNotResumed {
+                            activity: expected,
+                            foreground,
+                        })
+                    }
+                    Err(e) => return Err(e),
+                };
+            if !output.status.success() {
+                return Err(NdkError::CmdFailed(Box::new(adb)));
+            }
+
+            let output = String::from_utf8_lossy(&output.stdout);
+            foreground = resumed_activity(&output).map(expand_component);
+            if foreground.as_deref() == Some(&expected) {
+                return Ok(());
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(NdkError::ActivityNotResumed {
+                    activity: expected,
+                    foreground,
+                });
+            }
+            std::thread::sleep(Duration::from_millis(250));
+        }
+    }
+
+    /// Verifies the signature of the APK with `apksigner verify`, returning the schemes that
+    /// verified and the signer certificates. An APK that does not verify results in
+    /// [`NdkError::VerificationFailed`] with the reason reported by `apksigner`.
+    pub fn verify(&self) -> Result<Verification, NdkError> {
+        let mut apksigner = self.ndk.build_tool(bat!("apksigner"))?;
+        apksigner
+            .arg("verify")
+            .arg("-v")
+            .arg("--print-certs")
+            .arg(&self.path);
+        let output = apksigner.output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stdout
+                .lines()
+                .chain(stderr.lines())
+                .filter(|line| line.starts_with("ERROR"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Err(NdkError::VerificationFailed(if reason.is_empty() {
+                stderr.trim().to_owned()
+            } else {
+                reason
+            }));
+        }
+
+        Ok(parse_verification(&stdout))
+    }
+
+    /// Verifies the APK signature and ensures that it was signed by a certificate with the
+    /// `expected_sha256` fingerprint. Both `AB:CD:...` (as printed by `keytool`) and plain hex
+    /// (as printed by `apksigner`) notations are accepted, in either case.
+    pub fn verify_signed_by(&self, expected_sha256: &str) -> Result<(), NdkError> {
+        let expected = normalize_fingerprint(expected_sha256);
+        let signers = self.verify()?.signers;
+        if signers.contains(&expected) {
+            Ok(())
+        } else {
+            Err(NdkError::UnexpectedSigner { expected, signers })
+        }
+    }
+
+    /// Uninstalls the app, retaining its data and cache directories when `keep_data` is set.
+    ///
+    /// Uninstalling an app that is not installed is not an error, but returns
+    /// [`UninstallStatus::NotInstalled`], so that this can be called defensively before
+    /// installing.
+    pub fn uninstall(
+        &self,
+        device_serial: Option<&str>,
+        keep_data: bool,
+    ) -> Result<UninstallStatus, NdkError> {
+        self.uninstall_for_user(device_serial, keep_data, None)
+    }
+
+    /// Same as [`Apk::uninstall`], but only uninstalls the app for `user` when set, see
+    /// [`Ndk::uninstall_for_user`].
+    pub fn uninstall_for_user(
+        &self,
+        device_serial: Option<&str>,
+        keep_data: bool,
+        user: Option<u32>,
+    ) -> Result<UninstallStatus, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        uninstall_args(&mut adb, &self.package_name, keep_data, user);
+        if self.dry_run.skip(&adb) {
+            return Ok(UninstallStatus::Uninstalled);
+        }
+        let installed = self
+            .ndk
+            .list_packages(device_serial, Some(&self.package_name), false)?;
+        if !installed.contains(&self.package_name) {
+            return Ok(UninstallStatus::NotInstalled);
+        }
+        self.ndk
+            .uninstall_for_user(device_serial, &self.package_name, keep_data, user)?;
+        Ok(UninstallStatus::Uninstalled)
+    }
+
+    /// Clears the log buffer and spawns `adb logcat` filtered to the messages of this app, with
+    /// its standard output piped so that the caller can stream the log.
+    ///
+    /// Messages are filtered by the uid of the app (`--uid`), falling back to the pid of its
+    /// running process (`--pid`) on devices whose `logcat` does not support `--uid`, in which case
+    /// the app must have been started already.
+    pub fn logcat(&self, device_serial: Option<&str>) -> Result<Child, NdkError> {
+        let mut clear = self.ndk.adb(device_serial)?;
+        clear.arg("logcat").arg("-c");
+        if !clear.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(clear)));
+        }
+
+        let uid = self.uidof(device_serial)?;
+        let mut probe = self.ndk.adb(device_serial)?;
+        probe
+            .arg("logcat")
+            .arg("-d")
+            .arg("-t")
+            .arg("1")
+            .arg(format!("--uid={}", uid))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let filter = if probe.status()?.success() {
+            format!("--uid={}", uid)
+        } else {
+            format!("--pid={}", self.pidof(device_serial)?)
+        };
+
+        let mut logcat = self.ndk.adb(device_serial)?;
+        logcat.arg("logcat").arg(filter).stdout(Stdio::piped());
+        Ok(logcat.spawn()?)
+    }
+
+    /// Retrieves the pid of the running process of this app through `pidof`.
+    pub fn pidof(&self, device_serial: Option<&str>) -> Result<u32, NdkError> {
+        self.pids(device_serial)?
+            .first()
+            .copied()
+            .ok_or_else(|| NdkError::AppNotRunning(self.package_name.clone()))
+    }
+
+    /// All pids that `pidof` reports for the package, empty when it is not running
+    fn pids(&self, device_serial: Option<&str>) -> Result<Vec<u32>, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("pidof").arg(&self.package_name);
         let output = adb.output()?;
+        // `pidof` exits with an error when no process matches
+        Ok(parse_pids(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Polls `pidof` every `poll_interval` until the process of this app that is running when
+    /// this is called has exited, returning `true`, or `timeout` elapses, returning `false`.
+    /// A new process of the app that is started in the meantime does not count as the original
+    /// process still running.
+    pub fn wait_for_exit(
+        &self,
+        poll_interval: Duration,
+        timeout: Option<Duration>,
+        device_serial: Option<&str>,
+    ) -> Result<bool, NdkError> {
+        let pid = match self.pids(device_serial)?.first() {
+            Some(&pid) => pid,
+            None => return Ok(true),
+        };
+        let start = Instant::now();
+        loop {
+            if !self.pids(device_serial)?.contains(&pid) {
+                return Ok(true);
+            }
+            if timeout.map_or(false, |timeout| start.elapsed() >= timeout) {
+                return Ok(false);
+            }
+            std::thread::sleep(poll_interval);
+        }
+    }
 
+    /// Copies `remote` from the device to `local` with `adb pull`. With `run_as`, the file is
+    /// read as the app through `run-as <package> cat` instead, which reaches the private data
+    /// directory of debuggable apps on non-rooted devices; relative paths are then resolved
+    /// against that directory. Fails with [`NdkError::RemoteFileNotFound`] when `remote` does not
+    /// exist.
+    pub fn pull(
+        &self,
+        remote: &str,
+        local: &Path,
+        run_as: bool,
+        device_serial: Option<&str>,
+    ) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        pull_args(&mut adb, &self.package_name, remote, local, run_as);
+        adb.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = run_with_timeout(&mut adb, self.timeout)?;
+        if !output.status.success() {
+            if is_missing_remote(&String::from_utf8_lossy(&output.stderr)) {
+                return Err(NdkError::RemoteFileNotFound(remote.to_owned()));
+            }
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        if run_as {
+            fs::write(local, &output.stdout)
+                .map_err(|e| NdkError::IoPathError(local.to_owned(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Copies `local` to `remote` on the device with `adb push`, e.g. to stage input files in
+    /// the external storage of the app.
+    pub fn push(
+        &self,
+        local: &Path,
+        remote: &str,
+        device_serial: Option<&str>,
+    ) -> Result<(), NdkError> {
+        if !local.exists() {
+            return Err(NdkError::PathNotFound(local.to_owned()));
+        }
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("push").arg(local).arg(remote);
+        if !self.dry_run.skip(&adb) && !run_with_timeout(&mut adb, self.timeout)?.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        Ok(())
+    }
+
+    /// Runs the instrumentation `runner` of this package, such as
+    /// `androidx.test.runner.AndroidJUnitRunner`, with `am instrument -w -r`, passing every
+    /// `(key, value)` in `args` with `-e`. Waits for the instrumentation to finish and parses
+    /// the reported status of every test; check [`InstrumentationResult::passed`] for the
+    /// outcome.
+    pub fn instrument(
+        &self,
+        runner: &str,
+        args: &[(String, String)],
+        device_serial: Option<&str>,
+    ) -> Result<InstrumentationResult, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        instrument_args(&mut adb, &format!("{}/{}", self.package_name, runner), args);
+        if self.dry_run.skip(&adb) {
+            return Ok(InstrumentationResult::default());
+        }
+        adb.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = run_with_timeout(&mut adb, self.timeout)?;
+        // `am instrument` exits successfully even when tests fail
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        Ok(parse_instrumentation(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    /// Takes a screenshot with `screencap -p` and writes the PNG to `local`. The image is read
+    /// through `adb exec-out`, as `adb shell` may translate line endings and corrupt binary data.
+    /// Fails with [`NdkError::ScreencapFailed`] when the device returns something other than a
+    /// PNG, such as an error message.
+    pub fn screencap(&self, local: &Path, device_serial: Option<&str>) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("exec-out")
+            .arg("screencap")
+            .arg("-p")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = run_with_timeout(&mut adb, self.timeout)?;
         if !output.status.success() {
-            return Err(NdkError::CmdFailed(adb));
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        if !is_png_data(&output.stdout) {
+            let mut message = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            if message.is_empty() {
+                message = String::from_utf8_lossy(&output.stdout[..output.stdout.len().min(200)])
+                    .trim()
+                    .to_owned();
+            }
+            return Err(NdkError::ScreencapFailed(message));
         }
+        fs::write(local, &output.stdout).map_err(|e| NdkError::IoPathError(local.to_owned(), e))
+    }
 
-        let output = std::str::from_utf8(&output.stdout).unwrap();
-        let (_package, uid) = output
-            .lines()
-            .filter_map(|line| line.split_once(' '))
-            // `pm list package` uses the id as a substring filter; make sure
-            // we select the right package in case it returns multiple matches:
-            .find(|(package, _uid)| package.strip_prefix("package:") == Some(&self.package_name))
-            .ok_or(NdkError::PackageNotInOutput {
+    pub fn uidof(&self, device_serial: Option<&str>) -> Result<u32, NdkError> {
+        let output = self.pm_list_package(true, device_serial)?;
+        let uid = find_package(&output, &self.package_name).ok_or_else(|| {
+            NdkError::PackageNotInOutput {
                 package: self.package_name.clone(),
-                output: output.to_owned(),
-            })?;
+                output: output.clone(),
+            }
+        })?;
         let uid = uid
             .strip_prefix("uid:")
-            .ok_or(NdkError::UidNotInOutput(output.to_owned()))?;
+            .ok_or_else(|| NdkError::UidNotInOutput(output.clone()))?;
         uid.parse()
             .map_err(|e| NdkError::NotAUid(e, uid.to_owned()))
     }
+
+    /// Whether this exact package is installed on the device.
+    pub fn is_installed(&self, device_serial: Option<&str>) -> Result<bool, NdkError> {
+        let output = self.pm_list_package(false, device_serial)?;
+        Ok(find_package(&output, &self.package_name).is_some())
+    }
+
+    /// Runs `pm list package` on the package name, optionally with the uids (`-U`).
+    fn pm_list_package(&self, uid: bool, device_serial: Option<&str>) -> Result<String, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("pm").arg("list").arg("package");
+        if uid {
+            adb.arg("-U");
+        }
+        adb.arg(&self.package_name)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = run_with_timeout(&mut adb, self.timeout)?;
+
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Appends the arguments of [`Apk::pull`] to `adb`. `exec-out` passes the bytes written by
+/// `run-as ... cat` through unmodified, unlike `shell` which may translate line endings.
+fn pull_args(adb: &mut Command, package: &str, remote: &str, local: &Path, run_as: bool) {
+    if run_as {
+        adb.arg("exec-out")
+            .arg("run-as")
+            .arg(package)
+            .arg("cat")
+            .arg(remote);
+    } else {
+        adb.arg("pull").arg(remote).arg(local);
+    }
+}
+
+/// Whether the stderr of a failed [`Apk::pull`] reports a missing file, as either
+/// `adb: error: failed to stat remote object '...': No such file or directory`,
+/// `remote object '...' does not exist` or `cat: ...: No such file or directory`.
+fn is_missing_remote(stderr: &str) -> bool {
+    stderr.contains("No such file or directory") || stderr.contains("does not exist")
+}
+
+/// Finds the `package:<package>[ <details>]` line of `package` in the output of
+/// `pm list package`, returning the details such as `uid:<uid>`. `pm` filters on substrings,
+/// so the output may list other packages containing the name as well.
+fn find_package<'a>(output: &'a str, package: &str) -> Option<&'a str> {
+    output.lines().find_map(|line| {
+        let line = line.trim().strip_prefix("package:")?;
+        let (name, details) = line.split_once(' ').unwrap_or((line, ""));
+        (name == package).then(|| details)
+    })
+}
+
+/// Appends the arguments of [`Apk::instrument`] to `adb`. `-r` prints the raw
+/// `INSTRUMENTATION_STATUS` stream that [`parse_instrumentation`] reads.
+fn instrument_args(adb: &mut Command, component: &str, args: &[(String, String)]) {
+    adb.arg("shell")
+        .arg("am")
+        .arg("instrument")
+        .arg("-w")
+        .arg("-r");
+    for (key, value) in args {
+        adb.arg("-e").arg(key).arg(value);
+    }
+    adb.arg(component);
+}
+
+/// Outcome of a single test, reported as `INSTRUMENTATION_STATUS_CODE`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestStatus {
+    /// `0`
+    Passed,
+    /// `-2`, an assertion failed
+    Failed,
+    /// `-1`, the test threw an unexpected exception
+    Error,
+    /// `-3`
+    Ignored,
+    /// `-4`, an assumption of the test did not hold
+    AssumptionFailure,
+}
+
+/// A test reported by [`Apk::instrument`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstrumentationTest {
+    pub class: String,
+    pub name: String,
+    pub status: TestStatus,
+    /// Stack trace of a failed test
+    pub stack: Option<String>,
+}
+
+/// Result of [`Apk::instrument`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstrumentationResult {
+    pub tests: Vec<InstrumentationTest>,
+    /// `INSTRUMENTATION_CODE`, which is `-1` (`Activity.RESULT_OK`) when the instrumentation
+    /// finished normally. `None` when it did not finish, e.g. because the process crashed.
+    pub code: Option<i32>,
+    /// Why the instrumentation could not run or was aborted, from `INSTRUMENTATION_FAILED` or
+    /// the `shortMsg` of `INSTRUMENTATION_RESULT`
+    pub failure: Option<String>,
+}
+
+impl InstrumentationResult {
+    /// Whether the instrumentation finished normally without failed tests
+    pub fn passed(&self) -> bool {
+        self.code == Some(-1)
+            && self.failure.is_none()
+            && self
+                .tests
+                .iter()
+                .all(|test| !matches!(test.status, TestStatus::Failed | TestStatus::Error))
+    }
+
+    /// The tests that failed or threw an exception
+    pub fn failed(&self) -> impl Iterator<Item = &InstrumentationTest> {
+        self.tests
+            .iter()
+            .filter(|test| matches!(test.status, TestStatus::Failed | TestStatus::Error))
+    }
+}
+
+/// Parses the output of `am instrument -r`. Each test is reported as a block of
+/// `INSTRUMENTATION_STATUS: key=value` lines when it starts (`INSTRUMENTATION_STATUS_CODE: 1`)
+/// and another when it finishes; values such as `stack` may span multiple lines.
+fn parse_instrumentation(output: &str) -> InstrumentationResult {
+    let mut result = InstrumentationResult::default();
+    let mut status = HashMap::<String, String>::new();
+    // The key of the last value, that following lines without a prefix are appended to
+    let mut last_key: Option<String> = None;
+    for line in output.lines() {
+        let line = line.trim_end_matches('\r');
+        if let Some(pair) = line
+            .strip_prefix("INSTRUMENTATION_STATUS: ")
+            .or_else(|| line.strip_prefix("INSTRUMENTATION_RESULT: "))
+        {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if line.starts_with("INSTRUMENTATION_RESULT: ") && key == "shortMsg" {
+                result.failure = Some(value.to_owned());
+            }
+            status.insert(key.to_owned(), value.to_owned());
+            last_key = Some(key.to_owned());
+        } else if let Some(code) = line.strip_prefix("INSTRUMENTATION_STATUS_CODE: ") {
+            let status = std::mem::take(&mut status);
+            last_key = None;
+            let status_code = match code.trim().parse::<i32>() {
+                Ok(0) => TestStatus::Passed,
+                Ok(-1) => TestStatus::Error,
+                Ok(-2) => TestStatus::Failed,
+                Ok(-3) => TestStatus::Ignored,
+                Ok(-4) => TestStatus::AssumptionFailure,
+                // `1` starts a test, other codes are runner specific
+                _ => continue,
+            };
+            result.tests.push(InstrumentationTest {
+                class: status.get("class").cloned().unwrap_or_default(),
+                name: status.get("test").cloned().unwrap_or_default(),
+                status: status_code,
+                stack: status.get("stack").filter(|s| !s.is_empty()).cloned(),
+            });
+        } else if let Some(code) = line.strip_prefix("INSTRUMENTATION_CODE: ") {
+            result.code = code.trim().parse().ok();
+            last_key = None;
+        } else if let Some(failure) = line.strip_prefix("INSTRUMENTATION_FAILED: ") {
+            result.failure = Some(failure.to_owned());
+            last_key = None;
+        } else if let Some(key) = &last_key {
+            let value = status.get_mut(key).unwrap();
+            value.push('\n');
+            value.push_str(line);
+        }
+    }
+    result
+}
+
+/// Parses a `versionCode`, which must be a positive integer.
+fn parse_version_code(version_code: &str) -> Result<u32, NdkError> {
+    match version_code.trim().parse() {
+        Ok(0) | Err(_) => Err(NdkError::InvalidVersionCode(version_code.to_owned())),
+        Ok(version_code) => Ok(version_code),
+    }
+}
+
+/// Finds the `package/activity` component of the resumed activity in the output of
+/// `dumpsys activity activities`. Older releases print `mResumedActivity: ActivityRecord{...}`,
+/// newer ones `ResumedActivity: ActivityRecord{...}` or `topResumedActivity=ActivityRecord{...}`.
+fn resumed_activity(dumpsys: &str) -> Option<&str> {
+    dumpsys
+        .lines()
+        .map(str::trim)
+        .find(|line| {
+            line.starts_with("mResumedActivity:")
+                || line.starts_with("ResumedActivity:")
+                || line.starts_with("topResumedActivity=")
+        })
+        .and_then(|line| {
+            // ActivityRecord{<hash> u<user> <component> t<task>}
+            line.split_whitespace()
+                .find(|word| word.contains('/'))
+                .map(|component| component.trim_end_matches('}'))
+        })
+}
+
+/// Expands an activity name starting with `.` against the manifest package, the same way
+/// `android:name` is resolved in the manifest.
+fn qualify_activity(manifest_package: &str, activity: &str) -> String {
+    if activity.starts_with('.') {
+        format!("{}{}", manifest_package, activity)
+    } else {
+        activity.to_owned()
+    }
+}
+
+/// Appends the `adb` arguments for launching `component` with the intent described by
+/// `options`.
+fn am_start_args(adb: &mut Command, component: &str, options: &StartOptions, wait: bool) {
+    adb.arg("shell").arg("am").arg("start");
+    if wait {
+        adb.arg("-W");
+    }
+    if let Some(user) = options.user {
+        adb.arg("--user").arg(user.to_string());
+    }
+    if let Some(action) = &options.action {
+        adb.arg("-a").arg(shell_quote(action));
+    }
+    if let Some(category) = &options.category {
+        adb.arg("-c").arg(shell_quote(category));
+    }
+    for extra in &options.extras {
+        let (flag, key, value) = match extra {
+            IntentExtra::String(key, value) => ("--es", key, shell_quote(value)),
+            IntentExtra::Bool(key, value) => ("--ez", key, value.to_string()),
+            IntentExtra::Int(key, value) => ("--ei", key, value.to_string()),
+            IntentExtra::Long(key, value) => ("--el", key, value.to_string()),
+        };
+        adb.arg(flag).arg(shell_quote(key)).arg(value);
+    }
+    adb.arg("-n").arg(component);
+}
+
+/// A socket spec understood by `adb forward` and `adb reverse`, such as `tcp:8080` or
+/// `localabstract:name`. Parsing validates the spec, and [`Display`](std::fmt::Display)
+/// produces its canonical form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Forward {
+    Tcp(u16),
+    LocalAbstract(String),
+    LocalReserved(String),
+    LocalFilesystem(String),
+    Dev(String),
+    /// Process id of the debuggable app, only valid on the device side
+    Jdwp(u32),
+    Vsock {
+        cid: u32,
+        port: u32,
+    },
+    AcceptFd(u32),
+}
+
+impl std::str::FromStr for Forward {
+    type Err = NdkError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| NdkError::InvalidForwardSpec {
+            spec: spec.to_owned(),
+            reason,
+        };
+        let number = |value: &str| {
+            value
+                .parse::<u32>()
+                .map_err(|_| invalid(format!("`{}` is not a number", value)))
+        };
+        let (kind, value) = spec
+            .split_once(':')
+            .ok_or_else(|| invalid("expected `<kind>:<value>`, e.g. `tcp:8080`".to_string()))?;
+        if value.is_empty() {
+            return Err(invalid(format!("missing value after `{}:`", kind)));
+        }
+        Ok(match kind {
+            "tcp" => Self::Tcp(
+                value
+                    .parse()
+                    .map_err(|_| invalid(format!("`{}` is not a port number", value)))?,
+            ),
+            "localabstract" => Self::LocalAbstract(value.to_owned()),
+            "localreserved" => Self::LocalReserved(value.to_owned()),
+            "localfilesystem" => Self::LocalFilesystem(value.to_owned()),
+            "dev" => Self::Dev(value.to_owned()),
+            "jdwp" => Self::Jdwp(number(value)?),
+            "vsock" => {
+                let (cid, port) = value
+                    .split_once(':')
+                    .ok_or_else(|| invalid("expected `vsock:<cid>:<port>`".to_string()))?;
+                Self::Vsock {
+                    cid: number(cid)?,
+                    port: number(port)?,
+                }
+            }
+            "acceptfd" => Self::AcceptFd(number(value)?),
+            _ => return Err(invalid(format!("unknown socket kind `{}`", kind))),
+        })
+    }
+}
+
+impl std::fmt::Display for Forward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(port) => write!(f, "tcp:{}", port),
+            Self::LocalAbstract(name) => write!(f, "localabstract:{}", name),
+            Self::LocalReserved(name) => write!(f, "localreserved:{}", name),
+            Self::LocalFilesystem(path) => write!(f, "localfilesystem:{}", path),
+            Self::Dev(path) => write!(f, "dev:{}", path),
+            Self::Jdwp(pid) => write!(f, "jdwp:{}", pid),
+            Self::Vsock { cid, port } => write!(f, "vsock:{}:{}", cid, port),
+            Self::AcceptFd(fd) => write!(f, "acceptfd:{}", fd),
+        }
+    }
+}
+
+/// Parses every `(from, to)` pair into [`Forward`] specs, so that a malformed entry is reported
+/// before any of them is passed to `adb`.
+fn parse_forwards(forwards: &HashMap<String, String>) -> Result<Vec<(Forward, Forward)>, NdkError> {
+    forwards
+        .iter()
+        .map(|(from, to)| Ok((from.parse()?, to.parse()?)))
+        .collect()
+}
+
+fn parse_pids(output: &str) -> Vec<u32> {
+    output
+        .split_whitespace()
+        .filter_map(|pid| pid.parse().ok())
+        .collect()
+}
+
+/// Checks that `am start -W` launched the activity or brought its existing task to the front,
+/// returning the error reported by `am` (e.g. for a missing activity) otherwise.
+fn check_am_start_wait(output: &str) -> Result<(), String> {
+    if let Some(error) = output.lines().find_map(|line| line.strip_prefix("Error: ")) {
+        return Err(error.trim().to_owned());
+    }
+    match output
+        .lines()
+        .find_map(|line| line.strip_prefix("Status: "))
+    {
+        Some(status) if status.trim() == "ok" => Ok(()),
+        Some(status) => Err(format!("launch status `{}`", status.trim())),
+        None => Err(output.trim().to_owned()),
+    }
+}
+
+/// Quotes `arg` for the device shell that `adb shell` joins its arguments for, unless it only
+/// consists of characters without special meaning.
+fn shell_quote(arg: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "._-/:@%+=,".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Expands the `package/.Activity` shorthand used by `dumpsys` into `package/package.Activity`.
+fn expand_component(component: &str) -> String {
+    match component.split_once('/') {
+        Some((package, activity)) if activity.starts_with('.') => {
+            format!("{}/{}{}", package, package, activity)
+        }
+        _ => component.to_owned(),
+    }
+}
+
+/// Lowercase hex without separators
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(char::is_ascii_hexdigit)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Parses the output of `apksigner verify -v --print-certs`.
+fn parse_verification(output: &str) -> Verification {
+    let verified = |scheme: &str| {
+        output.lines().any(|line| {
+            line.strip_prefix(&format!("Verified using {} scheme", scheme))
+                .map_or(false, |rest| rest.trim_end().ends_with("true"))
+        })
+    };
+    Verification {
+        schemes: SigningScheme {
+            v1: verified("v1"),
+            v2: verified("v2"),
+            v3: verified("v3"),
+            v4: verified("v4"),
+        },
+        signers: parse_signer_sha256_digests(output),
+    }
+}
+
+/// Collects `Signer #N certificate SHA-256 digest: <hex>` lines from `apksigner verify --print-certs`
+fn parse_signer_sha256_digests(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("certificate SHA-256 digest:"))
+        .map(|(_, digest)| normalize_fingerprint(digest))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jarsigner_args() {
+        let args = |key: &Key| {
+            let mut cmd = Command::new("jarsigner");
+            let stdin = jarsigner_sign_args(
+                &mut cmd,
+                key,
+                "release",
+                Path::new("app.apk"),
+                Path::new("app-signed.apk"),
+            );
+            let args = cmd
+                .get_args()
+                .map(|arg| arg.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            let stdin = stdin.into_iter().map(str::to_owned).collect::<Vec<_>>();
+            (args, stdin)
+        };
+
+        let key = Key {
+            path: PathBuf::from("release.keystore"),
+            password: "secret".into(),
+            alias: None,
+            key_password: Some(KeyPassword::Env("KEY_PASSWORD".to_string())),
+        };
+        let (cmd, stdin) = args(&key);
+        assert_eq!(
+            cmd,
+            [
+                "-keystore",
+                "release.keystore",
+                "-storepass",
+                "secret",
+                "-keypass:env",
+                "KEY_PASSWORD",
+                "-signedjar",
+                "app-signed.apk",
+                "app.apk",
+                "release"
+            ]
+        );
+        assert!(stdin.is_empty());
+
+        let key = Key {
+            password: KeyPassword::Stdin("secret".to_string()),
+            key_password: None,
+            ..key
+        };
+        let (cmd, stdin) = args(&key);
+        assert_eq!(
+            cmd,
+            [
+                "-keystore",
+                "release.keystore",
+                "-signedjar",
+                "app-signed.apk",
+                "app.apk",
+                "release"
+            ]
+        );
+        assert_eq!(stdin, ["secret"]);
+    }
+
+    #[test]
+    fn lineage_args() {
+        let lineage = Lineage {
+            file: PathBuf::from("lineage.bin"),
+            old_key: Key {
+                path: PathBuf::from("old.keystore"),
+                password: "old-secret".into(),
+                alias: Some("old".to_string()),
+                key_password: None,
+            },
+            new_key: Key {
+                path: PathBuf::from("new.keystore"),
+                password: KeyPassword::Env("NEW_PASSWORD".to_string()),
+                alias: Some("new".to_string()),
+                key_password: Some(KeyPassword::Stdin("new-key-secret".to_string())),
+            },
+        };
+        let scheme = SigningScheme {
+            v1: false,
+            v2: true,
+            v3: true,
+            v4: false,
+        };
+        let mut cmd = Command::new("apksigner");
+        apksigner_lineage_args(&mut cmd, &lineage, scheme, Path::new("app.apk"));
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            [
+                "sign",
+                "--ks",
+                "old.keystore",
+                "--ks-pass",
+                "pass:old-secret",
+                "--ks-key-alias",
+                "old",
+                "--next-signer",
+                "--ks",
+                "new.keystore",
+                "--ks-pass",
+                "env:NEW_PASSWORD",
+                "--ks-key-alias",
+                "new",
+                "--key-pass",
+                "stdin",
+                "--lineage",
+                "lineage.bin",
+                "--v1-signing-enabled",
+                "false",
+                "--v2-signing-enabled",
+                "true",
+                "--v3-signing-enabled",
+                "true",
+                "--v4-signing-enabled",
+                "false",
+                "app.apk"
+            ]
+        );
+    }
+
+    fn sign_args(key: &Key, scheme: SigningScheme) -> Vec<String> {
+        let mut cmd = Command::new("apksigner");
+        apksigner_sign_args(&mut cmd, key, scheme, Path::new("app.apk"));
+        cmd.get_args()
+            .map(|arg| arg.to_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn signing_scheme_args() {
+        let key = Key {
+            path: PathBuf::from("release.keystore"),
+            password: "secret".into(),
+            alias: None,
+            key_password: None,
+        };
+        let common = [
+            "sign",
+            "--ks",
+            "release.keystore",
+            "--ks-pass",
+            "pass:secret",
+        ];
+
+        assert_eq!(
+            sign_args(&key, SigningScheme::for_min_sdk(16)),
+            [
+                &common[..],
+                &[
+                    "--v1-signing-enabled",
+                    "true",
+                    "--v2-signing-enabled",
+                    "true",
+                    "--v3-signing-enabled",
+                    "true",
+                    "--v4-signing-enabled",
+                    "false",
+                    "app.apk"
+                ]
+            ]
+            .concat()
+        );
+
+        let v2_only = SigningScheme {
+            v1: false,
+            v2: true,
+            v3: false,
+            v4: false,
+        };
+        assert_eq!(
+            sign_args(&key, v2_only),
+            [
+                &common[..],
+                &[
+                    "--v1-signing-enabled",
+                    "false",
+                    "--v2-signing-enabled",
+                    "true",
+                    "--v3-signing-enabled",
+                    "false",
+                    "--v4-signing-enabled",
+                    "false",
+                    "app.apk"
+                ]
+            ]
+            .concat()
+        );
+        assert!(!SigningScheme::for_min_sdk(24).v1);
+    }
+
+    #[test]
+    fn signing_key_alias_args() {
+        let mut key = Key {
+            path: PathBuf::from("keys.keystore"),
+            password: "store".into(),
+            alias: None,
+            key_password: None,
+        };
+        let scheme = SigningScheme::for_min_sdk(24);
+
+        let args = sign_args(&key, scheme);
+        assert!(!args.iter().any(|arg| arg == "--ks-key-alias"));
+        assert!(!args.iter().any(|arg| arg == "--key-pass"));
+
+        key.alias = Some("release".to_string());
+        key.key_password = Some("key".into());
+        let args = sign_args(&key, scheme);
+        assert_eq!(
+            args[..9],
+            [
+                "sign",
+                "--ks",
+                "keys.keystore",
+                "--ks-pass",
+                "pass:store",
+                "--ks-key-alias",
+                "release",
+                "--key-pass",
+                "pass:key"
+            ]
+        );
+    }
+
+    #[test]
+    fn signing_password_specifiers() {
+        let key = Key {
+            path: PathBuf::from("keys.keystore"),
+            password: KeyPassword::Env("KS_PASS".to_string()),
+            alias: None,
+            key_password: Some(KeyPassword::File(PathBuf::from("key.pass"))),
+        };
+        let args = sign_args(&key, SigningScheme::for_min_sdk(24));
+        assert_eq!(
+            args[3..7],
+            ["--ks-pass", "env:KS_PASS", "--key-pass", "file:key.pass"]
+        );
+
+        let key = Key {
+            password: KeyPassword::Stdin("secret".to_string()),
+            key_password: None,
+            ..key
+        };
+        let args = sign_args(&key, SigningScheme::for_min_sdk(24));
+        assert_eq!(args[3..5], ["--ks-pass", "stdin"]);
+        assert!(!args.iter().any(|arg| arg.contains("secret")));
+    }
+
+    #[test]
+    fn zipalign_alignment() {
+        let mut cmd = Command::new("zipalign");
+        zipalign_args(
+            &mut cmd,
+            DEFAULT_ALIGNMENT,
+            PageAlignment::Align16K,
+            Path::new("in.apk"),
+            Path::new("out.apk"),
+        )
+        .unwrap();
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            ["-P", "16", "-f", "-v", "4", "in.apk", "out.apk"]
+        );
+
+        let mut cmd = Command::new("zipalign");
+        assert!(matches!(
+            zipalign_args(
+                &mut cmd,
+                6,
+                PageAlignment::Align4K,
+                Path::new("in.apk"),
+                Path::new("out.apk"),
+            ),
+            Err(NdkError::InvalidAlignment(6))
+        ));
+        assert_eq!(cmd.get_args().count(), 0);
+    }
+
+    #[test]
+    fn version_code_from_env() {
+        assert_eq!(parse_version_code("42").unwrap(), 42);
+        assert_eq!(parse_version_code(" 7\n").unwrap(), 7);
+        for invalid in ["0", "-1", "1.0", "", "abc", "99999999999"] {
+            assert!(matches!(
+                parse_version_code(invalid),
+                Err(NdkError::InvalidVersionCode(v)) if v == invalid
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_signer_digests() {
+        let output = "\
+Signer #1 certificate DN: C=US, O=Android, CN=Android Debug
+Signer #1 certificate SHA-256 digest: 3f0a9bc1d2e3f40516273849aabbccddeeff00112233445566778899aabbccdd
+Signer #1 certificate SHA-1 digest: 0123456789abcdef0123456789abcdef01234567
+";
+        let digests = parse_signer_sha256_digests(output);
+        assert_eq!(
+            digests,
+            ["3f0a9bc1d2e3f40516273849aabbccddeeff00112233445566778899aabbccdd"]
+        );
+        assert_eq!(
+            normalize_fingerprint("3F:0A:9B:C1:D2:E3:F4:05:16:27:38:49:AA:BB:CC:DD:EE:FF:00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD"),
+            digests[0]
+        );
+    }
+
+    fn am_start(component: &str, options: &StartOptions) -> Vec<String> {
+        let mut adb = Command::new("adb");
+        am_start_args(&mut adb, component, options, false);
+        adb.get_args()
+            .map(|arg| arg.to_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn am_start_wait_output() {
+        let mut adb = Command::new("adb");
+        am_start_args(
+            &mut adb,
+            "com.example/.Main",
+            &StartOptions::default(),
+            true,
+        );
+        assert_eq!(
+            adb.get_args().collect::<Vec<_>>(),
+            ["shell", "am", "start", "-W", "-n", "com.example/.Main"]
+        );
+
+        let launched = "Starting: Intent { cmp=com.example/.Main }
+Status: ok
+LaunchState: COLD
+Activity: com.example/.Main
+TotalTime: 512
+WaitTime: 520
+Complete
+";
+        assert_eq!(check_am_start_wait(launched), Ok(()));
+        let running = "Starting: Intent { cmp=com.example/.Main }
+Warning: Activity not started, its current task has been brought to the front
+Status: ok
+LaunchState: UNKNOWN (0)
+Activity: com.example/.Main
+WaitTime: 12
+Complete
+";
+        assert_eq!(check_am_start_wait(running), Ok(()));
+        let missing = "Starting: Intent { cmp=com.example/.Missing }
+Error type 3
+Error: Activity class {com.example/com.example.Missing} does not exist.
+";
+        assert_eq!(
+            check_am_start_wait(missing),
+            Err("Activity class {com.example/com.example.Missing} does not exist.".to_string())
+        );
+    }
+
+    #[test]
+    fn forward_specs() {
+        assert_eq!("tcp:8080".parse::<Forward>().unwrap(), Forward::Tcp(8080));
+        assert_eq!(
+            "localabstract:chrome_devtools_remote"
+                .parse::<Forward>()
+                .unwrap(),
+            Forward::LocalAbstract("chrome_devtools_remote".to_string())
+        );
+        assert_eq!(
+            "vsock:3:5000".parse::<Forward>().unwrap(),
+            Forward::Vsock { cid: 3, port: 5000 }
+        );
+        assert_eq!(
+            "tcp:08080".parse::<Forward>().unwrap().to_string(),
+            "tcp:8080"
+        );
+        for spec in [
+            "tcp8080",
+            "tcp:",
+            "tcp:http",
+            "tcp:70000",
+            "udp:53",
+            "jdwp:app",
+            "vsock:3",
+        ] {
+            assert!(
+                matches!(
+                    spec.parse::<Forward>(),
+                    Err(NdkError::InvalidForwardSpec { .. })
+                ),
+                "{}",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn find_listed_package() {
+        let output = "package:com.example.app.debug uid:10124\npackage:com.example.app uid:10123\n";
+        assert_eq!(find_package(output, "com.example.app"), Some("uid:10123"));
+        assert_eq!(find_package(output, "com.example"), None);
+
+        let output = "package:com.example.app.debug\r\npackage:com.example.app\r\n";
+        assert_eq!(find_package(output, "com.example.app"), Some(""));
+        assert_eq!(find_package("", "com.example.app"), None);
+    }
+
+    #[test]
+    fn merge_asset_dirs() {
+        let dir = std::env::temp_dir().join(format!("ndk-build-assets-{}", std::process::id()));
+        let write = |path: PathBuf, contents: &str| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        let shared = dir.join("shared");
+        write(shared.join("config.json"), "shared");
+        write(shared.join("textures").join("a.ktx"), "a");
+        let platform = dir.join("platform");
+        write(platform.join("config.json"), "platform");
+        write(platform.join("textures").join("b.ktx"), "b");
+
+        let merged = dir.join("merged");
+        merge_dirs(&[shared.clone(), platform.clone()], &merged).unwrap();
+        let read = |path: &str| fs::read_to_string(merged.join(path)).unwrap();
+        assert_eq!(read("config.json"), "platform");
+        assert_eq!(read("textures/a.ktx"), "a");
+        assert_eq!(read("textures/b.ktx"), "b");
+
+        // Merging again starts from scratch
+        merge_dirs(&[platform.clone(), shared.clone()], &merged).unwrap();
+        assert_eq!(read("config.json"), "shared");
+
+        let conflicting = dir.join("conflicting");
+        write(conflicting.join("textures"), "not a folder");
+        assert!(matches!(
+            merge_dirs(&[shared, conflicting], &merged),
+            Err(NdkError::MergeConflict(path)) if path == merged.join("textures")
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clean_staging_outputs() {
+        let staging_dir =
+            std::env::temp_dir().join(format!("ndk-build-clean-{}", std::process::id()));
+        let stale_lib = staging_dir.join("lib").join("x86_64").join("libremoved.so");
+        fs::create_dir_all(stale_lib.parent().unwrap()).unwrap();
+        fs::write(&stale_lib, "").unwrap();
+        for file in [
+            "classes.dex",
+            "classes2.dex",
+            "app-unaligned.apk",
+            "other-unaligned.apk",
+            "keystore.jks",
+        ] {
+            fs::write(staging_dir.join(file), "").unwrap();
+        }
+        // User-provided resources that happen to live in the staging directory
+        let res = staging_dir.join("res");
+        fs::create_dir_all(res.join("values")).unwrap();
+
+        remove_staging_outputs(&staging_dir, "app", &[&res]).unwrap();
+        let mut remaining = fs::read_dir(&staging_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, ["keystore.jks", "other-unaligned.apk", "res"]);
+
+        fs::remove_dir_all(&staging_dir).unwrap();
+        remove_staging_outputs(&staging_dir, "app", &[]).unwrap();
+    }
+
+    #[test]
+    fn dry_run_reports_commands() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let dry_run = DryRun {
+            enabled: true,
+            on_event: Some(Arc::new({
+                let events = Arc::clone(&events);
+                move |event| events.lock().unwrap().push(event)
+            })),
+        };
+        // Would fail if `aapt2` was run, as it is not on the `PATH`
+        aapt2_compile(
+            Command::new("nonexistent-aapt2"),
+            Path::new("res"),
+            Path::new("resources.zip"),
+            &dry_run,
+        )
+        .unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [BuildEvent::DryRun(
+                r#""nonexistent-aapt2" "compile" "--dir" "res" "-o" "resources.zip""#.to_string()
+            )]
+        );
+        assert!(!DryRun::default().skip(&Command::new("aapt2")));
+    }
+
+    #[test]
+    fn pending_entries_are_added_in_sorted_order() {
+        let dir = std::env::temp_dir().join(format!("ndk-build-order-{}", std::process::id()));
+        let build_tools = dir.join("sdk").join("build-tools").join("34.0.0");
+        fs::create_dir_all(&build_tools).unwrap();
+        for tool in [bin!("aapt"), bin!("zipalign")] {
+            fs::write(build_tools.join(tool), "").unwrap();
+        }
+        let files = ["b.bin", "a.bin", "c.dex"]
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                fs::write(&path, name).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+
+        let aapt_add = |reverse: bool| {
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let config = ApkConfig {
+                ndk: crate::ndk::fake_ndk(&dir.join("sdk"), "34.0.0"),
+                build_dir: dir.join("build"),
+                staging_dir: None,
+                clean: false,
+                apk_name: "app".to_string(),
+                assets: vec![],
+                resources: None,
+                icon: None,
+                manifest: AndroidManifest::default(),
+                manifest_overlay: None,
+                manifest_placeholders: HashMap::new(),
+                application_id: None,
+                disable_aapt_compression: false,
+                compression_level: None,
+                no_compress_extensions: vec![],
+                uncompressed_libs: false,
+                aapt: Aapt::Aapt1,
+                extra_aapt_args: vec![],
+                page_alignment: PageAlignment::Align4K,
+                alignment: DEFAULT_ALIGNMENT,
+                strip: StripConfig::Default,
+                strip_overrides: HashMap::new(),
+                objcopy_override: None,
+                signer: Signer::Apksigner,
+                activity_type: ActivityType::Native,
+                reverse_port_forward: HashMap::new(),
+                port_forward: HashMap::new(),
+                on_event: Some(Arc::new({
+                    let events = Arc::clone(&events);
+                    move |event| events.lock().unwrap().push(event)
+                })),
+                dry_run: true,
+            };
+            fs::create_dir_all(config.staging_dir()).unwrap();
+            let mut apk = UnalignedApk {
+                config: &config,
+                pending_libs: BTreeSet::default(),
+                dex_count: 0,
+            };
+            let mut entries = vec![
+                (&files[0], "assets/b.bin"),
+                (&files[1], "META-INF/a.bin"),
+                (&files[1], "a.bin"),
+            ];
+            if reverse {
+                entries.reverse();
+            }
+            apk.add_dex(&files[2]).unwrap();
+            for (path, apk_path) in entries {
+                apk.add_file(path, apk_path).unwrap();
+            }
+            apk.add_pending_libs_and_align().unwrap();
+
+            let events = events.lock().unwrap();
+            events
+                .iter()
+                .find_map(|event| match event {
+                    BuildEvent::DryRun(cmd) if cmd.contains("\"add\"") => Some(cmd.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let cmd = aapt_add(false);
+        assert_eq!(cmd, aapt_add(true));
+        assert!(cmd.ends_with(r#""META-INF/a.bin" "a.bin" "assets/b.bin" "classes.dex""#));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stage_files() {
+        let dir = std::env::temp_dir().join(format!("ndk-build-files-{}", std::process::id()));
+        let staging_dir = dir.join("staging");
+        fs::create_dir_all(&staging_dir).unwrap();
+        let path = dir.join("config.bin");
+        fs::write(&path, "config").unwrap();
+
+        assert_eq!(
+            stage_file(&staging_dir, &path, r"META-INF\services\config.bin").unwrap(),
+            "META-INF/services/config.bin"
+        );
+        assert_eq!(
+            fs::read_to_string(staging_dir.join("META-INF/services/config.bin")).unwrap(),
+            "config"
+        );
+        assert_eq!(
+            stage_file(&staging_dir, &path, "config.bin").unwrap(),
+            "config.bin"
+        );
+        for apk_path in [
+            "",
+            "/etc/config.bin",
+            "../config.bin",
+            "assets/../../x",
+            "a//b",
+        ] {
+            assert!(
+                matches!(
+                    stage_file(&staging_dir, &path, apk_path),
+                    Err(NdkError::InvalidApkPath(_))
+                ),
+                "{}",
+                apk_path
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multidex_numbering() {
+        let dir = std::env::temp_dir().join(format!("ndk-build-dex-{}", std::process::id()));
+        let staging_dir = dir.join("staging");
+        fs::create_dir_all(&staging_dir).unwrap();
+        let names = ["a.dex", "b.dex", "c.dex"]
+            .iter()
+            .enumerate()
+            .map(|(index, dex)| {
+                let path = dir.join(dex);
+                fs::write(&path, dex).unwrap();
+                stage_dex(&staging_dir, index, &path).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["classes.dex", "classes2.dex", "classes3.dex"]);
+        assert_eq!(
+            fs::read_to_string(staging_dir.join("classes3.dex")).unwrap(),
+            "c.dex"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let apk = crate::zip::central_directory(&[
+            ("AndroidManifest.xml", 1, 1),
+            ("classes2.dex", 1, 1),
+            ("classes.dex", 1, 1),
+            ("lib/x86_64/classes3.dex", 1, 1),
+        ]);
+        assert_eq!(dex_count(&apk).unwrap(), 2);
+    }
+
+    #[test]
+    fn clear_data() {
+        let args = |user| {
+            let mut adb = Command::new("adb");
+            clear_data_args(&mut adb, "com.example.app", user);
+            adb.get_args()
+                .map(|arg| arg.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(args(None), ["shell", "pm", "clear", "com.example.app"]);
+        assert_eq!(
+            args(Some(10)),
+            ["shell", "pm", "clear", "--user", "10", "com.example.app"]
+        );
+
+        assert_eq!(parse_pm_clear("Success\n"), Some(true));
+        assert_eq!(parse_pm_clear("Failed\n"), Some(false));
+        assert_eq!(
+            parse_pm_clear(
+                "Exception occurred while executing 'clear':\n\
+                java.lang.IllegalArgumentException: Unknown package: com.example.app\n"
+            ),
+            Some(false)
+        );
+        assert_eq!(
+            parse_pm_clear(
+                "Error: java.lang.SecurityException: Package com.example.app does not exist\n"
+            ),
+            Some(false)
+        );
+        assert_eq!(parse_pm_clear("cmd: Can't find service: package\n"), None);
+    }
+
+    #[test]
+    fn instrumentation_args() {
+        let mut adb = Command::new("adb");
+        instrument_args(
+            &mut adb,
+            "com.example.app/androidx.test.runner.AndroidJUnitRunner",
+            &[("class".to_string(), "com.example.FooTest".to_string())],
+        );
+        assert_eq!(
+            adb.get_args().collect::<Vec<_>>(),
+            [
+                "shell",
+                "am",
+                "instrument",
+                "-w",
+                "-r",
+                "-e",
+                "class",
+                "com.example.FooTest",
+                "com.example.app/androidx.test.runner.AndroidJUnitRunner"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_instrumentation_output() {
+        let output = "INSTRUMENTATION_STATUS: class=com.example.FooTest
+INSTRUMENTATION_STATUS: current=1
+INSTRUMENTATION_STATUS: numtests=2
+INSTRUMENTATION_STATUS: test=passes
+INSTRUMENTATION_STATUS_CODE: 1
+INSTRUMENTATION_STATUS: class=com.example.FooTest
+INSTRUMENTATION_STATUS: current=1
+INSTRUMENTATION_STATUS: numtests=2
+INSTRUMENTATION_STATUS: test=passes
+INSTRUMENTATION_STATUS_CODE: 0
+INSTRUMENTATION_STATUS: class=com.example.FooTest
+INSTRUMENTATION_STATUS: current=2
+INSTRUMENTATION_STATUS: numtests=2
+INSTRUMENTATION_STATUS: test=fails
+INSTRUMENTATION_STATUS_CODE: 1
+INSTRUMENTATION_STATUS: class=com.example.FooTest
+INSTRUMENTATION_STATUS: current=2
+INSTRUMENTATION_STATUS: numtests=2
+INSTRUMENTATION_STATUS: stack=java.lang.AssertionError: expected:<1> but was:<2>
+\tat com.example.FooTest.fails(FooTest.java:12)
+
+INSTRUMENTATION_STATUS: test=fails
+INSTRUMENTATION_STATUS_CODE: -2
+INSTRUMENTATION_RESULT: stream=
+Time: 0.01
+
+FAILURES!!!
+Tests run: 2,  Failures: 1
+
+INSTRUMENTATION_CODE: -1
+";
+        let result = parse_instrumentation(output);
+        assert_eq!(result.code, Some(-1));
+        assert_eq!(result.failure, None);
+        assert_eq!(
+            result.tests,
+            [
+                InstrumentationTest {
+                    class: "com.example.FooTest".to_string(),
+                    name: "passes".to_string(),
+                    status: TestStatus::Passed,
+                    stack: None,
+                },
+                InstrumentationTest {
+                    class: "com.example.FooTest".to_string(),
+                    name: "fails".to_string(),
+                    status: TestStatus::Failed,
+                    stack: Some(
+                        "java.lang.AssertionError: expected:<1> but was:<2>\n\tat com.example.FooTest.fails(FooTest.java:12)\n"
+                            .to_string()
+                    ),
+                },
+            ]
+        );
+        assert!(!result.passed());
+        assert_eq!(result.failed().count(), 1);
+
+        let result = parse_instrumentation(
+            "INSTRUMENTATION_RESULT: shortMsg=Process crashed.\nINSTRUMENTATION_CODE: 0\n",
+        );
+        assert_eq!(result.failure.as_deref(), Some("Process crashed."));
+        assert!(!result.passed());
+
+        let result = parse_instrumentation("INSTRUMENTATION_CODE: -1\n");
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn pull_file_args() {
+        let args = |run_as| {
+            let mut adb = Command::new("adb");
+            pull_args(
+                &mut adb,
+                "com.example.app",
+                "files/out.txt",
+                Path::new("out.txt"),
+                run_as,
+            );
+            adb.get_args()
+                .map(|arg| arg.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(args(false), ["pull", "files/out.txt", "out.txt"]);
+        assert_eq!(
+            args(true),
+            [
+                "exec-out",
+                "run-as",
+                "com.example.app",
+                "cat",
+                "files/out.txt"
+            ]
+        );
+
+        assert!(is_missing_remote(
+            "adb: error: failed to stat remote object '/sdcard/out.txt': No such file or directory\n"
+        ));
+        assert!(is_missing_remote(
+            "remote object '/sdcard/out.txt' does not exist\n"
+        ));
+        assert!(is_missing_remote(
+            "cat: files/out.txt: No such file or directory\n"
+        ));
+        assert!(!is_missing_remote(
+            "run-as: package not debuggable: com.example.app\n"
+        ));
+    }
+
+    #[test]
+    fn size_report_categories() {
+        let zip = crate::zip::central_directory(&[
+            ("AndroidManifest.xml", 100, 300),
+            ("classes.dex", 1000, 2000),
+            ("classes2.dex", 500, 1000),
+            ("lib/arm64-v8a/libexample.so", 4000, 4000),
+            ("lib/arm64-v8a/libc++_shared.so", 1000, 1000),
+            ("lib/x86_64/libexample.so", 3000, 3000),
+            ("resources.arsc", 200, 200),
+            ("res/mipmap-xxxhdpi/ic_launcher.png", 50, 50),
+            ("assets/level1.bin", 70, 90),
+            ("META-INF/MANIFEST.MF", 10, 20),
+        ]);
+        let report = SizeReport::from_zip(&zip).unwrap();
+        assert_eq!(report.total, zip.len() as u64);
+
+        let size = |compressed, uncompressed| SectionSize {
+            compressed,
+            uncompressed,
+        };
+        let mut expected = BTreeMap::new();
+        expected.insert(SizeCategory::Dex, size(1500, 3000));
+        expected.insert(SizeCategory::Lib("arm64-v8a".to_string()), size(5000, 5000));
+        expected.insert(SizeCategory::Lib("x86_64".to_string()), size(3000, 3000));
+        expected.insert(SizeCategory::Resources, size(250, 250));
+        expected.insert(SizeCategory::Assets, size(70, 90));
+        expected.insert(SizeCategory::Other, size(110, 320));
+        assert_eq!(report.sections, expected);
+    }
+
+    #[test]
+    fn no_compress_extension_args() {
+        let mut aapt = Command::new("aapt");
+        no_compress_args(&mut aapt, &[]);
+        assert_eq!(aapt.get_args().count(), 0);
+
+        no_compress_args(&mut aapt, &["png".to_string(), "ogg".to_string()]);
+        assert_eq!(
+            aapt.get_args().collect::<Vec<_>>(),
+            ["-0", "png", "-0", "ogg"]
+        );
+    }
+
+    #[test]
+    fn strip_overrides() {
+        let mut overrides = HashMap::new();
+        overrides.insert(Target::Arm64V8a, StripConfig::Default);
+        assert_eq!(
+            strip_for_target(StripConfig::Strip, &overrides, Target::Arm64V8a),
+            StripConfig::Default
+        );
+        assert_eq!(
+            strip_for_target(StripConfig::Strip, &overrides, Target::ArmV7a),
+            StripConfig::Strip
+        );
+    }
+
+    #[test]
+    fn parse_pidof_output() {
+        assert_eq!(parse_pids("12345\n"), [12345]);
+        assert_eq!(parse_pids("12345 6789\n"), [12345, 6789]);
+        assert!(parse_pids("").is_empty());
+    }
+
+    #[test]
+    fn install_options_args() {
+        let args = |options: &InstallOptions, incremental: bool| {
+            let mut adb = Command::new("adb");
+            install_args(&mut adb, options, incremental, Path::new("app.apk"));
+            adb.get_args()
+                .map(|arg| arg.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            args(&InstallOptions::default(), false),
+            ["install", "-r", "app.apk"]
+        );
+
+        let options = InstallOptions {
+            grant_permissions: true,
+            allow_downgrade: true,
+            allow_test_packages: true,
+            user: None,
+            clear_data: true,
+        };
+        assert_eq!(
+            args(&options, true),
+            [
+                "install",
+                "--incremental",
+                "-r",
+                "-g",
+                "-d",
+                "-t",
+                "app.apk"
+            ]
+        );
+
+        let options = InstallOptions {
+            user: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            args(&options, false),
+            ["install", "-r", "--user", "10", "app.apk"]
+        );
+
+        let mut adb = Command::new("adb");
+        install_multiple_args(
+            &mut adb,
+            &InstallOptions {
+                allow_downgrade: true,
+                ..Default::default()
+            },
+            &[Path::new("app.apk"), Path::new("app-arm64-v8a.apk")],
+        );
+        assert_eq!(
+            adb.get_args().collect::<Vec<_>>(),
+            [
+                "install-multiple",
+                "-r",
+                "-d",
+                "app.apk",
+                "app-arm64-v8a.apk"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_aapt_badging() {
+        let output = "package: name='com.example.app' versionCode='42' versionName='1.2.0' compileSdkVersion='33' compileSdkVersionCodename='13'
+sdkVersion:'23'
+targetSdkVersion:'33'
+uses-permission: name='android.permission.INTERNET'
+uses-permission: name='android.permission.CAMERA' maxSdkVersion='29'
+application-label:'Example'
+application: label='Example' icon=''
+application-debuggable
+launchable-activity: name='android.app.NativeActivity'  label='Example' icon=''
+native-code: 'arm64-v8a'
+";
+        assert_eq!(
+            parse_badging(output),
+            Badging {
+                package_name: "com.example.app".to_string(),
+                version_code: Some(42),
+                version_name: Some("1.2.0".to_string()),
+                min_sdk_version: Some(23),
+                target_sdk_version: Some(33),
+                launchable_activity: Some("android.app.NativeActivity".to_string()),
+                permissions: vec![
+                    "android.permission.INTERNET".to_string(),
+                    "android.permission.CAMERA".to_string()
+                ],
+                debuggable: true,
+            }
+        );
+        assert!(!parse_badging("package: name='com.example.app'\n").debuggable);
+    }
+
+    #[test]
+    fn start_activity_args() {
+        for (activity, component) in [
+            (
+                "android.app.NativeActivity",
+                "com.example.app/android.app.NativeActivity",
+            ),
+            (".MainActivity", "com.example.app/com.example.MainActivity"),
+        ] {
+            let qualified = format!(
+                "com.example.app/{}",
+                qualify_activity("com.example", activity)
+            );
+            assert_eq!(
+                am_start(&qualified, &StartOptions::main()),
+                [
+                    "shell",
+                    "am",
+                    "start",
+                    "-a",
+                    "android.intent.action.MAIN",
+                    "-n",
+                    component
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn start_with_options_args() {
+        let component = "com.example/android.app.NativeActivity";
+        assert_eq!(
+            am_start(component, &StartOptions::default()),
+            ["shell", "am", "start", "-n", component]
+        );
+
+        let options = StartOptions {
+            user: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            am_start(component, &options),
+            ["shell", "am", "start", "--user", "10", "-n", component]
+        );
+
+        let options = StartOptions {
+            action: Some("android.intent.action.VIEW".to_string()),
+            category: Some("android.intent.category.DEFAULT".to_string()),
+            extras: vec![
+                IntentExtra::String("greeting".to_string(), "hello world".to_string()),
+                IntentExtra::String("quote".to_string(), "it's".to_string()),
+                IntentExtra::Bool("verbose".to_string(), true),
+                IntentExtra::Int("count".to_string(), -3),
+                IntentExtra::Long("seed".to_string(), 1 << 40),
+            ],
+            user: None,
+        };
+        assert_eq!(
+            am_start(component, &options),
+            [
+                "shell",
+                "am",
+                "start",
+                "-a",
+                "android.intent.action.VIEW",
+                "-c",
+                "android.intent.category.DEFAULT",
+                "--es",
+                "greeting",
+                "'hello world'",
+                "--es",
+                "quote",
+                r"'it'\''s'",
+                "--ez",
+                "verbose",
+                "true",
+                "--ei",
+                "count",
+                "-3",
+                "--el",
+                "seed",
+                "1099511627776",
+                "-n",
+                component
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_apksigner_verification() {
+        let output = "\
+Verifies
+Verified using v1 scheme (JAR signing): false
+Verified using v2 scheme (APK Signature Scheme v2): true
+Verified using v3 scheme (APK Signature Scheme v3): true
+Verified using v3.1 scheme (APK Signature Scheme v3.1): false
+Verified using v4 scheme (APK Signature Scheme v4): false
+Verified for SourceStamp: false
+Number of signers: 1
+Signer #1 certificate DN: C=US, O=Android, CN=Android Debug
+Signer #1 certificate SHA-256 digest: 3f0a9bc1d2e3f40516273849aabbccddeeff00112233445566778899aabbccdd
+";
+        assert_eq!(
+            parse_verification(output),
+            Verification {
+                schemes: SigningScheme {
+                    v1: false,
+                    v2: true,
+                    v3: true,
+                    v4: false,
+                },
+                signers: vec![
+                    "3f0a9bc1d2e3f40516273849aabbccddeeff00112233445566778899aabbccdd".to_string()
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn split_apk_names() {
+        assert_eq!(
+            split_apk_name("app", Target::Arm64V8a),
+            "app-arm64-v8a".to_string()
+        );
+        assert_eq!(
+            split_apk_name("app", Target::ArmV7a),
+            "app-armeabi-v7a".to_string()
+        );
+    }
+
+    #[test]
+    fn blocking_task() {
+        struct ThreadWaker(std::thread::Thread);
+        impl std::task::Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut task = BlockingTask::spawn(|| {
+            std::thread::sleep(Duration::from_millis(10));
+            42
+        });
+        let result = loop {
+            match Pin::new(&mut task).poll(&mut cx) {
+                Poll::Ready(result) => break result,
+                Poll::Pending => std::thread::park(),
+            }
+        };
+        assert_eq!(result, 42);
+    }
+
+    #[test]
+    fn icon_is_scaled_to_every_density() {
+        let dir = std::env::temp_dir().join(format!("ndk-build-icon-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let png = dir.join("icon.png");
+        let image = png::Image {
+            width: 512,
+            height: 512,
+            rgba: vec![200; 512 * 512 * 4],
+        };
+        fs::write(&png, png::encode(&image)).unwrap();
+        let jpeg = dir.join("icon.jpg");
+        fs::write(&jpeg, b"\xff\xd8\xff\xe0\0\x10JFIF\0\x01\x01\0\0\x01").unwrap();
+
+        let res = dir.join("res");
+        write_launcher_icons(&png, &res).unwrap();
+        for (density, size) in LAUNCHER_ICON_SIZES {
+            let path = res
+                .join(format!("mipmap-{}", density))
+                .join("ic_launcher.png");
+            let scaled = png::decode(&fs::read(path).unwrap()).unwrap();
+            assert_eq!((scaled.width, scaled.height), (size, size));
+            assert!(scaled.rgba.iter().all(|&channel| channel == 200));
+        }
+        assert!(matches!(
+            write_launcher_icons(&jpeg, &res),
+            Err(NdkError::InvalidIcon(_))
+        ));
+        assert!(matches!(
+            write_launcher_icons(&dir.join("missing.png"), &res),
+            Err(NdkError::InvalidIcon(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn screencap_output_must_be_png() {
+        assert!(is_png_data(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\x04\x38"));
+        assert!(!is_png_data(b"\x89PNG"));
+        assert!(!is_png_data(b"Error: failed to take screenshot\r\n"));
+        // `adb shell` translates `\n` to `\r\n`, corrupting the signature
+        assert!(!is_png_data(b"\x89PNG\r\r\n\x1a\r\n\0\0\0\x0dIHDR"));
+    }
+
+    #[test]
+    fn parse_resumed_activity() {
+        let dumpsys = "\
+ACTIVITY MANAGER ACTIVITIES (dumpsys activity activities)
+Display #0 (activities from top to bottom):
+  * Task{2d3e1f5 #12 type=standard A=10123:rust.example.hello_world U=0 visible=true}
+    topResumedActivity=ActivityRecord{8a1c2b u0 rust.example.hello_world/android.app.NativeActivity t12}
+  ResumedActivity: ActivityRecord{8a1c2b u0 rust.example.hello_world/android.app.NativeActivity t12}
+";
+        assert_eq!(
+            resumed_activity(dumpsys),
+            Some("rust.example.hello_world/android.app.NativeActivity")
+        );
+
+        let legacy = "    mResumedActivity: ActivityRecord{41b0f u0 com.example/.MainActivity t3}";
+        assert_eq!(
+            resumed_activity(legacy).map(expand_component).as_deref(),
+            Some("com.example/com.example.MainActivity")
+        );
+
+        assert_eq!(resumed_activity("mFocusedApp=null"), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn wait_for_activity_times_out_when_dumpsys_hangs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("ndk-build-wait-{}", std::process::id()));
+        let platform_tools = dir.join("sdk/platform-tools");
+        fs::create_dir_all(&platform_tools).unwrap();
+        // Reports another activity in the foreground, then hangs on the next poll
+        let adb = platform_tools.join("adb");
+        fs::write(
+            &adb,
+            "#!/bin/sh\n\
+             if [ -e \"$0.polled\" ]; then exec sleep 30; fi\n\
+             touch \"$0.polled\"\n\
+             echo '  mResumedActivity: ActivityRecord{41b0f u0 com.example/.OtherActivity t3}'\n",
+        )
+        .unwrap();
+        fs::set_permissions(&adb, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let apk = Apk {
+            path: dir.join("app.apk"),
+            idsig: None,
+            package_name: "com.example".to_string(),
+            manifest_package: "com.example".to_string(),
+            activity_name: ".MainActivity".to_string(),
+            min_sdk_version: None,
+            ndk: crate::ndk::fake_ndk(&dir.join("sdk"), "34.0.0"),
+            reverse_port_forward: HashMap::new(),
+            port_forward: HashMap::new(),
+            timeout: None,
+            dry_run: DryRun::default(),
+        };
+        let start = Instant::now();
+        let err = apk
+            .wait_for_activity(".MainActivity", Duration::from_secs(1), None)
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        match err {
+            NdkError::ActivityNotResumed {
+                activity,
+                foreground,
+            } => {
+                assert_eq!(activity, "com.example/com.example.MainActivity");
+                assert_eq!(
+                    foreground.as_deref(),
+                    Some("com.example/com.example.OtherActivity")
+                );
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
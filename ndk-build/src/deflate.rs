@@ -0,0 +1,846 @@
+//! Minimal raw DEFLATE ([RFC 1951]) compressor and decompressor, with the checksums of the zlib,
+//! zip and PNG formats that wrap it. Used to decode launcher icons and to recompress the entries
+//! of an APK at [`ApkConfig::compression_level`](crate::apk::ApkConfig::compression_level).
+//!
+//! [RFC 1951]: https://www.rfc-editor.org/rfc/rfc1951
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// CRC-32 as used by zip archives and PNG chunks
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Adler-32 as used by zlib streams
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the code lengths of the code length alphabet are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+const END_OF_BLOCK: usize = 256;
+
+/// Code lengths of the literal/length alphabet of blocks compressed with fixed Huffman codes
+fn fixed_literal_lengths() -> [u8; 288] {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    lengths
+}
+
+/// Reads the bits of a deflate stream, least significant bit first.
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.position / 8)?;
+            value |= ((byte as u32 >> (self.position % 8)) & 1) << i;
+            self.position += 1;
+        }
+        Some(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.position = (self.position + 7) / 8 * 8;
+    }
+}
+
+/// Canonical Huffman code, as the number of codes per length and the symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as i32;
+            let count = count as i32;
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+/// Decompresses a raw deflate stream, returning the data and the number of bytes the stream
+/// took up. Returns [`None`] for corrupt streams, and stops at the first byte beyond `limit` so
+/// that a small stream can't decompress to an arbitrarily large buffer.
+pub(crate) fn decompress(deflate: &[u8], limit: usize) -> Option<(Vec<u8>, usize)> {
+    let mut bits = Bits {
+        data: deflate,
+        position: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                bits.align_to_byte();
+                let start = bits.position / 8;
+                let header = bits.data.get(start..start + 4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return None;
+                }
+                let data = bits.data.get(start + 4..start + 4 + len as usize)?;
+                if out.len() + data.len() > limit {
+                    return None;
+                }
+                out.extend_from_slice(data);
+                bits.position = (start + 4 + len as usize) * 8;
+            }
+            1 => {
+                let literals = Huffman::new(&fixed_literal_lengths());
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut bits, &mut out, limit, &literals, &distances)?;
+            }
+            2 => {
+                let literal_count = bits.read(5)? as usize + 257;
+                let distance_count = bits.read(5)? as usize + 1;
+                let code_length_count = bits.read(4)? as usize + 4;
+                let mut code_lengths = [0u8; 19];
+                for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+                    code_lengths[i] = bits.read(3)? as u8;
+                }
+                let code_lengths = Huffman::new(&code_lengths);
+
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (value, repeat) = match code_lengths.decode(&mut bits)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => (*lengths.last()?, 3 + bits.read(2)?),
+                        17 => (0, 3 + bits.read(3)?),
+                        18 => (0, 11 + bits.read(7)?),
+                        _ => return None,
+                    };
+                    lengths.extend(std::iter::repeat(value).take(repeat as usize));
+                }
+                if lengths.len() != literal_count + distance_count {
+                    return None;
+                }
+                let literals = Huffman::new(&lengths[..literal_count]);
+                let distances = Huffman::new(&lengths[literal_count..]);
+                inflate_block(&mut bits, &mut out, limit, &literals, &distances)?;
+            }
+            _ => return None,
+        }
+        if last {
+            bits.align_to_byte();
+            return Some((out, bits.position / 8));
+        }
+    }
+}
+
+/// Decodes the symbols of a Huffman compressed block until its end of block symbol, failing
+/// when `out` would grow beyond `limit`.
+fn inflate_block(
+    bits: &mut Bits<'_>,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Option<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 if out.len() < limit => out.push(symbol as u8),
+            0..=255 => return None,
+            END_OF_BLOCK => return Some(()),
+            _ => {
+                let i = symbol - 257;
+                let len =
+                    *LENGTH_BASE.get(i)? as usize + bits.read(LENGTH_EXTRA[i] as u32)? as usize;
+                let i = distances.decode(bits)? as usize;
+                let distance =
+                    *DISTANCE_BASE.get(i)? as usize + bits.read(DISTANCE_EXTRA[i] as u32)? as usize;
+                let start = out.len().checked_sub(distance)?;
+                if out.len() + len > limit {
+                    return None;
+                }
+                for i in start..start + len {
+                    out.push(out[i]);
+                }
+            }
+        }
+    }
+}
+
+/// Highest level accepted by [`compress`]
+pub(crate) const MAX_LEVEL: u32 = 9;
+
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Matches of the minimum length that are further away than this cost more than the literals
+const TOO_FAR: usize = 4096;
+const HASH_BITS: u32 = 15;
+/// Number of symbols after which a block is ended, so that its codes adapt to the data
+const BLOCK_SYMBOLS: usize = 1 << 14;
+const NO_POSITION: u32 = u32::MAX;
+
+/// How hard each level searches for matches: the number of earlier positions compared, the
+/// match length that ends the search early, and whether a match is deferred when the next
+/// position has a longer one, similar to zlib.
+struct Effort {
+    max_chain: usize,
+    nice_length: usize,
+    lazy: bool,
+}
+
+const EFFORT: [Effort; MAX_LEVEL as usize] = [
+    Effort {
+        max_chain: 4,
+        nice_length: 8,
+        lazy: false,
+    },
+    Effort {
+        max_chain: 8,
+        nice_length: 16,
+        lazy: false,
+    },
+    Effort {
+        max_chain: 32,
+        nice_length: 32,
+        lazy: false,
+    },
+    Effort {
+        max_chain: 16,
+        nice_length: 16,
+        lazy: true,
+    },
+    Effort {
+        max_chain: 32,
+        nice_length: 32,
+        lazy: true,
+    },
+    Effort {
+        max_chain: 128,
+        nice_length: 128,
+        lazy: true,
+    },
+    Effort {
+        max_chain: 256,
+        nice_length: 128,
+        lazy: true,
+    },
+    Effort {
+        max_chain: 1024,
+        nice_length: MAX_MATCH,
+        lazy: true,
+    },
+    Effort {
+        max_chain: 4096,
+        nice_length: MAX_MATCH,
+        lazy: true,
+    },
+];
+
+/// A literal byte, or a match of `len` bytes `distance` bytes back when `distance` is non-zero
+#[derive(Clone, Copy)]
+struct Symbol {
+    len: u16,
+    distance: u16,
+}
+
+/// Hash chains of the positions in the window that start with the same three bytes
+struct Matcher<'a> {
+    data: &'a [u8],
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            head: vec![NO_POSITION; 1 << HASH_BITS],
+            prev: vec![NO_POSITION; WINDOW_SIZE],
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let bytes = &self.data[pos..pos + MIN_MATCH];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH <= self.data.len() {
+            let hash = self.hash(pos);
+            self.prev[pos % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = pos as u32;
+        }
+    }
+
+    /// The longest earlier match of the bytes at `pos`, as its length and distance
+    fn longest_match(&self, pos: usize, effort: &Effort) -> (usize, usize) {
+        let max_len = MAX_MATCH.min(self.data.len() - pos);
+        if max_len < MIN_MATCH {
+            return (0, 0);
+        }
+        let (mut best_len, mut best_distance) = (0, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        for _ in 0..effort.max_chain {
+            if candidate == NO_POSITION {
+                break;
+            }
+            let candidate_pos = candidate as usize;
+            // Positions are overwritten once they leave the window
+            if candidate_pos >= pos || pos - candidate_pos > WINDOW_SIZE {
+                break;
+            }
+            let len = self.data[candidate_pos..candidate_pos + max_len]
+                .iter()
+                .zip(&self.data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best_len {
+                best_len = len;
+                best_distance = pos - candidate_pos;
+                if len >= effort.nice_length {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate_pos % WINDOW_SIZE];
+        }
+        if best_len < MIN_MATCH || (best_len == MIN_MATCH && best_distance > TOO_FAR) {
+            (0, 0)
+        } else {
+            (best_len, best_distance)
+        }
+    }
+}
+
+/// Writes the bits of a deflate stream, least significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+
+/// Compresses `data` into a raw deflate stream. Level `0` only stores the data, and higher
+/// levels up to [`MAX_LEVEL`] search longer for matches to produce smaller output.
+pub(crate) fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    if level == 0 || data.is_empty() {
+        write_stored(&mut writer, data, true);
+        return writer.out;
+    }
+
+    let effort = &EFFORT[level.min(MAX_LEVEL) as usize - 1];
+    let mut matcher = Matcher::new(data);
+    let mut symbols = Vec::with_capacity(BLOCK_SYMBOLS);
+    let mut block_start = 0;
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut len, distance) = matcher.longest_match(pos, effort);
+        matcher.insert(pos);
+        if effort.lazy && len >= MIN_MATCH && len < effort.nice_length {
+            // Emit a literal instead when the next position starts a longer match
+            let (next_len, _) = matcher.longest_match(pos + 1, effort);
+            if next_len > len {
+                len = 0;
+            }
+        }
+        if len >= MIN_MATCH {
+            symbols.push(Symbol {
+                len: len as u16,
+                distance: distance as u16,
+            });
+            for pos in pos + 1..pos + len {
+                matcher.insert(pos);
+            }
+            pos += len;
+        } else {
+            symbols.push(Symbol {
+                len: data[pos] as u16,
+                distance: 0,
+            });
+            pos += 1;
+        }
+
+        if symbols.len() == BLOCK_SYMBOLS || pos == data.len() {
+            write_block(
+                &mut writer,
+                &symbols,
+                &data[block_start..pos],
+                pos == data.len(),
+            );
+            symbols.clear();
+            block_start = pos;
+        }
+    }
+    writer.align_to_byte();
+    writer.out
+}
+
+fn write_stored(writer: &mut BitWriter, data: &[u8], last: bool) {
+    let mut chunks = data.chunks(u16::MAX as usize).collect::<Vec<_>>();
+    if chunks.is_empty() {
+        chunks.push(data);
+    }
+    let count = chunks.len();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        writer.write((last && i + 1 == count) as u32, 3);
+        writer.align_to_byte();
+        let len = chunk.len() as u16;
+        writer.out.extend_from_slice(&len.to_le_bytes());
+        writer.out.extend_from_slice(&(!len).to_le_bytes());
+        writer.out.extend_from_slice(chunk);
+    }
+}
+
+/// Index of the length or distance code of `value` in `base`, that `value` is the extra bits
+/// above of
+fn code_index(base: &[u16], value: usize) -> usize {
+    base.partition_point(|&base| base as usize <= value) - 1
+}
+
+/// Writes `symbols`, that encode `data`, as a block with fixed or dynamic Huffman codes, or
+/// stores `data` when that is smaller.
+fn write_block(writer: &mut BitWriter, symbols: &[Symbol], data: &[u8], last: bool) {
+    let mut literal_freqs = [0u32; 286];
+    let mut distance_freqs = [0u32; 30];
+    for symbol in symbols {
+        if symbol.distance == 0 {
+            literal_freqs[symbol.len as usize] += 1;
+        } else {
+            literal_freqs[257 + code_index(&LENGTH_BASE, symbol.len as usize)] += 1;
+            distance_freqs[code_index(&DISTANCE_BASE, symbol.distance as usize)] += 1;
+        }
+    }
+    literal_freqs[END_OF_BLOCK] = 1;
+
+    let literal_lengths = code_lengths(&literal_freqs, 15);
+    let mut distance_lengths = code_lengths(&distance_freqs, 15);
+    if distance_lengths.iter().all(|&len| len == 0) {
+        // At least one distance code has to be described
+        distance_lengths[0] = 1;
+    }
+    let header = DynamicHeader::new(&literal_lengths, &distance_lengths);
+
+    let extra_bits = |freqs: &[u32], extra: &[u8], offset: usize| -> u64 {
+        extra
+            .iter()
+            .enumerate()
+            .map(|(i, &bits)| freqs[offset + i] as u64 * bits as u64)
+            .sum()
+    };
+    let cost = |freqs: &[u32], lengths: &[u8]| -> u64 {
+        freqs
+            .iter()
+            .zip(lengths)
+            .map(|(&freq, &len)| freq as u64 * len as u64)
+            .sum()
+    };
+    let extra = extra_bits(&literal_freqs, &LENGTH_EXTRA, 257)
+        + extra_bits(&distance_freqs, &DISTANCE_EXTRA, 0);
+    let fixed_lengths = fixed_literal_lengths();
+    let fixed_cost =
+        3 + cost(&literal_freqs, &fixed_lengths) + cost(&distance_freqs, &[5; 30]) + extra;
+    let dynamic_cost = 3
+        + header.cost()
+        + cost(&literal_freqs, &literal_lengths)
+        + cost(&distance_freqs, &distance_lengths)
+        + extra;
+    // The header of every stored block, and the padding to the next byte
+    let stored_cost =
+        (data.len() / u16::MAX as usize + 1) as u64 * (3 + 7 + 32) + data.len() as u64 * 8;
+
+    if stored_cost <= fixed_cost.min(dynamic_cost) {
+        write_stored(writer, data, last);
+    } else if fixed_cost <= dynamic_cost {
+        writer.write(last as u32 | 1 << 1, 3);
+        write_symbols(writer, symbols, &fixed_lengths, &[5; 30]);
+    } else {
+        writer.write(last as u32 | 2 << 1, 3);
+        header.write(writer);
+        write_symbols(writer, symbols, &literal_lengths, &distance_lengths);
+    }
+}
+
+fn write_symbols(
+    writer: &mut BitWriter,
+    symbols: &[Symbol],
+    literal_lengths: &[u8],
+    distance_lengths: &[u8],
+) {
+    let literal_codes = canonical_codes(literal_lengths);
+    let distance_codes = canonical_codes(distance_lengths);
+    for symbol in symbols {
+        if symbol.distance == 0 {
+            let literal = symbol.len as usize;
+            writer.write(literal_codes[literal], literal_lengths[literal] as u32);
+            continue;
+        }
+        let (len, distance) = (symbol.len as usize, symbol.distance as usize);
+        let i = code_index(&LENGTH_BASE, len);
+        writer.write(literal_codes[257 + i], literal_lengths[257 + i] as u32);
+        writer.write(
+            (len - LENGTH_BASE[i] as usize) as u32,
+            LENGTH_EXTRA[i] as u32,
+        );
+        let i = code_index(&DISTANCE_BASE, distance);
+        writer.write(distance_codes[i], distance_lengths[i] as u32);
+        writer.write(
+            (distance - DISTANCE_BASE[i] as usize) as u32,
+            DISTANCE_EXTRA[i] as u32,
+        );
+    }
+    writer.write(
+        literal_codes[END_OF_BLOCK],
+        literal_lengths[END_OF_BLOCK] as u32,
+    );
+}
+
+/// The code lengths of a dynamic block, run-length encoded with the code length alphabet
+struct DynamicHeader {
+    literal_count: usize,
+    distance_count: usize,
+    /// Code length symbols with the value of their extra bits
+    symbols: Vec<(u8, u8)>,
+    code_lengths: Vec<u8>,
+    code_length_count: usize,
+}
+
+impl DynamicHeader {
+    fn new(literal_lengths: &[u8], distance_lengths: &[u8]) -> Self {
+        let used = |lengths: &[u8], min: usize| {
+            lengths
+                .iter()
+                .rposition(|&len| len != 0)
+                .map_or(0, |i| i + 1)
+                .max(min)
+        };
+        let literal_count = used(literal_lengths, 257);
+        let distance_count = used(distance_lengths, 1);
+        let lengths = [
+            &literal_lengths[..literal_count],
+            &distance_lengths[..distance_count],
+        ]
+        .concat();
+
+        let mut symbols = Vec::new();
+        let mut i = 0;
+        while i < lengths.len() {
+            let len = lengths[i];
+            let run = lengths[i..].iter().take_while(|&&l| l == len).count();
+            let mut remaining = run;
+            if len == 0 {
+                while remaining >= 11 {
+                    let count = remaining.min(138);
+                    symbols.push((18, (count - 11) as u8));
+                    remaining -= count;
+                }
+                if remaining >= 3 {
+                    symbols.push((17, (remaining - 3) as u8));
+                    remaining = 0;
+                }
+            } else {
+                symbols.push((len, 0));
+                remaining -= 1;
+                while remaining >= 3 {
+                    let count = remaining.min(6);
+                    symbols.push((16, (count - 3) as u8));
+                    remaining -= count;
+                }
+            }
+            symbols.extend(std::iter::repeat((len, 0)).take(remaining));
+            i += run;
+        }
+
+        let mut freqs = [0u32; 19];
+        for &(symbol, _) in &symbols {
+            freqs[symbol as usize] += 1;
+        }
+        let code_lengths = code_lengths(&freqs, 7);
+        let code_length_count = CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&i| code_lengths[i] != 0)
+            .map_or(0, |i| i + 1)
+            .max(4);
+        Self {
+            literal_count,
+            distance_count,
+            symbols,
+            code_lengths,
+            code_length_count,
+        }
+    }
+
+    fn extra_bits(symbol: u8) -> u32 {
+        match symbol {
+            16 => 2,
+            17 => 3,
+            18 => 7,
+            _ => 0,
+        }
+    }
+
+    /// Size of the header in bits
+    fn cost(&self) -> u64 {
+        5 + 5
+            + 4
+            + 3 * self.code_length_count as u64
+            + self
+                .symbols
+                .iter()
+                .map(|&(symbol, _)| {
+                    (self.code_lengths[symbol as usize] as u32 + Self::extra_bits(symbol)) as u64
+                })
+                .sum::<u64>()
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write((self.literal_count - 257) as u32, 5);
+        writer.write((self.distance_count - 1) as u32, 5);
+        writer.write((self.code_length_count - 4) as u32, 4);
+        for &i in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            writer.write(self.code_lengths[i] as u32, 3);
+        }
+        let codes = canonical_codes(&self.code_lengths);
+        for &(symbol, extra) in &self.symbols {
+            let symbol = symbol as usize;
+            writer.write(codes[symbol], self.code_lengths[symbol] as u32);
+            writer.write(extra as u32, Self::extra_bits(symbol as u8));
+        }
+    }
+}
+
+/// Huffman code lengths of at most `max_len` bits for symbols occurring `freqs` times. Unused
+/// symbols get no code.
+fn code_lengths(freqs: &[u32], max_len: u8) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut used = (0..freqs.len())
+        .filter(|&i| freqs[i] > 0)
+        .collect::<Vec<_>>();
+    if used.len() == 1 {
+        lengths[used[0]] = 1;
+    }
+    if used.len() <= 1 {
+        return lengths;
+    }
+
+    // Build the tree by repeatedly merging the two least frequent nodes, keeping track of the
+    // parent of every node to find the depth of the leaves
+    let mut parents = vec![0usize; used.len() * 2 - 1];
+    let mut heap = used
+        .iter()
+        .enumerate()
+        .map(|(node, &symbol)| Reverse((freqs[symbol] as u64, node)))
+        .collect::<BinaryHeap<_>>();
+    let mut next = used.len();
+    while let (Some(Reverse((a, a_node))), Some(Reverse((b, b_node)))) = (heap.pop(), heap.pop()) {
+        parents[a_node] = next;
+        parents[b_node] = next;
+        heap.push(Reverse((a + b, next)));
+        next += 1;
+    }
+    let root = next - 1;
+    let mut depths = vec![0usize; parents.len()];
+    for node in (0..root).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+
+    // Count the codes of every length, moving codes that are too long up to `max_len` and
+    // splitting shorter codes until the lengths describe a complete code again
+    let max_len = max_len as usize;
+    let mut counts = vec![0u32; max_len + 1];
+    for &depth in &depths[..used.len()] {
+        counts[depth.min(max_len)] += 1;
+    }
+    let mut kraft = (1..=max_len)
+        .map(|len| (counts[len] as u64) << (max_len - len))
+        .sum::<u64>();
+    while kraft > 1 << max_len {
+        counts[max_len] -= 1;
+        if let Some(len) = (1..max_len).rev().find(|&len| counts[len] > 0) {
+            counts[len] -= 1;
+            counts[len + 1] += 2;
+        }
+        kraft -= 1;
+    }
+
+    // Hand out the shortest codes to the most frequent symbols
+    used.sort_by_key(|&symbol| Reverse(freqs[symbol]));
+    let mut symbols = used.into_iter();
+    for (len, &count) in counts.iter().enumerate().skip(1) {
+        for symbol in symbols.by_ref().take(count as usize) {
+            lengths[symbol] = len as u8;
+        }
+    }
+    lengths
+}
+
+/// Canonical Huffman codes of `lengths`, with their bits reversed to be written least
+/// significant bit first.
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut counts = [0u32; 16];
+    for &len in lengths {
+        counts[len as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut next = [0u32; 16];
+    for len in 1..16 {
+        next[len] = (next[len - 1] + counts[len - 1]) << 1;
+    }
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next[len as usize];
+            next[len as usize] += 1;
+            code.reverse_bits() >> (32 - len as u32)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn compress_every_level() {
+        let text = "Android package kit, built by cargo-apk from a Rust crate. "
+            .repeat(100)
+            .into_bytes();
+        // Data that only repeats in parts, with all byte values
+        let noise = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> (i % 24)) as u8)
+            .collect::<Vec<_>>();
+        for data in [&b""[..], &b"a"[..], &text[..], &noise[..]] {
+            let mut previous = usize::MAX;
+            for level in 0..=MAX_LEVEL {
+                let compressed = compress(data, level);
+                let (decompressed, len) = decompress(&compressed, data.len()).unwrap();
+                assert_eq!(decompressed, data, "level {}", level);
+                assert_eq!(len, compressed.len());
+                if level == 0 {
+                    previous = compressed.len();
+                } else {
+                    assert!(compressed.len() <= previous + 16, "level {}", level);
+                }
+            }
+        }
+        assert!(compress(&text, 9).len() < text.len() / 20);
+    }
+
+    #[test]
+    fn code_lengths_are_limited() {
+        // Fibonacci frequencies produce the deepest possible tree
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            freqs.push(freqs[freqs.len() - 1] + freqs[freqs.len() - 2]);
+        }
+        let lengths = code_lengths(&freqs, 15);
+        assert_eq!(lengths.iter().max(), Some(&15));
+        let kraft = lengths.iter().map(|&len| 1u64 << (15 - len)).sum::<u64>();
+        assert_eq!(kraft, 1 << 15);
+
+        assert_eq!(code_lengths(&[0, 5, 0], 15), [0, 1, 0]);
+    }
+}
@@ -2,8 +2,11 @@ use std::io::Error as IoError;
 use std::num::ParseIntError;
 use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
 use thiserror::Error;
 
+use crate::ndk::{BuildToolsCapability, NdkVersion, SdkComponent};
+
 #[derive(Debug, Error)]
 pub enum NdkError {
     #[error(
@@ -28,12 +31,60 @@ pub enum NdkError {
     PathNotFound(PathBuf),
     #[error("Command `{0}` not found.")]
     CmdNotFound(String),
-    #[error("Android SDK has no build tools.")]
-    BuildToolsNotFound,
+    #[error(
+        "Android SDK has no build tools{}, installed versions: {available:?}. \
+        Install them with `sdkmanager \"build-tools;{}\"`.",
+        requested.as_ref().map_or(String::new(), |v| format!(" version `{}`", v)),
+        requested.as_deref().unwrap_or("<version>")
+    )]
+    BuildToolsNotFound {
+        /// The version passed to [`Ndk::set_build_tools_version`](crate::ndk::Ndk::set_build_tools_version),
+        /// if any
+        requested: Option<String>,
+        available: Vec<String>,
+    },
+    #[error(
+        "{capability:?} requires build-tools >= {required}, but build-tools {found} are selected."
+    )]
+    UnsupportedBuildTools {
+        capability: BuildToolsCapability,
+        required: &'static str,
+        found: String,
+    },
+    #[error("NDK {found} is not supported, NDK r{required} or newer is required.")]
+    UnsupportedNdkVersion { found: NdkVersion, required: u32 },
     #[error("Android SDK has no platforms installed.")]
     NoPlatformFound,
     #[error("Platform `{0}` is not installed.")]
     PlatformNotFound(u32),
+    #[error(
+        "{component} is not installed (`{path:?}` doesn't exist), install it with \
+        `sdkmanager \"{}\"`.",
+        component.sdkmanager_package()
+    )]
+    SdkComponentNotFound {
+        component: SdkComponent,
+        /// The file or directory that was looked up
+        path: PathBuf,
+    },
+    #[error("`{0}` is not part of the platform, add a `.dex` containing it to the APK.")]
+    MissingDex(&'static str),
+    #[error("APK path `{0}` must be relative and can't contain `..` components.")]
+    InvalidApkPath(String),
+    #[error("Resource `{0}` was not found in the resources directory.")]
+    ResourceNotFound(String),
+    #[error("Icon `{0}` is not a readable PNG image.")]
+    InvalidIcon(PathBuf),
+    #[error("`{0:?}` is a file in one of the merged folders, but a folder in another.")]
+    MergeConflict(PathBuf),
+    #[error("`android:requiresSmallestWidthDp` must be a positive integer.")]
+    InvalidSmallestWidth,
+    #[error("`versionCode` must be a positive integer, got `{0}`.")]
+    InvalidVersionCode(String),
+    #[error("Alignment `{0}` is not a power of two.")]
+    InvalidAlignment(u16),
+    #[error("Compression level `{0}` is not in the range `0..=9`.")]
+    InvalidCompressionLevel(u32),
     #[error("Target is not supported.")]
     UnsupportedTarget,
     #[error("Host `{0}` is not supported.")]
@@ -45,13 +96,72 @@ pub enum NdkError {
     #[error("Invalid semver")]
     InvalidSemver,
     #[error("Command `{}` had a non-zero exit code.", format!("{:?}", .0).replace('"', ""))]
-    CmdFailed(Command),
+    CmdFailed(Box<Command>),
+    #[error("Command `{cmd}` did not finish within {timeout:?}.")]
+    Timeout { cmd: String, timeout: Duration },
     #[error(transparent)]
     Serialize(#[from] quick_xml::de::DeError),
+    #[error(transparent)]
+    Xml(#[from] quick_xml::Error),
+    #[error("Invalid manifest: {0}")]
+    InvalidManifestXml(String),
+    #[error("Invalid zip archive: {0}")]
+    InvalidZip(String),
     #[error("String `{1}` is not a UID")]
     NotAUid(#[source] ParseIntError, String),
     #[error("Could not find `package:{package}` in output `{output}`")]
     PackageNotInOutput { package: String, output: String },
+    #[error(
+        "Activity `{activity}` was not resumed before the timeout, foreground activity is `{}`",
+        .foreground.as_deref().unwrap_or("<none>")
+    )]
+    ActivityNotResumed {
+        activity: String,
+        foreground: Option<String>,
+    },
+    #[error(
+        "APK is not signed by a certificate with SHA-256 digest `{expected}`, signers: {signers:?}"
+    )]
+    UnexpectedSigner {
+        expected: String,
+        signers: Vec<String>,
+    },
+    #[error("Could not connect to `{address}`: {output}")]
+    ConnectFailed { address: String, output: String },
+    #[error("Could not install {apks:?} together: {output}")]
+    InstallMultipleFailed { apks: Vec<PathBuf>, output: String },
+    #[error("Could not disconnect from `{address}`: {output}")]
+    DisconnectFailed { address: String, output: String },
+    #[error("No process of `{0}` is running")]
+    AppNotRunning(String),
+    #[error("Remote file `{0}` does not exist on the device")]
+    RemoteFileNotFound(String),
+    #[error("`screencap` did not return a PNG image: {0}")]
+    ScreencapFailed(String),
+    #[error("Neither `apksigner` of the build tools nor the JDK's `jarsigner` was found.")]
+    NoSignerFound,
+    #[error("`jarsigner` can't produce the v4 signature, sign with `apksigner` instead.")]
+    UnsupportedJarsignerScheme,
+    #[error("Invalid forward spec `{spec}`: {reason}")]
+    InvalidForwardSpec { spec: String, reason: String },
+    #[error("User `{0}` does not exist on the device")]
+    UserNotFound(u32),
+    #[error("No device available: {0}")]
+    NoDevice(String),
+    #[error("Device property `{0}` is not set")]
+    EmptyDeviceProperty(String),
+    #[error("Device property `{property}` has unexpected value `{value}`")]
+    InvalidDeviceProperty { property: String, value: String },
+    #[error("Failed to start the activity: {0}")]
+    ActivityStartFailed(String),
     #[error("Could not find `uid:` in output `{0}`")]
     UidNotInOutput(String),
+    #[error("Environment variable `{name}` holding a password is not set")]
+    MissingEnvVar { name: String },
+    #[error("A key alias must be specified for a keystore with keys {0:?}")]
+    AmbiguousKeyAlias(Vec<String>),
+    #[error("APK signature does not verify: {0}")]
+    VerificationFailed(String),
+    #[error("Could not find `SHA256:` certificate fingerprint in output `{0}`")]
+    FingerprintNotInOutput(String),
 }
@@ -28,11 +28,15 @@ macro_rules! cmd {
     };
 }
 
+pub mod aab;
 pub mod apk;
 pub mod cargo;
+mod deflate;
 pub mod dylibs;
 pub mod error;
 pub mod manifest;
 pub mod ndk;
+mod png;
 pub mod readelf;
 pub mod target;
+mod zip;
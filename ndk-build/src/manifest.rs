@@ -1,6 +1,8 @@
 use crate::error::NdkError;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::{Reader, Writer};
 use serde::{Deserialize, Serialize, Serializer};
-use std::{fs::File, path::Path};
+use std::{collections::HashMap, fs::File, path::Path};
 
 /// Android [manifest element](https://developer.android.com/guide/topics/manifest/manifest-element), containing an [`Application`] element.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -11,6 +13,7 @@ pub struct AndroidManifest {
     ns_android: String,
     #[serde(default)]
     pub package: String,
+    /// Deprecated since API level 29; only use this to migrate data of legacy apps.
     #[serde(rename(serialize = "android:sharedUserId"))]
     pub shared_user_id: Option<String>,
     #[serde(rename(serialize = "android:versionCode"))]
@@ -28,6 +31,13 @@ pub struct AndroidManifest {
     #[serde(rename(serialize = "uses-permission"))]
     #[serde(default)]
     pub uses_permission: Vec<Permission>,
+    #[serde(rename(serialize = "supports-screens"))]
+    pub supports_screens: Option<SupportsScreens>,
+    /// Serialize strings wrapped in `<supports-gl-texture android:name="..." />`
+    #[serde(serialize_with = "serialize_supports_gl_texture")]
+    #[serde(rename(serialize = "supports-gl-texture"))]
+    #[serde(default)]
+    pub supports_gl_texture: Vec<String>,
 
     #[serde(default)]
     pub queries: Option<Queries>,
@@ -47,6 +57,8 @@ impl Default for AndroidManifest {
             sdk: Default::default(),
             uses_feature: Default::default(),
             uses_permission: Default::default(),
+            supports_screens: Default::default(),
+            supports_gl_texture: Default::default(),
             queries: Default::default(),
             application: Default::default(),
         }
@@ -60,11 +72,226 @@ impl AndroidManifest {
         quick_xml::se::to_writer(w, &self)?;
         Ok(())
     }
+
+    /// Same as [`AndroidManifest::write_to`], but replaces every `${key}` in the generated
+    /// manifest with the corresponding (escaped) value from `placeholders`.
+    pub fn write_to_with_placeholders(
+        &self,
+        dir: &Path,
+        placeholders: &HashMap<String, String>,
+    ) -> Result<(), NdkError> {
+        let mut xml = quick_xml::se::to_string(&self)?;
+        for (key, value) in placeholders {
+            xml = xml.replace(&format!("${{{}}}", key), &quick_xml::escape::escape(value));
+        }
+        let path = dir.join("AndroidManifest.xml");
+        std::fs::write(&path, xml).map_err(|e| NdkError::IoPathError(path, e))?;
+        Ok(())
+    }
+
+    /// Makes the activity launchable: adds a `MAIN`/`LAUNCHER` intent filter unless
+    /// [`Activity::launcher`] is disabled or a filter with a `MAIN` action is supplied by hand,
+    /// and exports the activity when targeting API level 31 or higher unless
+    /// [`Activity::exported`] is set explicitly.
+    pub fn add_launcher_defaults(&mut self) {
+        let activity = &mut self.application.activity;
+        if activity.launcher
+            && activity
+                .intent_filter
+                .iter()
+                .all(|i| i.actions.iter().all(|f| f != "android.intent.action.MAIN"))
+        {
+            activity.intent_filter.push(IntentFilter {
+                auto_verify: None,
+                priority: None,
+                actions: vec!["android.intent.action.MAIN".to_string()],
+                categories: vec!["android.intent.category.LAUNCHER".to_string()],
+                data: vec![],
+            });
+        }
+
+        // Without this, apps with intent filters fail to install on S and up.
+        // https://developer.android.com/about/versions/12/behavior-changes-12#exported
+        if self.sdk.target_sdk_version.map_or(false, |v| v >= 31) {
+            activity.exported.get_or_insert(true);
+        }
+    }
+}
+
+/// Elements that occur at most once within their parent, and are therefore merged by
+/// [`merge_manifest`] even though they have no `android:name`
+const SINGLETON_ELEMENTS: &[&str] = &["application", "uses-sdk", "supports-screens", "queries"];
+
+/// Merges the `overlay` manifest on top of the `generated` one, e.g. to add elements that
+/// [`AndroidManifest`] doesn't model. Attributes of the overlay replace those of the generated
+/// manifest. Child elements with the same tag and `android:name` (or the same tag, for elements
+/// like `<application>` that occur once) are merged recursively, all other children of the
+/// overlay are appended.
+pub fn merge_manifest(generated: &str, overlay: &str) -> Result<String, NdkError> {
+    let mut manifest = XmlElement::parse(generated)?;
+    let overlay = XmlElement::parse(overlay)?;
+    if overlay.name != manifest.name {
+        return Err(NdkError::InvalidManifestXml(format!(
+            "expected `<{}>` root element in overlay, found `<{}>`",
+            manifest.name, overlay.name
+        )));
+    }
+    manifest.merge(&overlay);
+
+    let mut writer = Writer::new(Vec::new());
+    manifest.write(&mut writer)?;
+    Ok(String::from_utf8(writer.into_inner()).unwrap())
+}
+
+/// Minimal XML tree for [`merge_manifest`]; text and comments are not retained.
+#[derive(Clone, Debug)]
+struct XmlElement {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+    fn parse(xml: &str) -> Result<Self, NdkError> {
+        let mut reader = Reader::from_str(xml);
+        reader.trim_text(true);
+        let mut open = Vec::<Self>::new();
+        loop {
+            let element = match reader.read_event()? {
+                Event::Start(start) => {
+                    open.push(Self::from_start(&start)?);
+                    continue;
+                }
+                Event::Empty(start) => Self::from_start(&start)?,
+                Event::End(_) => open.pop().ok_or_else(|| {
+                    NdkError::InvalidManifestXml("unexpected closing tag".to_string())
+                })?,
+                Event::Eof => {
+                    return Err(NdkError::InvalidManifestXml(
+                        "missing root element".to_string(),
+                    ))
+                }
+                _ => continue,
+            };
+            match open.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => return Ok(element),
+            }
+        }
+    }
+
+    fn from_start(start: &BytesStart<'_>) -> Result<Self, NdkError> {
+        let attributes = start
+            .attributes()
+            .map(|attribute| {
+                let attribute = attribute.map_err(quick_xml::Error::from)?;
+                Ok((
+                    String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+                    attribute.unescape_value()?.into_owned(),
+                ))
+            })
+            .collect::<Result<_, NdkError>>()?;
+        Ok(Self {
+            name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+            attributes,
+            children: Vec::new(),
+        })
+    }
+
+    fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether `other` describes the same element, and should be merged into this one
+    fn is_same(&self, other: &Self) -> bool {
+        self.name == other.name
+            && match (
+                self.attribute("android:name"),
+                other.attribute("android:name"),
+            ) {
+                (Some(name), Some(other_name)) => name == other_name,
+                (None, None) => SINGLETON_ELEMENTS.contains(&self.name.as_str()),
+                _ => false,
+            }
+    }
+
+    fn merge(&mut self, overlay: &Self) {
+        for (key, value) in &overlay.attributes {
+            match self.attributes.iter_mut().find(|(k, _)| k == key) {
+                Some((_, existing)) => *existing = value.clone(),
+                None => self.attributes.push((key.clone(), value.clone())),
+            }
+        }
+        for child in &overlay.children {
+            match self.children.iter_mut().find(|c| c.is_same(child)) {
+                Some(existing) => existing.merge(child),
+                None => self.children.push(child.clone()),
+            }
+        }
+    }
+
+    fn write(&self, writer: &mut Writer<Vec<u8>>) -> Result<(), NdkError> {
+        let mut start = BytesStart::new(self.name.as_str());
+        for (key, value) in &self.attributes {
+            start.push_attribute((key.as_str(), value.as_str()));
+        }
+        if self.children.is_empty() {
+            writer.write_event(Event::Empty(start))?;
+        } else {
+            writer.write_event(Event::Start(start))?;
+            for child in &self.children {
+                child.write(writer)?;
+            }
+            writer.write_event(Event::End(BytesEnd::new(self.name.as_str())))?;
+        }
+        Ok(())
+    }
+}
+
+/// Texture compression formats documented for the [supports-gl-texture element](https://developer.android.com/guide/topics/manifest/supports-gl-texture-element).
+pub const KNOWN_GL_TEXTURE_FORMATS: &[&str] = &[
+    "GL_OES_compressed_ETC1_RGB8_texture",
+    "GL_OES_compressed_paletted_texture",
+    "GL_AMD_compressed_3DC_texture",
+    "GL_AMD_compressed_ATC_texture",
+    "GL_EXT_texture_compression_latc",
+    "GL_EXT_texture_compression_dxt1",
+    "GL_EXT_texture_compression_s3tc",
+    "GL_IMG_texture_compression_pvrtc",
+    "GL_KHR_texture_compression_astc_ldr",
+    "GL_KHR_texture_compression_astc_hdr",
+];
+
+fn serialize_supports_gl_texture<S>(formats: &[String], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    use serde::ser::SerializeSeq;
+
+    #[derive(Serialize)]
+    struct SupportsGlTexture {
+        #[serde(rename = "android:name")]
+        name: String,
+    }
+    let mut seq = serializer.serialize_seq(Some(formats.len()))?;
+    for format in formats {
+        seq.serialize_element(&SupportsGlTexture {
+            name: format.clone(),
+        })?;
+    }
+    seq.end()
 }
 
 /// Android [application element](https://developer.android.com/guide/topics/manifest/application-element), containing an [`Activity`] element.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Application {
+    /// Fully-qualified name of an `android.app.Application` subclass instantiated before any
+    /// other component, which has to be part of a `.dex` in the APK.
+    #[serde(rename(serialize = "android:name"))]
+    pub name: Option<String>,
     #[serde(rename(serialize = "android:debuggable"))]
     pub debuggable: Option<bool>,
     #[serde(rename(serialize = "android:theme"))]
@@ -74,6 +301,10 @@ pub struct Application {
     pub has_code: bool,
     #[serde(rename(serialize = "android:icon"))]
     pub icon: Option<String>,
+    #[serde(rename(serialize = "android:roundIcon"))]
+    pub round_icon: Option<String>,
+    #[serde(rename(serialize = "android:appCategory"))]
+    pub app_category: Option<AppCategory>,
     #[serde(rename(serialize = "android:label"))]
     #[serde(default)]
     pub label: String,
@@ -81,6 +312,11 @@ pub struct Application {
     pub extract_native_libs: Option<bool>,
     #[serde(rename(serialize = "android:usesCleartextTraffic"))]
     pub uses_cleartext_traffic: Option<bool>,
+    /// Drawable resource shown on the Android TV home screen, e.g. `@drawable/banner`.
+    #[serde(rename(serialize = "android:banner"))]
+    pub banner: Option<String>,
+    #[serde(rename(serialize = "android:uiOptions"))]
+    pub ui_options: Option<String>,
 
     #[serde(rename(serialize = "meta-data"))]
     #[serde(default)]
@@ -89,6 +325,45 @@ pub struct Application {
     pub activity: Activity,
 }
 
+/// Values of the [`android:appCategory`](https://developer.android.com/guide/topics/manifest/application-element#appCategory)
+/// attribute, used to group apps by purpose when summarizing battery, network or disk usage.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AppCategory {
+    Accessibility,
+    Audio,
+    Game,
+    Image,
+    Maps,
+    News,
+    Productivity,
+    Social,
+    Video,
+}
+
+impl AppCategory {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Accessibility => "accessibility",
+            Self::Audio => "audio",
+            Self::Game => "game",
+            Self::Image => "image",
+            Self::Maps => "maps",
+            Self::News => "news",
+            Self::Productivity => "productivity",
+            Self::Social => "social",
+            Self::Video => "video",
+        }
+    }
+}
+
+/// Serialized as a plain string so that it ends up in an attribute, rather than a child element
+impl Serialize for AppCategory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 /// Android [activity element](https://developer.android.com/guide/topics/manifest/activity-element).
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Activity {
@@ -104,17 +379,30 @@ pub struct Activity {
     pub name: String,
     #[serde(rename(serialize = "android:screenOrientation"))]
     pub orientation: Option<String>,
+    /// Style resource replacing the [`Application::theme`] for this activity, e.g. for a splash
+    /// screen.
+    #[serde(rename(serialize = "android:theme"))]
+    pub theme: Option<String>,
     #[serde(rename(serialize = "android:exported"))]
     pub exported: Option<bool>,
     #[serde(rename(serialize = "android:resizeableActivity"))]
     pub resizeable_activity: Option<bool>,
     #[serde(rename(serialize = "android:alwaysRetainTaskState"))]
     pub always_retain_task_state: Option<bool>,
+    /// Drawable resource shown on the Android TV home screen, overriding the [`Application::banner`].
+    #[serde(rename(serialize = "android:banner"))]
+    pub banner: Option<String>,
+    #[serde(rename(serialize = "android:uiOptions"))]
+    pub ui_options: Option<String>,
+    /// Whether [`AndroidManifest::add_launcher_defaults`] adds a `MAIN`/`LAUNCHER` intent filter.
+    #[serde(skip_serializing, default = "default_launcher")]
+    pub launcher: bool,
 
     #[serde(rename(serialize = "meta-data"))]
     #[serde(default)]
     pub meta_data: Vec<MetaData>,
-    /// If no `MAIN` action exists in any intent filter, a default `MAIN` filter is serialized by `cargo-apk`.
+    /// If no `MAIN` action exists in any intent filter, a default `MAIN` filter is added by
+    /// [`AndroidManifest::add_launcher_defaults`].
     #[serde(rename(serialize = "intent-filter"))]
     #[serde(default)]
     pub intent_filter: Vec<IntentFilter>,
@@ -128,9 +416,13 @@ impl Default for Activity {
             launch_mode: None,
             name: default_activity_name(),
             orientation: None,
+            theme: None,
             exported: None,
             resizeable_activity: None,
             always_retain_task_state: None,
+            banner: None,
+            ui_options: None,
+            launcher: default_launcher(),
             meta_data: Default::default(),
             intent_filter: Default::default(),
         }
@@ -140,6 +432,13 @@ impl Default for Activity {
 /// Android [intent filter element](https://developer.android.com/guide/topics/manifest/intent-filter-element).
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct IntentFilter {
+    /// Request verification of all hosts in this filter for [Android App Links](https://developer.android.com/training/app-links/verify-android-applinks).
+    #[serde(rename(serialize = "android:autoVerify"))]
+    pub auto_verify: Option<bool>,
+    /// Order in which the filter is considered when several components can handle an intent.
+    /// Higher values are preferred; the default is `0`.
+    #[serde(rename(serialize = "android:priority"))]
+    pub priority: Option<i32>,
     /// Serialize strings wrapped in `<action android:name="..." />`
     #[serde(serialize_with = "serialize_actions")]
     #[serde(rename(serialize = "action"))]
@@ -150,6 +449,7 @@ pub struct IntentFilter {
     #[serde(rename(serialize = "category"))]
     #[serde(default)]
     pub categories: Vec<String>,
+    /// Serialized as one `<data />` element per entry
     #[serde(default)]
     pub data: Vec<IntentFilterData>,
 }
@@ -223,6 +523,25 @@ pub struct MetaData {
     pub value: String,
 }
 
+/// Android [supports-screens element](https://developer.android.com/guide/topics/manifest/supports-screens-element).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SupportsScreens {
+    /// Minimum `smallestWidth` in dp a device must have, used by Google Play to filter out
+    /// smaller devices. Must be positive.
+    #[serde(rename(serialize = "android:requiresSmallestWidthDp"))]
+    pub requires_smallest_width_dp: Option<u32>,
+    #[serde(rename(serialize = "android:smallScreens"))]
+    pub small_screens: Option<bool>,
+    #[serde(rename(serialize = "android:normalScreens"))]
+    pub normal_screens: Option<bool>,
+    #[serde(rename(serialize = "android:largeScreens"))]
+    pub large_screens: Option<bool>,
+    #[serde(rename(serialize = "android:xlargeScreens"))]
+    pub xlarge_screens: Option<bool>,
+    #[serde(rename(serialize = "android:anyDensity"))]
+    pub any_density: Option<bool>,
+}
+
 /// Android [uses-feature element](https://developer.android.com/guide/topics/manifest/uses-feature-element).
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Feature {
@@ -239,7 +558,7 @@ pub struct Feature {
     ///   for available levels and the respective Vulkan features required/provided.
     ///
     /// - `name="android.hardware.vulkan.version"`: Represents the value of Vulkan's `VkPhysicalDeviceProperties::apiVersion`. See the [Android documentation](https://developer.android.com/reference/android/content/pm/PackageManager#FEATURE_VULKAN_HARDWARE_VERSION)
-    ///    for available levels and the respective Vulkan features required/provided.
+    ///   for available levels and the respective Vulkan features required/provided.
     #[serde(rename(serialize = "android:version"))]
     pub version: Option<u32>,
     #[serde(rename(serialize = "android:glEsVersion"))]
@@ -331,6 +650,198 @@ fn default_activity_name() -> String {
     "android.app.NativeActivity".to_string()
 }
 
+fn default_launcher() -> bool {
+    true
+}
+
 fn default_config_changes() -> Option<String> {
     Some("orientation|keyboardHidden|screenSize".to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENERATED: &str = r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example" android:versionCode="1"><uses-sdk android:minSdkVersion="23"/><application android:label="Example"><activity android:name="android.app.NativeActivity"><intent-filter><action android:name="android.intent.action.MAIN"/></intent-filter></activity></application></manifest>"#;
+
+    #[test]
+    fn merge_manifest_overrides_attributes() {
+        let overlay = r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" xmlns:tools="http://schemas.android.com/tools" android:versionCode="2">
+            <uses-sdk android:targetSdkVersion="33"/>
+            <application android:label="Overridden"/>
+        </manifest>"#;
+        assert_eq!(
+            merge_manifest(GENERATED, overlay).unwrap(),
+            r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example" android:versionCode="2" xmlns:tools="http://schemas.android.com/tools"><uses-sdk android:minSdkVersion="23" android:targetSdkVersion="33"/><application android:label="Overridden"><activity android:name="android.app.NativeActivity"><intent-filter><action android:name="android.intent.action.MAIN"/></intent-filter></activity></application></manifest>"#
+        );
+    }
+
+    #[test]
+    fn merge_manifest_appends_and_merges_children() {
+        let overlay = r#"<manifest>
+            <application>
+                <!-- merged by android:name -->
+                <activity android:name="android.app.NativeActivity" android:exported="true">
+                    <intent-filter><action android:name="android.intent.action.VIEW"/></intent-filter>
+                </activity>
+                <receiver android:name=".BootReceiver"/>
+                <meta-data android:name="key" android:value="a &amp; b"/>
+            </application>
+        </manifest>"#;
+        assert_eq!(
+            merge_manifest(GENERATED, overlay).unwrap(),
+            r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example" android:versionCode="1"><uses-sdk android:minSdkVersion="23"/><application android:label="Example"><activity android:name="android.app.NativeActivity" android:exported="true"><intent-filter><action android:name="android.intent.action.MAIN"/></intent-filter><intent-filter><action android:name="android.intent.action.VIEW"/></intent-filter></activity><receiver android:name=".BootReceiver"/><meta-data android:name="key" android:value="a &amp; b"/></application></manifest>"#
+        );
+    }
+
+    #[test]
+    fn merge_manifest_rejects_other_root() {
+        assert!(matches!(
+            merge_manifest(GENERATED, "<application/>"),
+            Err(NdkError::InvalidManifestXml(_))
+        ));
+    }
+
+    #[test]
+    fn manifest_placeholders() {
+        let mut manifest = AndroidManifest::default();
+        manifest.application.label = "${appName}".to_string();
+        manifest.application.meta_data.push(MetaData {
+            name: "api_host".to_string(),
+            value: "${apiHost}".to_string(),
+        });
+
+        let dir = std::env::temp_dir().join("ndk-build-manifest-placeholders");
+        std::fs::create_dir_all(&dir).unwrap();
+        let placeholders = [("appName", "Foo & Bar"), ("apiHost", "staging.example.com")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        manifest
+            .write_to_with_placeholders(&dir, &placeholders)
+            .unwrap();
+
+        let xml = std::fs::read_to_string(dir.join("AndroidManifest.xml")).unwrap();
+        assert!(xml.contains(r#"android:label="Foo &amp; Bar""#));
+        assert!(xml.contains(r#"android:value="staging.example.com""#));
+        assert!(!xml.contains("${"));
+    }
+
+    #[test]
+    fn launcher_defaults_export_activity_on_s() {
+        let mut manifest = AndroidManifest::default();
+        manifest.sdk.target_sdk_version = Some(31);
+        manifest.add_launcher_defaults();
+        let xml = quick_xml::se::to_string(&manifest).unwrap();
+        assert!(xml.contains(r#"android:exported="true""#));
+        assert!(xml.contains(r#"<category android:name="android.intent.category.LAUNCHER"/>"#));
+        assert!(!xml.contains("launcher="));
+
+        let mut manifest = AndroidManifest::default();
+        manifest.sdk.target_sdk_version = Some(31);
+        manifest.application.activity.exported = Some(false);
+        manifest.add_launcher_defaults();
+        let xml = quick_xml::se::to_string(&manifest).unwrap();
+        assert!(xml.contains(r#"android:exported="false""#));
+
+        let mut manifest = AndroidManifest::default();
+        manifest.sdk.target_sdk_version = Some(30);
+        manifest.application.activity.launcher = false;
+        manifest.add_launcher_defaults();
+        let xml = quick_xml::se::to_string(&manifest).unwrap();
+        assert!(!xml.contains("android:exported"));
+        assert!(!xml.contains("<intent-filter"));
+    }
+
+    #[test]
+    fn application_app_category() {
+        let mut manifest = AndroidManifest::default();
+        manifest.application.round_icon = Some("@mipmap/ic_launcher_round".to_string());
+        manifest.application.app_category = Some(AppCategory::Game);
+
+        let xml = quick_xml::se::to_string(&manifest).unwrap();
+        assert!(xml.contains(r#"android:roundIcon="@mipmap/ic_launcher_round""#));
+        assert!(xml.contains(r#"android:appCategory="game""#));
+    }
+
+    #[test]
+    fn application_name_and_activity_theme() {
+        let xml = quick_xml::se::to_string(&AndroidManifest::default()).unwrap();
+        assert!(!xml.contains("android:name=\"com.example.App\""));
+        assert!(!xml.contains("android:theme"));
+
+        let mut manifest = AndroidManifest::default();
+        manifest.application.name = Some("com.example.App".to_string());
+        manifest.application.activity.theme = Some("@style/SplashTheme".to_string());
+        let xml = quick_xml::se::to_string(&manifest).unwrap();
+        assert!(xml.contains(r#"<application android:name="com.example.App""#));
+        assert!(xml.contains(r#"android:theme="@style/SplashTheme""#));
+    }
+
+    #[test]
+    fn intent_filter_with_multiple_data() {
+        let filter = IntentFilter {
+            auto_verify: Some(true),
+            priority: None,
+            actions: vec!["android.intent.action.VIEW".to_string()],
+            categories: vec![
+                "android.intent.category.DEFAULT".to_string(),
+                "android.intent.category.BROWSABLE".to_string(),
+            ],
+            data: vec![
+                IntentFilterData {
+                    scheme: Some("https".to_string()),
+                    host: Some("example.com".to_string()),
+                    path_prefix: Some("/app".to_string()),
+                    ..Default::default()
+                },
+                IntentFilterData {
+                    scheme: Some("myapp".to_string()),
+                    ..Default::default()
+                },
+            ],
+        };
+        let mut manifest = AndroidManifest::default();
+        manifest.application.activity.intent_filter.push(filter);
+
+        let xml = quick_xml::se::to_string(&manifest).unwrap();
+        assert!(xml.contains(
+            "<intent-filter android:autoVerify=\"true\">\
+             <action android:name=\"android.intent.action.VIEW\"/>\
+             <category android:name=\"android.intent.category.DEFAULT\"/>\
+             <category android:name=\"android.intent.category.BROWSABLE\"/>\
+             <data android:scheme=\"https\" android:host=\"example.com\" android:pathPrefix=\"/app\"/>\
+             <data android:scheme=\"myapp\"/>\
+             </intent-filter>"
+        ));
+    }
+
+    #[test]
+    fn intent_filter_with_multiple_actions_and_priority() {
+        let filter = IntentFilter {
+            priority: Some(-5),
+            actions: vec![
+                "android.intent.action.SEND".to_string(),
+                "android.intent.action.SEND_MULTIPLE".to_string(),
+            ],
+            categories: vec!["android.intent.category.DEFAULT".to_string()],
+            data: vec![IntentFilterData {
+                mime_type: Some("image/*".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut manifest = AndroidManifest::default();
+        manifest.application.activity.intent_filter.push(filter);
+
+        let xml = quick_xml::se::to_string(&manifest).unwrap();
+        assert!(xml.contains(
+            "<intent-filter android:priority=\"-5\">\
+             <action android:name=\"android.intent.action.SEND\"/>\
+             <action android:name=\"android.intent.action.SEND_MULTIPLE\"/>\
+             <category android:name=\"android.intent.category.DEFAULT\"/>\
+             <data android:mimeType=\"image/*\"/>\
+             </intent-filter>"
+        ));
+    }
+}
@@ -1,21 +1,139 @@
+use crate::apk::{install_multiple_args, InstallOptions};
 use crate::error::NdkError;
 use crate::target::Target;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 /// The default password used when creating the default `debug.keystore` via
 /// [`Ndk::debug_key`]
 pub const DEFAULT_DEV_KEYSTORE_PASSWORD: &str = "android";
 
+/// A package of the Android SDK, that can be installed with `sdkmanager`
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum SdkComponent {
+    /// `platforms;android-<api>`, which provides `android.jar`
+    Platform(u32),
+    /// `build-tools;<version>`, which provides `aapt`, `d8`, `zipalign` and `apksigner`
+    BuildTools(String),
+    /// `platform-tools`, which provides `adb`
+    PlatformTools,
+}
+
+impl SdkComponent {
+    /// The package path passed to `sdkmanager`
+    pub fn sdkmanager_package(&self) -> String {
+        match self {
+            Self::Platform(api) => format!("platforms;android-{}", api),
+            Self::BuildTools(version) => format!("build-tools;{}", version),
+            Self::PlatformTools => "platform-tools".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for SdkComponent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Platform(api) => write!(f, "Platform `android-{}`", api),
+            Self::BuildTools(version) => write!(f, "Build tools `{}`", version),
+            Self::PlatformTools => write!(f, "Platform tools"),
+        }
+    }
+}
+
+/// Features of the build tools that are not available in every version
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BuildToolsCapability {
+    /// `apksigner sign --v3-signing-enabled`
+    ApksignerV3Signing,
+    /// `apksigner sign --v4-signing-enabled`
+    ApksignerV4Signing,
+    /// `zipalign -P <pagesize_kb>`
+    ZipalignPageAlignment,
+}
+
+impl BuildToolsCapability {
+    fn tool(self) -> &'static str {
+        match self {
+            Self::ApksignerV3Signing | Self::ApksignerV4Signing => bat!("apksigner"),
+            Self::ZipalignPageAlignment => bin!("zipalign"),
+        }
+    }
+
+    /// Arguments that make the tool print the usage text documenting this capability
+    fn help_args(self) -> &'static [&'static str] {
+        match self {
+            Self::ApksignerV3Signing | Self::ApksignerV4Signing => &["sign", "--help"],
+            // Prints its usage when invoked without arguments
+            Self::ZipalignPageAlignment => &[],
+        }
+    }
+
+    fn flag(self) -> &'static str {
+        match self {
+            Self::ApksignerV3Signing => "--v3-signing-enabled",
+            Self::ApksignerV4Signing => "--v4-signing-enabled",
+            Self::ZipalignPageAlignment => "-P <pagesize_kb>",
+        }
+    }
+
+    /// First build-tools release providing this capability
+    pub fn min_build_tools_version(self) -> &'static str {
+        match self {
+            Self::ApksignerV3Signing => "28.0.0",
+            Self::ApksignerV4Signing => "30.0.0",
+            Self::ZipalignPageAlignment => "35.0.0",
+        }
+    }
+}
+
+/// Oldest NDK release with the unified LLVM toolchain layout that [`Ndk`] locates tools in
+pub const MIN_NDK_VERSION: u32 = 19;
+
+/// `Pkg.Revision` of an NDK, as read from its `source.properties`
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct NdkVersion {
+    /// Release number, e.g. `25` for r25
+    pub major: u32,
+    /// Revision letter of the release, e.g. `2` for r25c
+    pub minor: u32,
+    /// AOSP build number, which increases across releases
+    pub build: u32,
+}
+
+impl std::fmt::Display for NdkVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
+
+/// Results of [`Ndk::supports`], shared between clones. Does not partake in comparisons.
+#[derive(Clone, Debug, Default)]
+struct CapabilityCache(Arc<Mutex<HashMap<BuildToolsCapability, bool>>>);
+
+impl PartialEq for CapabilityCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for CapabilityCache {}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Ndk {
     sdk_path: PathBuf,
     user_home: PathBuf,
     ndk_path: PathBuf,
     build_tools_version: String,
-    build_tag: u32,
+    version: NdkVersion,
     platforms: Vec<u32>,
+    adb_env: BTreeMap<String, String>,
+    capabilities: CapabilityCache,
 }
 
 impl Ndk {
@@ -75,40 +193,18 @@ impl Ndk {
             }
         };
 
-        let build_tools_dir = sdk_path.join("build-tools");
-        let build_tools_version = std::fs::read_dir(&build_tools_dir)
-            .or(Err(NdkError::PathNotFound(build_tools_dir)))?
-            .filter_map(|path| path.ok())
-            .filter(|path| path.path().is_dir())
-            .filter_map(|path| path.file_name().into_string().ok())
-            .filter(|name| name.chars().next().unwrap().is_ascii_digit())
-            .max()
-            .ok_or(NdkError::BuildToolsNotFound)?;
+        let build_tools_version =
+            list_build_tools(&sdk_path)?
+                .pop()
+                .ok_or(NdkError::BuildToolsNotFound {
+                    requested: None,
+                    available: Vec::new(),
+                })?;
 
-        let build_tag = std::fs::read_to_string(ndk_path.join("source.properties"))
+        let source_properties = std::fs::read_to_string(ndk_path.join("source.properties"))
             .expect("Failed to read source.properties");
-
-        let build_tag = build_tag
-            .split('\n')
-            .find_map(|line| {
-                let (key, value) = line
-                    .split_once('=')
-                    .expect("Failed to parse `key = value` from source.properties");
-                if key.trim() == "Pkg.Revision" {
-                    // AOSP writes a constantly-incrementing build version to the patch field.
-                    // This number is incrementing across NDK releases.
-                    let mut parts = value.trim().split('.');
-                    let _major = parts.next().unwrap();
-                    let _minor = parts.next().unwrap();
-                    let patch = parts.next().unwrap();
-                    // Can have an optional `XXX-beta1`
-                    let patch = patch.split_once('-').map_or(patch, |(patch, _beta)| patch);
-                    Some(patch.parse().expect("Failed to parse patch field"))
-                } else {
-                    None
-                }
-            })
-            .expect("No `Pkg.Revision` in source.properties");
+        let version = parse_ndk_version(&source_properties)
+            .expect("No valid `Pkg.Revision` in source.properties");
 
         let ndk_platforms = std::fs::read_to_string(ndk_path.join("build/core/platforms.mk"))?;
         let ndk_platforms = ndk_platforms
@@ -145,8 +241,10 @@ impl Ndk {
             user_home,
             ndk_path,
             build_tools_version,
-            build_tag,
+            version,
             platforms,
+            adb_env: BTreeMap::new(),
+            capabilities: CapabilityCache::default(),
         })
     }
 
@@ -162,8 +260,42 @@ impl Ndk {
         &self.build_tools_version
     }
 
+    /// Selects the build tools `version` instead of the latest installed version, to not pick up
+    /// changes in behavior when the SDK is updated. Fails with [`NdkError::BuildToolsNotFound`]
+    /// listing the installed versions if `version` is not installed.
+    pub fn set_build_tools_version(&mut self, version: &str) -> Result<(), NdkError> {
+        let available = list_build_tools(&self.sdk_path)?;
+        if !available.iter().any(|v| v == version) {
+            return Err(NdkError::BuildToolsNotFound {
+                requested: Some(version.to_owned()),
+                available,
+            });
+        }
+        self.build_tools_version = version.to_owned();
+        // Capabilities were probed with the previous build tools
+        self.capabilities = CapabilityCache::default();
+        Ok(())
+    }
+
     pub fn build_tag(&self) -> u32 {
-        self.build_tag
+        self.version.build
+    }
+
+    /// Version of the NDK, from `Pkg.Revision` in its `source.properties`
+    pub fn version(&self) -> NdkVersion {
+        self.version
+    }
+
+    /// Returns [`NdkError::UnsupportedNdkVersion`] if the NDK is older than release `min_major`.
+    pub fn require_version(&self, min_major: u32) -> Result<(), NdkError> {
+        if self.version.major < min_major {
+            Err(NdkError::UnsupportedNdkVersion {
+                found: self.version,
+                required: min_major,
+            })
+        } else {
+            Ok(())
+        }
     }
 
     pub fn platforms(&self) -> &[u32] {
@@ -177,15 +309,66 @@ impl Ndk {
             .join(&self.build_tools_version)
             .join(tool);
         if !path.exists() {
-            return Err(NdkError::CmdNotFound(tool.to_string()));
+            return Err(NdkError::SdkComponentNotFound {
+                component: SdkComponent::BuildTools(self.build_tools_version.clone()),
+                path,
+            });
         }
         Ok(Command::new(dunce::canonicalize(path)?))
     }
 
+    /// Sets an environment variable, such as `ADB_SERVER_SOCKET` or `HTTP_PROXY`, on every `adb`
+    /// command spawned through this [`Ndk`], including those of [`crate::apk::Apk`] and `ndk-gdb`.
+    pub fn set_adb_env(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.adb_env.insert(key.into(), value.into());
+    }
+
+    pub fn adb_env(&self) -> &BTreeMap<String, String> {
+        &self.adb_env
+    }
+
+    /// Detects whether the selected build tools support `capability`, by inspecting the usage
+    /// text of the tool. The result is cached.
+    pub fn supports(&self, capability: BuildToolsCapability) -> Result<bool, NdkError> {
+        if let Some(&supported) = self.capabilities.0.lock().unwrap().get(&capability) {
+            return Ok(supported);
+        }
+
+        let mut tool = self.build_tool(capability.tool())?;
+        // The exit code is irrelevant, some tools exit with an error after printing their usage
+        let output = tool.args(capability.help_args()).output()?;
+        let supported = String::from_utf8_lossy(&output.stdout).contains(capability.flag())
+            || String::from_utf8_lossy(&output.stderr).contains(capability.flag());
+
+        self.capabilities
+            .0
+            .lock()
+            .unwrap()
+            .insert(capability, supported);
+        Ok(supported)
+    }
+
+    /// Returns [`NdkError::UnsupportedBuildTools`] if `capability` is not supported by the
+    /// selected build tools.
+    pub fn require(&self, capability: BuildToolsCapability) -> Result<(), NdkError> {
+        if self.supports(capability)? {
+            Ok(())
+        } else {
+            Err(NdkError::UnsupportedBuildTools {
+                capability,
+                required: capability.min_build_tools_version(),
+                found: self.build_tools_version.clone(),
+            })
+        }
+    }
+
     pub fn platform_tool_path(&self, tool: &str) -> Result<PathBuf, NdkError> {
         let path = self.sdk_path.join("platform-tools").join(tool);
         if !path.exists() {
-            return Err(NdkError::CmdNotFound(tool.to_string()));
+            return Err(NdkError::SdkComponentNotFound {
+                component: SdkComponent::PlatformTools,
+                path,
+            });
         }
         Ok(dunce::canonicalize(path)?)
     }
@@ -216,7 +399,10 @@ impl Ndk {
             .join("platforms")
             .join(format!("android-{}", platform));
         if !dir.exists() {
-            return Err(NdkError::PlatformNotFound(platform));
+            return Err(NdkError::SdkComponentNotFound {
+                component: SdkComponent::Platform(platform),
+                path: dir,
+            });
         }
         Ok(dir)
     }
@@ -224,7 +410,10 @@ impl Ndk {
     pub fn android_jar(&self, platform: u32) -> Result<PathBuf, NdkError> {
         let android_jar = self.platform_dir(platform)?.join("android.jar");
         if !android_jar.exists() {
-            return Err(NdkError::PathNotFound(android_jar));
+            return Err(NdkError::SdkComponentNotFound {
+                component: SdkComponent::Platform(platform),
+                path: android_jar,
+            });
         }
         Ok(android_jar)
     }
@@ -329,6 +518,17 @@ impl Ndk {
         }
     }
 
+    /// Resolves `objcopy`, preferring `llvm-objcopy` over the target-prefixed GNU binutils, which
+    /// are absent from NDK r23 onwards.
+    pub fn objcopy_bin(&self, target: Target) -> Result<PathBuf, NdkError> {
+        llvm_or_gnu_bin(&self.toolchain_dir()?.join("bin"), "objcopy", target)
+    }
+
+    /// Resolves `strip`, preferring `llvm-strip` over the target-prefixed GNU binutils.
+    pub fn strip_bin(&self, target: Target) -> Result<PathBuf, NdkError> {
+        llvm_or_gnu_bin(&self.toolchain_dir()?.join("bin"), "strip", target)
+    }
+
     pub fn prebuilt_dir(&self) -> Result<PathBuf, NdkError> {
         let arch = Self::host_arch()?;
         let prebuilt_dir = self
@@ -362,6 +562,7 @@ impl Ndk {
         }
 
         ndk_gdb
+            .envs(&self.adb_env)
             .arg("--adb")
             .arg(self.adb_path()?)
             .arg("--launch")
@@ -378,48 +579,17 @@ impl Ndk {
     }
 
     pub fn keytool(&self) -> Result<Command, NdkError> {
-        if let Ok(keytool) = which::which(bin!("keytool")) {
-            return Ok(Command::new(keytool));
-        }
-        if let Ok(java) = std::env::var("JAVA_HOME") {
-            let keytool = PathBuf::from(java).join("bin").join(bin!("keytool"));
-            if keytool.exists() {
-                return Ok(Command::new(keytool));
-            }
-        }
-        Err(NdkError::CmdNotFound("keytool".to_string()))
+        keytool()
     }
 
-    pub fn debug_key(&self) -> Result<Key, NdkError> {
-        let path = self.android_user_home()?.join("debug.keystore");
-        let password = DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned();
+    pub fn jarsigner(&self) -> Result<Command, NdkError> {
+        jdk_tool("jarsigner")
+    }
 
-        if !path.exists() {
-            let mut keytool = self.keytool()?;
-            keytool
-                .arg("-genkey")
-                .arg("-v")
-                .arg("-keystore")
-                .arg(&path)
-                .arg("-storepass")
-                .arg(&password)
-                .arg("-alias")
-                .arg("androiddebugkey")
-                .arg("-keypass")
-                .arg(&password)
-                .arg("-dname")
-                .arg("CN=Android Debug,O=Android,C=US")
-                .arg("-keyalg")
-                .arg("RSA")
-                .arg("-keysize")
-                .arg("2048")
-                .arg("-validity")
-                .arg("10000");
-            if !keytool.status()?.success() {
-                return Err(NdkError::CmdFailed(keytool));
-            }
-        }
-        Ok(Key { path, password })
+    /// Retrieves the standard `debug.keystore` in [`Ndk::android_user_home`], generating it if
+    /// missing, see [`Key::debug`].
+    pub fn debug_key(&self) -> Result<Key, NdkError> {
+        Key::debug(&self.android_user_home()?.join("debug.keystore"))
     }
 
     pub fn sysroot_lib_dir(&self, target: Target) -> Result<PathBuf, NdkError> {
@@ -478,8 +648,331 @@ impl Ndk {
         Target::from_android_abi(abi.trim())
     }
 
+    /// Reads a system property of the device with `getprop`, failing with
+    /// [`NdkError::NoDevice`] when no (matching) device is connected and with
+    /// [`NdkError::EmptyDeviceProperty`] when the property is not set.
+    pub fn getprop(&self, device_serial: Option<&str>, property: &str) -> Result<String, NdkError> {
+        let mut adb = self.adb(device_serial)?;
+        adb.arg("shell")
+            .arg("getprop")
+            .arg(property)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = adb.output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if is_no_device(&stderr) {
+                return Err(NdkError::NoDevice(stderr.trim().to_owned()));
+            }
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        if value.is_empty() {
+            return Err(NdkError::EmptyDeviceProperty(property.to_owned()));
+        }
+        Ok(value)
+    }
+
+    /// Waits with `adb wait-for-device` until the device is connected, and then polls
+    /// `sys.boot_completed` until it has finished booting, failing with [`NdkError::Timeout`]
+    /// when that takes longer than `timeout`. The package manager is not ready to install
+    /// packages before booting has completed.
+    pub fn wait_for_device(
+        &self,
+        device_serial: Option<&str>,
+        timeout: Duration,
+    ) -> Result<(), NdkError> {
+        let start = Instant::now();
+        let mut adb = self.adb(device_serial)?;
+        adb.arg("wait-for-device");
+        if !run_with_timeout(&mut adb, Some(timeout))?.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+
+        let property = "sys.boot_completed";
+        loop {
+            match self.getprop(device_serial, property) {
+                Ok(value) if value == "1" => return Ok(()),
+                // Not set until booting has completed
+                Ok(_) | Err(NdkError::EmptyDeviceProperty(_)) => {}
+                Err(err) => return Err(err),
+            }
+            if start.elapsed() >= timeout {
+                return Err(NdkError::Timeout {
+                    cmd: format!("adb shell getprop {}", property),
+                    timeout,
+                });
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+
+    /// Retrieves the API level of the device from `ro.build.version.sdk`.
+    pub fn device_sdk(&self, device_serial: Option<&str>) -> Result<u32, NdkError> {
+        let property = "ro.build.version.sdk";
+        let value = self.getprop(device_serial, property)?;
+        value.parse().map_err(|_| NdkError::InvalidDeviceProperty {
+            property: property.to_owned(),
+            value,
+        })
+    }
+
+    /// Retrieves the ABIs supported by the device from `ro.product.cpu.abilist`, in order of
+    /// preference.
+    pub fn device_abilist(&self, device_serial: Option<&str>) -> Result<Vec<String>, NdkError> {
+        let abilist = self.getprop(device_serial, "ro.product.cpu.abilist")?;
+        Ok(parse_abilist(&abilist))
+    }
+
+    /// Lists the names of installed packages through `pm list packages`, optionally only those
+    /// that start with `prefix` and/or are third-party (`-3`) packages.
+    pub fn list_packages(
+        &self,
+        device_serial: Option<&str>,
+        prefix: Option<&str>,
+        third_party_only: bool,
+    ) -> Result<Vec<String>, NdkError> {
+        let mut adb = self.adb(device_serial)?;
+        adb.arg("shell").arg("pm").arg("list").arg("packages");
+        if third_party_only {
+            adb.arg("-3");
+        }
+        if let Some(prefix) = prefix {
+            // `pm` filters on substrings, narrow it down to prefixes below
+            adb.arg(prefix);
+        }
+        let output = adb.output()?;
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+
+        let output = String::from_utf8_lossy(&output.stdout);
+        Ok(parse_package_list(&output)
+            .filter(|package| prefix.map_or(true, |prefix| package.starts_with(prefix)))
+            .map(str::to_owned)
+            .collect())
+    }
+
+    /// Retrieves the ids of the users (including work profiles) on the device through
+    /// `pm list users`.
+    pub fn users(&self, device_serial: Option<&str>) -> Result<Vec<u32>, NdkError> {
+        let mut adb = self.adb(device_serial)?;
+        adb.arg("shell").arg("pm").arg("list").arg("users");
+        let output = adb.output()?;
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        Ok(parse_users(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Returns [`NdkError::UserNotFound`] if `user` does not exist on the device, see
+    /// [`Ndk::users`].
+    pub fn require_user(&self, device_serial: Option<&str>, user: u32) -> Result<(), NdkError> {
+        if self.users(device_serial)?.contains(&user) {
+            Ok(())
+        } else {
+            Err(NdkError::UserNotFound(user))
+        }
+    }
+
+    /// Installs `apks` as a single package with `adb install-multiple`, replacing an existing
+    /// installation, e.g. a base APK together with the per-ABI APKs of
+    /// [`ApkConfig::build_split_apks`](crate::apk::ApkConfig::build_split_apks). Either all APKs
+    /// are installed or none, in which case [`NdkError::InstallMultipleFailed`] holds the reason
+    /// reported by the package manager.
+    ///
+    /// [`InstallOptions::clear_data`] is not supported, use [`Apk::clear_data`](crate::apk::Apk::clear_data)
+    /// afterwards instead.
+    pub fn install_multiple(
+        &self,
+        apks: &[&Path],
+        device_serial: Option<&str>,
+        options: &InstallOptions,
+    ) -> Result<(), NdkError> {
+        if let Some(missing) = apks.iter().find(|apk| !apk.exists()) {
+            return Err(NdkError::PathNotFound(missing.to_path_buf()));
+        }
+        if options.clear_data {
+            eprintln!("Warning: not clearing data, which `install_multiple()` does not support");
+        }
+        if let Some(user) = options.user {
+            self.require_user(device_serial, user)?;
+        }
+        let mut adb = self.adb(device_serial)?;
+        install_multiple_args(&mut adb, options, apks);
+        let output = adb.output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // Older `adb` versions exit successfully while printing `Failure [...]`
+        if !output.status.success() || stdout.contains("Failure") {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(NdkError::InstallMultipleFailed {
+                apks: apks.iter().map(|apk| apk.to_path_buf()).collect(),
+                output: format!("{}{}", stdout, stderr).trim().to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Uninstalls `package` through `adb uninstall`.
+    pub fn uninstall(&self, device_serial: Option<&str>, package: &str) -> Result<(), NdkError> {
+        self.uninstall_keeping_data(device_serial, package, false)
+    }
+
+    /// Same as [`Ndk::uninstall`], but retains the data and cache directories of `package` when
+    /// `keep_data` is set (`-k`).
+    pub fn uninstall_keeping_data(
+        &self,
+        device_serial: Option<&str>,
+        package: &str,
+        keep_data: bool,
+    ) -> Result<(), NdkError> {
+        self.uninstall_for_user(device_serial, package, keep_data, None)
+    }
+
+    /// Same as [`Ndk::uninstall_keeping_data`], but only uninstalls `package` for `user`
+    /// (`--user`) when set, which must exist on the device.
+    pub fn uninstall_for_user(
+        &self,
+        device_serial: Option<&str>,
+        package: &str,
+        keep_data: bool,
+        user: Option<u32>,
+    ) -> Result<(), NdkError> {
+        if let Some(user) = user {
+            self.require_user(device_serial, user)?;
+        }
+        let mut adb = self.adb(device_serial)?;
+        uninstall_args(&mut adb, package, keep_data, user);
+        let output = adb.output()?;
+        // Older `adb` versions exit successfully while printing `Failure [...]`
+        if !output.status.success() || String::from_utf8_lossy(&output.stdout).contains("Failure") {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        Ok(())
+    }
+
+    /// Uninstalls every package in `packages` that is installed, for example all flavors of an
+    /// app, and returns the outcome for each package in the same order.
+    pub fn uninstall_all(
+        &self,
+        device_serial: Option<&str>,
+        packages: &[&str],
+    ) -> Result<Vec<UninstallResult>, NdkError> {
+        let installed = self.list_packages(device_serial, None, false)?;
+        Ok(packages
+            .iter()
+            .map(|&package| {
+                let status = if installed.iter().any(|p| p == package) {
+                    self.uninstall(device_serial, package)
+                        .map(|()| UninstallStatus::Uninstalled)
+                } else {
+                    Ok(UninstallStatus::NotInstalled)
+                };
+                UninstallResult {
+                    package: package.to_owned(),
+                    status,
+                }
+            })
+            .collect())
+    }
+
+    fn input(&self, device_serial: Option<&str>, args: &[&str]) -> Result<(), NdkError> {
+        let mut adb = self.adb(device_serial)?;
+        adb.arg("shell").arg("input").args(args);
+        if !adb.status()?.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        Ok(())
+    }
+
+    /// Types `text` into the focused view through `adb shell input text`.
+    pub fn input_text(&self, device_serial: Option<&str>, text: &str) -> Result<(), NdkError> {
+        self.input(device_serial, &["text", &escape_input_text(text)])
+    }
+
+    /// Sends a key event through `adb shell input keyevent`, either by name such as
+    /// `KEYCODE_BACK` or by its numeric code.
+    pub fn input_keyevent(&self, device_serial: Option<&str>, key: &str) -> Result<(), NdkError> {
+        self.input(device_serial, &["keyevent", key])
+    }
+
+    /// Taps the screen at the given pixel coordinates through `adb shell input tap`.
+    pub fn input_tap(&self, device_serial: Option<&str>, x: u32, y: u32) -> Result<(), NdkError> {
+        self.input(device_serial, &["tap", &x.to_string(), &y.to_string()])
+    }
+
+    /// Swipes from `from` to `to` (pixel coordinates) over `duration` through
+    /// `adb shell input swipe`.
+    pub fn input_swipe(
+        &self,
+        device_serial: Option<&str>,
+        from: (u32, u32),
+        to: (u32, u32),
+        duration: std::time::Duration,
+    ) -> Result<(), NdkError> {
+        self.input(
+            device_serial,
+            &[
+                "swipe",
+                &from.0.to_string(),
+                &from.1.to_string(),
+                &to.0.to_string(),
+                &to.1.to_string(),
+                &duration.as_millis().to_string(),
+            ],
+        )
+    }
+
+    /// Lists the devices known to `adb`, including those that are offline or unauthorized, as
+    /// reported by `adb devices -l`.
+    pub fn devices(&self) -> Result<Vec<Device>, NdkError> {
+        let mut adb = self.adb(None)?;
+        adb.arg("devices").arg("-l");
+        let output = adb.output()?;
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(adb)));
+        }
+        Ok(parse_devices(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Connects to a device over the network, such as one with wireless debugging enabled, through
+    /// `adb connect <address>`. Returns the serial of the device, which is `address` including the
+    /// port that `adb` defaults to.
+    pub fn connect(&self, address: &str) -> Result<String, NdkError> {
+        let mut adb = self.adb(None)?;
+        adb.arg("connect").arg(address);
+        let output = adb.output()?;
+        // `adb` versions differ in whether failures exit with an error, only trust the output
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        parse_connect(&stdout).ok_or_else(|| NdkError::ConnectFailed {
+            address: address.to_owned(),
+            output: format!("{}{}", stdout, String::from_utf8_lossy(&output.stderr))
+                .trim()
+                .to_owned(),
+        })
+    }
+
+    /// Disconnects from a device connected with [`Ndk::connect`].
+    pub fn disconnect(&self, address: &str) -> Result<(), NdkError> {
+        let mut adb = self.adb(None)?;
+        adb.arg("disconnect").arg(address);
+        let output = adb.output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if output.status.success() && stdout.trim_start().starts_with("disconnected") {
+            Ok(())
+        } else {
+            Err(NdkError::DisconnectFailed {
+                address: address.to_owned(),
+                output: format!("{}{}", stdout, String::from_utf8_lossy(&output.stderr))
+                    .trim()
+                    .to_owned(),
+            })
+        }
+    }
+
     pub fn adb(&self, device_serial: Option<&str>) -> Result<Command, NdkError> {
         let mut adb = Command::new(self.adb_path()?);
+        adb.envs(&self.adb_env);
 
         if let Some(device_serial) = device_serial {
             adb.arg("-s").arg(device_serial);
@@ -489,15 +982,638 @@ impl Ndk {
     }
 }
 
+/// Versions installed in the `build-tools` directory of the SDK at `sdk_path`, from oldest to
+/// newest.
+fn list_build_tools(sdk_path: &Path) -> Result<Vec<String>, NdkError> {
+    let build_tools_dir = sdk_path.join("build-tools");
+    let mut versions = std::fs::read_dir(&build_tools_dir)
+        .or(Err(NdkError::PathNotFound(build_tools_dir)))?
+        .filter_map(|path| path.ok())
+        .filter(|path| path.path().is_dir())
+        .filter_map(|path| path.file_name().into_string().ok())
+        .filter(|name| name.starts_with(|c: char| c.is_ascii_digit()))
+        .collect::<Vec<_>>();
+    // Compare numerically, so that 35.0.0 is newer than 9.0.0, and order release candidates
+    // (`35.0.0-rc1`) before the release
+    versions.sort_by_cached_key(|version| {
+        let (release, pre_release) = match version.split_once('-') {
+            Some((release, pre_release)) => (release, Some(pre_release.to_owned())),
+            None => (version.as_str(), None),
+        };
+        let numbers = release
+            .split('.')
+            .map(|part| part.parse::<u32>().unwrap_or(0))
+            .collect::<Vec<_>>();
+        (numbers, pre_release.is_none(), pre_release)
+    });
+    Ok(versions)
+}
+
+/// Parses `Pkg.Revision = <major>.<minor>.<build>[-beta<n>]` from the contents of an NDK's
+/// `source.properties`.
+fn parse_ndk_version(source_properties: &str) -> Option<NdkVersion> {
+    let revision = source_properties.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == "Pkg.Revision").then(|| value.trim())
+    })?;
+    let mut parts = revision.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    // AOSP writes a constantly-incrementing build version to the patch field, which can have an
+    // optional `-beta1` suffix
+    let build = parts.next()?;
+    let build = build.split_once('-').map_or(build, |(build, _beta)| build);
+    Some(NdkVersion {
+        major,
+        minor,
+        build: build.parse().ok()?,
+    })
+}
+
+/// Extracts the serial from the output of a successful `adb connect`, which is either
+/// `connected to <serial>` or `already connected to <serial>`.
+fn parse_connect(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let line = line.trim();
+        line.strip_prefix("already connected to ")
+            .or_else(|| line.strip_prefix("connected to "))
+            .map(str::to_owned)
+    })
+}
+
+/// Whether the stderr of a failed `adb` command reports that no (matching) device is connected.
+fn is_no_device(stderr: &str) -> bool {
+    stderr.contains("no devices/emulators found")
+        || (stderr.contains("device '") && stderr.contains("' not found"))
+        || stderr.contains("more than one device/emulator")
+}
+
+/// Splits the comma-separated `ro.product.cpu.abilist`.
+fn parse_abilist(abilist: &str) -> Vec<String> {
+    abilist
+        .split(',')
+        .map(str::trim)
+        .filter(|abi| !abi.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// State of a [`Device`] as reported by `adb devices`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceState {
+    /// Connected and ready to use
+    Device,
+    /// Not connected or not responding
+    Offline,
+    /// The debugging authorization prompt on the device has not been accepted
+    Unauthorized,
+    /// Any other state, such as `recovery` or `sideload`
+    Other(String),
+}
+
+/// A device listed by [`Ndk::devices`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Device {
+    /// Serial to pass as `device_serial` to select this device
+    pub serial: String,
+    pub state: DeviceState,
+    pub model: Option<String>,
+    pub product: Option<String>,
+}
+
+/// Parses the output of `adb devices -l`.
+fn parse_devices(output: &str) -> Vec<Device> {
+    output
+        .lines()
+        // Skip the header and `* daemon started successfully` messages
+        .filter(|line| !line.starts_with("List of devices") && !line.starts_with('*'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let serial = fields.next()?.to_owned();
+            let state = match fields.next()? {
+                "device" => DeviceState::Device,
+                "offline" => DeviceState::Offline,
+                "unauthorized" => DeviceState::Unauthorized,
+                other => DeviceState::Other(other.to_owned()),
+            };
+            let mut device = Device {
+                serial,
+                state,
+                model: None,
+                product: None,
+            };
+            for field in fields {
+                if let Some(model) = field.strip_prefix("model:") {
+                    device.model = Some(model.to_owned());
+                } else if let Some(product) = field.strip_prefix("product:") {
+                    device.product = Some(product.to_owned());
+                }
+            }
+            Some(device)
+        })
+        .collect()
+}
+
+/// Outcome of uninstalling a single package through [`Ndk::uninstall_all`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UninstallStatus {
+    Uninstalled,
+    /// The package was not installed, so nothing was done
+    NotInstalled,
+}
+
+/// Result of uninstalling `package` through [`Ndk::uninstall_all`]
+#[derive(Debug)]
+pub struct UninstallResult {
+    pub package: String,
+    pub status: Result<UninstallStatus, NdkError>,
+}
+
+/// How a keystore or key password is handed to `apksigner` and `keytool`.
+///
+/// Prefer [`KeyPassword::Env`], [`KeyPassword::File`] or [`KeyPassword::Stdin`]:
+/// [`KeyPassword::Plaintext`] passes the password on the command line, where it is visible to
+/// every other process on the host (e.g. through `ps`).
+#[derive(Clone, Eq, PartialEq)]
+pub enum KeyPassword {
+    /// The password itself, passed on the command line
+    Plaintext(String),
+    /// Name of an environment variable holding the password
+    Env(String),
+    /// Path of a file whose first line is the password
+    File(PathBuf),
+    /// The password, written to the standard input of the tool
+    Stdin(String),
+}
+
+impl KeyPassword {
+    /// Parses a password as written in a configuration file: `env:<NAME>` refers to an
+    /// environment variable and `file:<PATH>` to a file, anything else is the password itself.
+    pub fn parse(spec: &str) -> Self {
+        if let Some(name) = spec.strip_prefix("env:") {
+            Self::Env(name.to_owned())
+        } else if let Some(path) = spec.strip_prefix("file:") {
+            Self::File(path.into())
+        } else {
+            Self::Plaintext(spec.to_owned())
+        }
+    }
+
+    /// Reads the environment variable of a [`KeyPassword::Env`], turning it into a
+    /// [`KeyPassword::Stdin`] so that the password never ends up on a command line.
+    pub(crate) fn resolve_env(&self) -> Result<Self, NdkError> {
+        match self {
+            Self::Env(name) => std::env::var(name)
+                .map(Self::Stdin)
+                .map_err(|_| NdkError::MissingEnvVar { name: name.clone() }),
+            password => Ok(password.clone()),
+        }
+    }
+
+    /// The password specifier understood by `apksigner`'s `--ks-pass` and `--key-pass`
+    pub(crate) fn apksigner_arg(&self) -> OsString {
+        match self {
+            Self::Plaintext(password) => format!("pass:{}", password).into(),
+            Self::Env(var) => format!("env:{}", var).into(),
+            Self::File(path) => {
+                let mut arg = OsString::from("file:");
+                arg.push(path);
+                arg
+            }
+            Self::Stdin(_) => "stdin".into(),
+        }
+    }
+
+    /// Appends `option` (e.g. `-storepass`) with this password to a `keytool` or `jarsigner`
+    /// invocation, returning the password to write to its standard input, if any.
+    pub(crate) fn jdk_tool_arg<'a>(
+        &'a self,
+        keytool: &mut Command,
+        option: &str,
+    ) -> Option<&'a str> {
+        match self {
+            Self::Plaintext(password) => {
+                keytool.arg(option).arg(password);
+            }
+            Self::Env(var) => {
+                keytool.arg(format!("{}:env", option)).arg(var);
+            }
+            Self::File(path) => {
+                keytool.arg(format!("{}:file", option)).arg(path);
+            }
+            // Prompted for by `keytool`
+            Self::Stdin(password) => return Some(password),
+        }
+        None
+    }
+
+    /// The password that should be written to the standard input of the tool, if any
+    pub(crate) fn stdin(&self) -> Option<&str> {
+        match self {
+            Self::Stdin(password) => Some(password),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for KeyPassword {
+    fn from(password: String) -> Self {
+        Self::Plaintext(password)
+    }
+}
+
+impl From<&str> for KeyPassword {
+    fn from(password: &str) -> Self {
+        Self::Plaintext(password.to_owned())
+    }
+}
+
+/// Runs `cmd`, writing every line of `stdin` to its standard input. It inherits the standard
+/// input of this process when `stdin` is empty.
+pub(crate) fn status_with_stdin(cmd: &mut Command, stdin: &[&str]) -> std::io::Result<bool> {
+    if stdin.is_empty() {
+        return Ok(cmd.status()?.success());
+    }
+    let mut child = cmd.stdin(Stdio::piped()).spawn()?;
+    {
+        let mut pipe = child.stdin.take().unwrap();
+        for line in stdin {
+            writeln!(pipe, "{}", line)?;
+        }
+    }
+    Ok(child.wait()?.success())
+}
+
+/// Runs `cmd` to completion, killing it and returning [`NdkError::Timeout`] when it does not
+/// exit within `timeout`. Only streams set to [`Stdio::piped()`] are captured in the [`Output`].
+pub(crate) fn run_with_timeout(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+) -> Result<Output, NdkError> {
+    let mut child = cmd.spawn()?;
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(child.wait_with_output()?),
+    };
+    let deadline = Instant::now() + timeout;
+
+    // Drain the pipes while polling, so that a chatty child can't block on a full pipe
+    fn read_to_end(mut pipe: impl Read + Send + 'static) -> JoinHandle<std::io::Result<Vec<u8>>> {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            pipe.read_to_end(&mut buf)?;
+            Ok(buf)
+        })
+    }
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // The child may have exited in the meantime
+            let _ = child.kill();
+            child.wait()?;
+            return Err(NdkError::Timeout {
+                cmd: format!("{:?}", cmd).replace('"', ""),
+                timeout,
+            });
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let join = |pipe: Option<JoinHandle<std::io::Result<Vec<u8>>>>| {
+        pipe.map_or(Ok(Vec::new()), |pipe| pipe.join().unwrap())
+    };
+    Ok(Output {
+        status,
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+    })
+}
+
+/// Looks up `llvm-<name>` in `toolchain_path`, falling back to the GNU `<triple>-<name>`.
+fn llvm_or_gnu_bin(toolchain_path: &Path, name: &str, target: Target) -> Result<PathBuf, NdkError> {
+    let ext = if cfg!(target_os = "windows") {
+        ".exe"
+    } else {
+        ""
+    };
+
+    let llvm_bin = format!("llvm-{}{}", name, ext);
+    let llvm_path = toolchain_path.join(&llvm_bin);
+    if llvm_path.exists() {
+        return Ok(llvm_path);
+    }
+    let gnu_bin = format!("{}-{}{}", target.ndk_triple(), name, ext);
+    let gnu_path = toolchain_path.join(&gnu_bin);
+    if gnu_path.exists() {
+        Ok(gnu_path)
+    } else {
+        Err(NdkError::ToolchainBinaryNotFound {
+            toolchain_path: toolchain_path.to_owned(),
+            gnu_bin,
+            llvm_bin,
+        })
+    }
+}
+
+/// Looks up a tool of the JDK, such as `keytool`, on `PATH` or in `JAVA_HOME`
+fn jdk_tool(name: &str) -> Result<Command, NdkError> {
+    let bin = if cfg!(target_os = "windows") {
+        format!("{}.exe", name)
+    } else {
+        name.to_owned()
+    };
+    if let Ok(tool) = which::which(&bin) {
+        return Ok(Command::new(tool));
+    }
+    if let Ok(java) = std::env::var("JAVA_HOME") {
+        let tool = PathBuf::from(java).join("bin").join(&bin);
+        if tool.exists() {
+            return Ok(Command::new(tool));
+        }
+    }
+    Err(NdkError::CmdNotFound(name.to_owned()))
+}
+
+fn keytool() -> Result<Command, NdkError> {
+    jdk_tool("keytool")
+}
+
+#[derive(Clone)]
 pub struct Key {
     pub path: PathBuf,
-    pub password: String,
+    /// Password of the keystore
+    pub password: KeyPassword,
+    /// Alias of the key in the keystore. When `None`, the keystore must contain a single key.
+    pub alias: Option<String>,
+    /// Password of the key, when it differs from the keystore [`Key::password`]
+    pub key_password: Option<KeyPassword>,
+}
+
+impl Key {
+    /// Resolves [`KeyPassword::Env`] passwords with [`KeyPassword::resolve_env`] when signing,
+    /// failing with [`NdkError::MissingEnvVar`] if a variable is not set.
+    pub(crate) fn resolve_env(&self) -> Result<Self, NdkError> {
+        Ok(Self {
+            password: self.password.resolve_env()?,
+            key_password: self
+                .key_password
+                .as_ref()
+                .map(KeyPassword::resolve_env)
+                .transpose()?,
+            ..self.clone()
+        })
+    }
+
+    /// Opens the debug keystore at `path`, generating it with `keytool` if it does not exist,
+    /// using the well-known credentials of the Android SDK: [`DEFAULT_DEV_KEYSTORE_PASSWORD`] for
+    /// both the keystore and the `androiddebugkey` key.
+    pub fn debug(path: &Path) -> Result<Self, NdkError> {
+        let password = DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned();
+
+        if !path.exists() {
+            let mut keytool = keytool()?;
+            keytool
+                .arg("-genkey")
+                .arg("-v")
+                .arg("-keystore")
+                .arg(path)
+                .arg("-storepass")
+                .arg(&password)
+                .arg("-alias")
+                .arg("androiddebugkey")
+                .arg("-keypass")
+                .arg(&password)
+                .arg("-dname")
+                .arg("CN=Android Debug,O=Android,C=US")
+                .arg("-keyalg")
+                .arg("RSA")
+                .arg("-keysize")
+                .arg("2048")
+                .arg("-validity")
+                .arg("10000");
+            if !keytool.status()?.success() {
+                return Err(NdkError::CmdFailed(Box::new(keytool)));
+            }
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            password: KeyPassword::Plaintext(password),
+            alias: None,
+            key_password: None,
+        })
+    }
+
+    /// Runs `keytool -list -v` on the keystore (restricted to [`Key::alias`] if set), returning
+    /// its output.
+    fn list(&self, ndk: &Ndk) -> Result<String, NdkError> {
+        let mut keytool = ndk.keytool()?;
+        keytool
+            .arg("-list")
+            .arg("-v")
+            .arg("-keystore")
+            .arg(&self.path);
+        let stdin = self.password.jdk_tool_arg(&mut keytool, "-storepass");
+        if let Some(alias) = &self.alias {
+            keytool.arg("-alias").arg(alias);
+        }
+        let output = if let Some(password) = stdin {
+            let mut child = keytool
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?;
+            writeln!(child.stdin.take().unwrap(), "{}", password)?;
+            child.wait_with_output()?
+        } else {
+            keytool.output()?
+        };
+        if !output.status.success() {
+            return Err(NdkError::CmdFailed(Box::new(keytool)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Reads the SHA-256 fingerprint of the signing certificate in this keystore, formatted as
+    /// colon-separated uppercase hex bytes (`AB:CD:...`).
+    pub fn sha256_fingerprint(&self, ndk: &Ndk) -> Result<String, NdkError> {
+        let output = self.list(ndk)?;
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("SHA256:"))
+            .map(|fingerprint| fingerprint.trim().to_owned())
+            .ok_or_else(|| NdkError::FingerprintNotInOutput(output.to_string()))
+    }
+
+    /// Returns [`Key::alias`], or otherwise the alias of the only key in the keystore. Tools
+    /// such as `jarsigner` require the alias to be passed explicitly.
+    pub fn resolve_alias(&self, ndk: &Ndk) -> Result<String, NdkError> {
+        if let Some(alias) = &self.alias {
+            return Ok(alias.clone());
+        }
+        let output = self.list(ndk)?;
+        let mut aliases = output
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("Alias name:"))
+            .map(|alias| alias.trim().to_owned())
+            .collect::<Vec<_>>();
+        if aliases.len() == 1 {
+            Ok(aliases.remove(0))
+        } else {
+            Err(NdkError::AmbiguousKeyAlias(aliases))
+        }
+    }
+
+    /// Generates the contents of a [Digital Asset Links] `assetlinks.json` file, which authorizes
+    /// `package` signed with this key to handle [Android App Links] for a domain.
+    ///
+    /// The result must be hosted by the developer at
+    /// `https://<domain>/.well-known/assetlinks.json` for every host listed in an intent filter
+    /// with [`auto_verify`](crate::manifest::IntentFilter::auto_verify) enabled.
+    ///
+    /// [Digital Asset Links]: https://developers.google.com/digital-asset-links
+    /// [Android App Links]: https://developer.android.com/training/app-links/verify-android-applinks
+    pub fn generate_assetlinks_json(&self, ndk: &Ndk, package: &str) -> Result<String, NdkError> {
+        Ok(assetlinks_json(package, &self.sha256_fingerprint(ndk)?))
+    }
+}
+
+/// Appends the `adb` arguments for uninstalling `package`.
+pub(crate) fn uninstall_args(adb: &mut Command, package: &str, keep_data: bool, user: Option<u32>) {
+    adb.arg("uninstall");
+    if keep_data {
+        adb.arg("-k");
+    }
+    if let Some(user) = user {
+        adb.arg("--user").arg(user.to_string());
+    }
+    adb.arg(package);
+}
+
+/// Extracts the ids from `UserInfo{<id>:<name>:<flags>}` lines, as printed by `pm list users`
+fn parse_users(output: &str) -> Vec<u32> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("UserInfo{"))
+        .filter_map(|info| info.split(':').next()?.parse().ok())
+        .collect()
+}
+
+/// Extracts package names from `package:<name>` lines, as printed by `pm list packages`
+fn parse_package_list(output: &str) -> impl Iterator<Item = &str> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .map(str::trim)
+        .filter(|package| !package.is_empty())
+}
+
+/// Escapes `text` for `input text`, which is evaluated by the device shell and reads `%s` as a
+/// space.
+fn escape_input_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            ' ' => escaped.push_str("%s"),
+            '\\' | '\'' | '"' | '`' | '$' | '&' | '|' | ';' | '<' | '>' | '(' | ')' | '*' | '~'
+            | '?' | '#' | '%' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn assetlinks_json(package: &str, sha256_fingerprint: &str) -> String {
+    format!(
+        r#"[
+  {{
+    "relation": ["delegate_permission/common.handle_all_urls"],
+    "target": {{
+      "namespace": "android_app",
+      "package_name": "{}",
+      "sha256_cert_fingerprints": ["{}"]
+    }}
+  }}
+]
+"#,
+        package, sha256_fingerprint
+    )
+}
+
+/// An [`Ndk`] whose build tools are looked up in `sdk_path`, for tests that don't run them
+#[cfg(test)]
+pub(crate) fn fake_ndk(sdk_path: &Path, build_tools_version: &str) -> Ndk {
+    Ndk {
+        sdk_path: sdk_path.to_owned(),
+        user_home: sdk_path.to_owned(),
+        ndk_path: sdk_path.join("ndk"),
+        build_tools_version: build_tools_version.to_owned(),
+        version: NdkVersion {
+            major: 25,
+            minor: 0,
+            build: 0,
+        },
+        platforms: vec![],
+        adb_env: BTreeMap::default(),
+        capabilities: CapabilityCache::default(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn key_password_from_env() {
+        assert!(KeyPassword::parse("env:KS_PASS") == KeyPassword::Env("KS_PASS".to_string()));
+        assert!(KeyPassword::parse("file:ks.pass") == KeyPassword::File("ks.pass".into()));
+        assert!(KeyPassword::parse("android") == KeyPassword::Plaintext("android".to_string()));
+
+        std::env::set_var("NDK_BUILD_TEST_KS_PASS", "env-secret");
+        let key = Key {
+            path: PathBuf::from("release.keystore"),
+            password: KeyPassword::Env("NDK_BUILD_TEST_KS_PASS".to_string()),
+            alias: None,
+            key_password: Some(KeyPassword::Env("NDK_BUILD_TEST_MISSING".to_string())),
+        };
+        assert!(matches!(
+            key.resolve_env(),
+            Err(NdkError::MissingEnvVar { name }) if name == "NDK_BUILD_TEST_MISSING"
+        ));
+
+        let key = Key {
+            key_password: None,
+            ..key
+        }
+        .resolve_env()
+        .unwrap();
+        assert!(key.password == KeyPassword::Stdin("env-secret".to_string()));
+        assert_eq!(key.password.apksigner_arg(), "stdin");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_with_timeout() {
+        let mut echo = Command::new("sh");
+        echo.arg("-c").arg("echo done").stdout(Stdio::piped());
+        let output = run_with_timeout(&mut echo, Some(Duration::from_secs(10))).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"done\n");
+
+        let mut sleep = Command::new("sleep");
+        sleep.arg("10");
+        let start = Instant::now();
+        let err = run_with_timeout(&mut sleep, Some(Duration::from_millis(100))).unwrap_err();
+        assert!(matches!(err, NdkError::Timeout { .. }), "{:?}", err);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
     #[test]
     #[ignore]
     fn test_detect() {
@@ -505,4 +1621,244 @@ mod tests {
         assert_eq!(ndk.build_tools_version(), "29.0.2");
         assert_eq!(ndk.platforms(), &[29, 28]);
     }
+
+    #[test]
+    fn test_debug_key() {
+        if keytool().is_err() {
+            eprintln!("Skipping, `keytool` is not available");
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("ndk-build-debug-key-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("debug.keystore");
+
+        let key = Key::debug(&path).unwrap();
+        assert_eq!(key.path, path);
+        assert!(key.password == KeyPassword::Plaintext(DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned()));
+        let generated = std::fs::read(&path).unwrap();
+        assert!(!generated.is_empty());
+
+        // An existing keystore is reused
+        Key::debug(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), generated);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_llvm_or_gnu_bin() {
+        let bin = |name: &str| {
+            if cfg!(target_os = "windows") {
+                format!("{}.exe", name)
+            } else {
+                name.to_owned()
+            }
+        };
+        let target = Target::Arm64V8a;
+        let dir = std::env::temp_dir().join(format!("ndk-build-toolchain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert!(matches!(
+            llvm_or_gnu_bin(&dir, "objcopy", target),
+            Err(NdkError::ToolchainBinaryNotFound { .. })
+        ));
+
+        // Older NDKs only ship GNU binutils
+        let gnu = dir.join(bin("aarch64-linux-android-objcopy"));
+        std::fs::write(&gnu, "").unwrap();
+        assert_eq!(llvm_or_gnu_bin(&dir, "objcopy", target).unwrap(), gnu);
+
+        // LLVM is preferred when both are present
+        let llvm = dir.join(bin("llvm-objcopy"));
+        std::fs::write(&llvm, "").unwrap();
+        assert_eq!(llvm_or_gnu_bin(&dir, "objcopy", target).unwrap(), llvm);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_uninstall_args() {
+        let mut adb = Command::new("adb");
+        uninstall_args(&mut adb, "com.example", false, None);
+        assert_eq!(
+            adb.get_args().collect::<Vec<_>>(),
+            ["uninstall", "com.example"]
+        );
+
+        let mut adb = Command::new("adb");
+        uninstall_args(&mut adb, "com.example", true, None);
+        assert_eq!(
+            adb.get_args().collect::<Vec<_>>(),
+            ["uninstall", "-k", "com.example"]
+        );
+
+        let mut adb = Command::new("adb");
+        uninstall_args(&mut adb, "com.example", false, Some(10));
+        assert_eq!(
+            adb.get_args().collect::<Vec<_>>(),
+            ["uninstall", "--user", "10", "com.example"]
+        );
+    }
+
+    #[test]
+    fn test_device_properties() {
+        assert_eq!(
+            parse_abilist("arm64-v8a,armeabi-v7a,armeabi\n"),
+            ["arm64-v8a", "armeabi-v7a", "armeabi"]
+        );
+        assert!(parse_abilist("").is_empty());
+
+        assert!(is_no_device("adb: no devices/emulators found\n"));
+        assert!(is_no_device("adb: device 'emulator-5556' not found\n"));
+        assert!(is_no_device("adb: more than one device/emulator\n"));
+        assert!(!is_no_device("/system/bin/sh: getprop: not found\n"));
+    }
+
+    #[test]
+    fn test_parse_users() {
+        let output =
+            "Users:\n\tUserInfo{0:Owner:c13} running\n\tUserInfo{10:Work profile:1030} running\n";
+        assert_eq!(parse_users(output), [0, 10]);
+    }
+
+    #[test]
+    fn test_parse_devices() {
+        let output = "* daemon not running; starting now at tcp:5037\n\
+            * daemon started successfully\n\
+            List of devices attached\n\
+            emulator-5554          device product:sdk_gphone64_x86_64 model:sdk_gphone64_x86_64 device:emu64xa transport_id:1\n\
+            R58M123ABC             unauthorized usb:1-1 transport_id:2\n\
+            192.168.1.7:5555       offline transport_id:3\n\
+            \n";
+        assert_eq!(
+            parse_devices(output),
+            [
+                Device {
+                    serial: "emulator-5554".to_string(),
+                    state: DeviceState::Device,
+                    model: Some("sdk_gphone64_x86_64".to_string()),
+                    product: Some("sdk_gphone64_x86_64".to_string()),
+                },
+                Device {
+                    serial: "R58M123ABC".to_string(),
+                    state: DeviceState::Unauthorized,
+                    model: None,
+                    product: None,
+                },
+                Device {
+                    serial: "192.168.1.7:5555".to_string(),
+                    state: DeviceState::Offline,
+                    model: None,
+                    product: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_build_tools() {
+        let sdk = std::env::temp_dir().join(format!("ndk-build-sdk-{}", std::process::id()));
+        for dir in ["9.0.0", "35.0.0", "35.0.0-rc1", "30.0.3", "debian"] {
+            std::fs::create_dir_all(sdk.join("build-tools").join(dir)).unwrap();
+        }
+        std::fs::write(sdk.join("build-tools").join("1.txt"), "").unwrap();
+        assert_eq!(
+            list_build_tools(&sdk).unwrap(),
+            ["9.0.0", "30.0.3", "35.0.0-rc1", "35.0.0"]
+        );
+        std::fs::remove_dir_all(&sdk).unwrap();
+    }
+
+    #[test]
+    fn test_sdk_component_suggestion() {
+        let err = NdkError::SdkComponentNotFound {
+            component: SdkComponent::Platform(33),
+            path: PathBuf::from("platforms/android-33"),
+        };
+        assert!(err
+            .to_string()
+            .ends_with("install it with `sdkmanager \"platforms;android-33\"`."));
+        assert_eq!(
+            SdkComponent::BuildTools("34.0.0".to_string()).sdkmanager_package(),
+            "build-tools;34.0.0"
+        );
+        assert_eq!(
+            SdkComponent::PlatformTools.sdkmanager_package(),
+            "platform-tools"
+        );
+    }
+
+    #[test]
+    fn test_parse_ndk_version() {
+        let source_properties = "Pkg.Desc = Android NDK\nPkg.Revision = 25.2.9519653\n";
+        assert_eq!(
+            parse_ndk_version(source_properties),
+            Some(NdkVersion {
+                major: 25,
+                minor: 2,
+                build: 9519653
+            })
+        );
+        assert_eq!(
+            parse_ndk_version("Pkg.Desc = Android NDK\r\nPkg.Revision = 23.0.7272597-beta3\r\n"),
+            Some(NdkVersion {
+                major: 23,
+                minor: 0,
+                build: 7272597
+            })
+        );
+        assert_eq!(parse_ndk_version("Pkg.Desc = Android NDK\n"), None);
+    }
+
+    #[test]
+    fn test_parse_connect() {
+        assert_eq!(
+            parse_connect("connected to 192.168.1.7:5555\n").as_deref(),
+            Some("192.168.1.7:5555")
+        );
+        assert_eq!(
+            parse_connect("already connected to 192.168.1.7:37215\n").as_deref(),
+            Some("192.168.1.7:37215")
+        );
+        assert_eq!(
+            parse_connect("failed to connect to '192.168.1.7:5555': Connection refused\n"),
+            None
+        );
+        assert_eq!(
+            parse_connect("cannot connect to 192.168.1.7:5555: No route to host (113)\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_package_list() {
+        let output = "package:com.example.app\r\npackage:com.example.app.staging\n\nWARNING: linker: foo\npackage: rust.example.looper \n";
+        assert_eq!(
+            parse_package_list(output).collect::<Vec<_>>(),
+            [
+                "com.example.app",
+                "com.example.app.staging",
+                "rust.example.looper"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escape_input_text() {
+        assert_eq!(escape_input_text("hello"), "hello");
+        assert_eq!(escape_input_text("hello world"), "hello%sworld");
+        assert_eq!(
+            escape_input_text("it's $5 & (more)"),
+            r"it\'s%s\$5%s\&%s\(more\)"
+        );
+        assert_eq!(escape_input_text("100%"), r"100\%");
+    }
+
+    #[test]
+    fn test_assetlinks_json() {
+        let json = assetlinks_json("com.example.app", "AB:CD:EF");
+        assert!(json.contains(r#""package_name": "com.example.app""#));
+        assert!(json.contains(r#""sha256_cert_fingerprints": ["AB:CD:EF"]"#));
+        assert!(json.contains(r#""relation": ["delegate_permission/common.handle_all_urls"]"#));
+    }
 }
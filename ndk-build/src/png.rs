@@ -0,0 +1,438 @@
+//! Minimal PNG decoder and encoder, just enough to scale the launcher icon to every mipmap
+//! density. Interlaced images are not supported, and images are written without compression
+//! since `aapt` crunches them when packaging anyway.
+
+use crate::deflate::{self, adler32, crc32};
+use std::convert::TryInto;
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+/// Largest width or height accepted by [`decode`], to bound the decoded size of the image
+const MAX_DIMENSION: u32 = 8192;
+
+/// A decoded image with 8-bit RGBA pixels stored row by row
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    /// Scales the image to `width`×`height` by averaging the area of the source covered by
+    /// each target pixel. Colors are weighted by their alpha so that transparent pixels do not
+    /// bleed into the edges of the icon.
+    pub(crate) fn resize(&self, width: u32, height: u32) -> Self {
+        let columns = area_weights(self.width, width);
+        let rows = area_weights(self.height, height);
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in &rows {
+            for column in &columns {
+                let mut sum = [0f32; 4];
+                for &(y, wy) in row {
+                    for &(x, wx) in column {
+                        let offset = (y * self.width as usize + x) * 4;
+                        let pixel = &self.rgba[offset..offset + 4];
+                        let weight = wx * wy;
+                        let alpha = pixel[3] as f32 * weight;
+                        for (sum, &channel) in sum.iter_mut().zip(&pixel[..3]) {
+                            *sum += channel as f32 * alpha;
+                        }
+                        sum[3] += alpha;
+                    }
+                }
+                let alpha = sum[3];
+                for channel in &sum[..3] {
+                    let value = if alpha > 0.0 { channel / alpha } else { 0.0 };
+                    rgba.push(value.round().clamp(0.0, 255.0) as u8);
+                }
+                rgba.push(alpha.round().clamp(0.0, 255.0) as u8);
+            }
+        }
+        Self {
+            width,
+            height,
+            rgba,
+        }
+    }
+}
+
+/// For every target pixel along an axis, the source pixels it covers and the fraction of the
+/// target pixel each of them makes up.
+fn area_weights(from: u32, to: u32) -> Vec<Vec<(usize, f32)>> {
+    let scale = from as f32 / to as f32;
+    (0..to)
+        .map(|i| {
+            let start = i as f32 * scale;
+            let end = (i + 1) as f32 * scale;
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(from as usize);
+            (first..last)
+                .map(|j| {
+                    let covered = end.min(j as f32 + 1.0) - start.max(j as f32);
+                    (j, covered / scale)
+                })
+                .filter(|&(_, weight)| weight > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+/// Decodes a PNG image of any color type and bit depth into 8-bit RGBA. Returns [`None`] for
+/// corrupt, interlaced or overly large images.
+pub(crate) fn decode(bytes: &[u8]) -> Option<Image> {
+    if bytes.get(..8)? != SIGNATURE {
+        return None;
+    }
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut offset = 8;
+    loop {
+        let len = u32_at(bytes, offset)? as usize;
+        let kind = bytes.get(offset + 4..offset + 8)?;
+        let data = bytes.get(offset + 8..(offset + 8).checked_add(len)?)?;
+        // The CRC covers the chunk type and data
+        if u32_at(bytes, offset + 8 + len)? != crc32(&bytes[offset + 4..offset + 8 + len]) {
+            return None;
+        }
+        match kind {
+            b"IHDR" => header = Some(data),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        offset += 12 + len;
+    }
+
+    let header = header.filter(|header| header.len() == 13)?;
+    let width = u32_at(header, 0)?;
+    let height = u32_at(header, 4)?;
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return None;
+    }
+    if interlace != 0 {
+        return None;
+    }
+    let channels = match (color_type, depth) {
+        (0, 1 | 2 | 4 | 8 | 16) => 1,
+        (3, 1 | 2 | 4 | 8) => 1,
+        (2 | 4 | 6, 8 | 16) => [0, 0, 3, 0, 2, 0, 4][color_type as usize],
+        _ => return None,
+    };
+
+    let bits_per_pixel = channels * depth as usize;
+    let stride = (width as usize * bits_per_pixel + 7) / 8;
+    // Every row is preceded by its filter type
+    let filtered_len = (stride + 1) * height as usize;
+    let filtered = inflate(&compressed, filtered_len)?;
+    if filtered.len() != filtered_len {
+        return None;
+    }
+    let pixels = unfilter(&filtered, stride, height as usize, (bits_per_pixel + 7) / 8)?;
+
+    let max = (1u32 << depth) - 1;
+    let scale = |sample: u32| (sample * 255 / max) as u8;
+    let transparent_key = |index: usize| {
+        transparency
+            .get(index * 2..index * 2 + 2)
+            .map(|key| u16::from_be_bytes([key[0], key[1]]) as u32)
+    };
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in pixels.chunks(stride) {
+        let sample = |index: usize| -> u32 {
+            match depth {
+                16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]) as u32,
+                8 => row[index] as u32,
+                _ => {
+                    let bit = index * depth as usize;
+                    let shift = 8 - depth as usize - bit % 8;
+                    (row[bit / 8] as u32 >> shift) & max
+                }
+            }
+        };
+        for x in 0..width as usize {
+            let first = x * channels;
+            let pixel = match color_type {
+                0 => {
+                    let gray = sample(first);
+                    let alpha = if transparent_key(0) == Some(gray) {
+                        0
+                    } else {
+                        255
+                    };
+                    [scale(gray), scale(gray), scale(gray), alpha]
+                }
+                2 => {
+                    let rgb = [sample(first), sample(first + 1), sample(first + 2)];
+                    let opaque = (0..3).any(|i| transparent_key(i) != Some(rgb[i]));
+                    [
+                        scale(rgb[0]),
+                        scale(rgb[1]),
+                        scale(rgb[2]),
+                        if opaque { 255 } else { 0 },
+                    ]
+                }
+                3 => {
+                    let index = sample(first) as usize;
+                    let color = palette.get(index * 3..index * 3 + 3)?;
+                    let alpha = transparency.get(index).copied().unwrap_or(255);
+                    [color[0], color[1], color[2], alpha]
+                }
+                4 => {
+                    let gray = scale(sample(first));
+                    [gray, gray, gray, scale(sample(first + 1))]
+                }
+                _ => [
+                    scale(sample(first)),
+                    scale(sample(first + 1)),
+                    scale(sample(first + 2)),
+                    scale(sample(first + 3)),
+                ],
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    Some(Image {
+        width,
+        height,
+        rgba,
+    })
+}
+
+/// Reverses the per-row filters, returning the raw rows without their filter type byte.
+fn unfilter(filtered: &[u8], stride: usize, height: usize, bpp: usize) -> Option<Vec<u8>> {
+    let mut pixels = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = filtered[y * (stride + 1)];
+        let input = &filtered[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (previous, current) = pixels.split_at_mut(y * stride);
+        let above = if y == 0 {
+            None
+        } else {
+            Some(&previous[(y - 1) * stride..])
+        };
+        let current = &mut current[..stride];
+        for x in 0..stride {
+            let a = if x >= bpp { current[x - bpp] } else { 0 };
+            let b = above.map_or(0, |above| above[x]);
+            let c = match above {
+                Some(above) if x >= bpp => above[x - bpp],
+                _ => 0,
+            };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return None,
+            };
+            current[x] = input[x].wrapping_add(predicted);
+        }
+    }
+    Some(pixels)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Encodes an 8-bit RGBA PNG, storing the pixel data uncompressed.
+pub(crate) fn encode(image: &Image) -> Vec<u8> {
+    let stride = image.width as usize * 4;
+    let mut raw = Vec::with_capacity((stride + 1) * image.height as usize);
+    for row in image.rgba.chunks(stride) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // A zlib stream of stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, not interlaced
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Decompresses a zlib stream and verifies its checksum. Returns [`None`] for corrupt streams,
+/// and stops at the first byte beyond `limit` so that a small stream can't decompress to an
+/// arbitrarily large buffer.
+fn inflate(zlib: &[u8], limit: usize) -> Option<Vec<u8>> {
+    // Deflate without a preset dictionary, with a valid header check
+    if zlib.len() < 2
+        || zlib[0] & 0x0f != 8
+        || zlib[1] & 0x20 != 0
+        || u16::from_be_bytes([zlib[0], zlib[1]]) % 31 != 0
+    {
+        return None;
+    }
+    let (out, len) = deflate::decompress(&zlib[2..], limit)?;
+    if u32_at(zlib, 2 + len)? == adler32(&out) {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn inflate_huffman_blocks() {
+        // Compressed with fixed Huffman codes
+        assert_eq!(
+            inflate(&hex("78da4b4c4a4e8421001de00499"), 12).unwrap(),
+            b"abcabcabcabc"
+        );
+        // Compressed with dynamic Huffman codes
+        let text = "Android package kit, built by cargo-apk from a Rust crate. ".repeat(2)
+            + "mipmap-mdpi mipmap-hdpi mipmap-xhdpi mipmap-xxhdpi mipmap-xxxhdpi ic_launcher.png";
+        let compressed = hex(
+            "78da9dcbc10d84300c05d1567e01400fb44003c838d9608524964924e87e0f2ba48523b7798719b3b3\
+             220e4a1c297844a91d96265bc57282c942e949233e56120853db2bd8a8fa01e3fb358926d23e3995\
+             abd7bf3e6e78e847e179a39679f536680e5fc5ab4876",
+        );
+        assert_eq!(inflate(&compressed, text.len()).unwrap(), text.as_bytes());
+
+        // A corrupt checksum
+        let mut corrupt = compressed.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_eq!(inflate(&corrupt, text.len()), None);
+    }
+
+    #[test]
+    fn inflate_stops_at_limit() {
+        // 120 bytes that decompress to 100000 zeros
+        let zeros = [
+            hex("78daedc13101000000c2a0f54f6d0d0fa0"),
+            vec![0; 96],
+            hex("80570386af0001"),
+        ]
+        .concat();
+        assert_eq!(inflate(&zeros, 100_000), Some(vec![0; 100_000]));
+        assert_eq!(inflate(&zeros, 99_999), None);
+
+        // A 16x16 grayscale image only holds 16 rows of 1 + 16 bytes
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(
+            &mut png,
+            b"IHDR",
+            &[0, 0, 0, 16, 0, 0, 0, 16, 8, 0, 0, 0, 0],
+        );
+        write_chunk(&mut png, b"IDAT", &zeros);
+        write_chunk(&mut png, b"IEND", &[]);
+        assert_eq!(decode(&png), None);
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let image = Image {
+            width: 3,
+            height: 2,
+            rgba: (0..24).map(|i| i * 10).collect(),
+        };
+        let png = encode(&image);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(decode(&png), Some(image));
+        assert_eq!(decode(&png[..png.len() - 20]), None);
+        let mut corrupt = png.clone();
+        // The last byte of the CRC of `IHDR`
+        corrupt[32] ^= 1;
+        assert_eq!(decode(&corrupt), None);
+        assert_eq!(decode(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn decode_palette() {
+        // 2x1 image with a 1-bit palette of opaque red and transparent blue
+        let zlib = hex("789c6370000000420041");
+        let mut png = SIGNATURE.to_vec();
+        let header = [0, 0, 0, 2, 0, 0, 0, 1, 1, 3, 0, 0, 0];
+        let palette = [255, 0, 0, 0, 0, 255];
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"PLTE", &palette);
+        write_chunk(&mut png, b"tRNS", &[255, 0]);
+        write_chunk(&mut png, b"IDAT", &zlib);
+        write_chunk(&mut png, b"IEND", &[]);
+        assert_eq!(decode(&png).unwrap().rgba, [255, 0, 0, 255, 0, 0, 255, 0]);
+    }
+
+    #[test]
+    fn resize_averages_visible_pixels() {
+        // An opaque white pixel next to a transparent black one
+        let image = Image {
+            width: 2,
+            height: 1,
+            rgba: vec![255, 255, 255, 255, 0, 0, 0, 0],
+        };
+        assert_eq!(image.resize(1, 1).rgba, [255, 255, 255, 128]);
+
+        let image = Image {
+            width: 1,
+            height: 1,
+            rgba: vec![10, 20, 30, 255],
+        };
+        let scaled = image.resize(3, 2);
+        assert_eq!((scaled.width, scaled.height), (3, 2));
+        assert!(scaled
+            .rgba
+            .chunks(4)
+            .all(|pixel| pixel == [10, 20, 30, 255]));
+    }
+}
@@ -2,16 +2,61 @@ use crate::apk::UnalignedApk;
 use crate::error::NdkError;
 use crate::target::Target;
 use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::io::BufRead;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+/// Libraries provided by the platform, that are never bundled by
+/// [`UnalignedApk::add_lib_with_deps`]. These are the
+/// [stable NDK APIs](https://developer.android.com/ndk/guides/stable_apis); any other library in
+/// the NDK sysroot for the `min_sdk_version` is skipped too, except for `libc++_shared.so`.
+pub const SYSTEM_LIBS: &[&str] = &[
+    "libaaudio.so",
+    "libamidi.so",
+    "libandroid.so",
+    "libbinder_ndk.so",
+    "libc.so",
+    "libcamera2ndk.so",
+    "libdl.so",
+    "libEGL.so",
+    "libGLESv1_CM.so",
+    "libGLESv2.so",
+    "libGLESv3.so",
+    "libjnigraphics.so",
+    "liblog.so",
+    "libm.so",
+    "libmediandk.so",
+    "libnativewindow.so",
+    "libneuralnetworks.so",
+    "libOpenMAXAL.so",
+    "libOpenSLES.so",
+    "libstdc++.so",
+    "libsync.so",
+    "libvulkan.so",
+    "libz.so",
+];
+
 impl<'a> UnalignedApk<'a> {
+    /// Same as [`UnalignedApk::add_lib_with_deps`].
     pub fn add_lib_recursively(
         &mut self,
         lib: &Path,
         target: Target,
         search_paths: &[&Path],
+    ) -> Result<(), NdkError> {
+        self.add_lib_with_deps(lib, target, search_paths)
+    }
+
+    /// Adds `lib` along with the libraries it depends on through `DT_NEEDED` entries,
+    /// transitively. Dependencies are looked up in `search_paths`, except for `libc++_shared.so`
+    /// which is taken from the NDK sysroot, and skipped when they are provided by the platform
+    /// (see [`SYSTEM_LIBS`]).
+    pub fn add_lib_with_deps(
+        &mut self,
+        lib: &Path,
+        target: Target,
+        search_paths: &[&Path],
     ) -> Result<(), NdkError> {
         let ndk = &self.config().ndk;
         let default_min_sdk = crate::manifest::Sdk::default().min_sdk_version.unwrap();
@@ -28,7 +73,10 @@ impl<'a> UnalignedApk<'a> {
             &*ndk.sysroot_platform_lib_dir(target, min_sdk_version)?,
         ];
 
-        let mut provided = HashSet::new();
+        let mut provided = SYSTEM_LIBS
+            .iter()
+            .map(|lib| lib.to_string())
+            .collect::<HashSet<_>>();
         for path in &android_search_paths {
             for lib in list_libs(path)? {
                 if lib != "libc++_shared.so" {
@@ -71,7 +119,7 @@ fn list_needed_libs(readelf_path: &Path, library_path: &Path) -> Result<HashSet<
     let mut readelf = Command::new(readelf_path);
     let output = readelf.arg("-d").arg(library_path).output()?;
     if !output.status.success() {
-        return Err(NdkError::CmdFailed(readelf));
+        return Err(NdkError::CmdFailed(Box::new(readelf)));
     }
     let mut needed = HashSet::new();
     for line in output.stdout.lines() {
@@ -119,3 +167,124 @@ fn find_library_path<S: AsRef<Path>>(
     }
     Ok(None)
 }
+
+/// Whether the ELF file `elf` contains `.debug_*` (or compressed `.zdebug_*`) sections, found
+/// by scanning its section headers. Returns `None` if `elf` is not a well-formed ELF file.
+pub fn has_debug_sections(elf: &[u8]) -> Option<bool> {
+    if elf.get(..4)? != b"\x7fELF" {
+        return None;
+    }
+    let is_64 = match elf.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let little_endian = match elf.get(5)? {
+        1 => true,
+        2 => false,
+        _ => return None,
+    };
+    let uint = |offset: usize, len: usize| -> Option<u64> {
+        let bytes = elf.get(offset..offset.checked_add(len)?)?;
+        let fold = |value: u64, byte: &u8| value << 8 | u64::from(*byte);
+        Some(if little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        })
+    };
+    let (shoff, shentsize, shnum, shstrndx) = if is_64 {
+        (
+            uint(0x28, 8)?,
+            uint(0x3a, 2)?,
+            uint(0x3c, 2)?,
+            uint(0x3e, 2)?,
+        )
+    } else {
+        (
+            uint(0x20, 4)?,
+            uint(0x2e, 2)?,
+            uint(0x30, 2)?,
+            uint(0x32, 2)?,
+        )
+    };
+    // Offsets of `sh_offset` and `sh_size` within a section header, and their size
+    let (offset_field, size_field, word) = if is_64 {
+        (0x18, 0x20, 8)
+    } else {
+        (0x10, 0x14, 4)
+    };
+    let header = |index: u64| -> Option<usize> {
+        usize::try_from(shoff.checked_add(index.checked_mul(shentsize)?)?).ok()
+    };
+
+    let strtab = header(shstrndx)?;
+    let strtab_offset = usize::try_from(uint(strtab.checked_add(offset_field)?, word)?).ok()?;
+    let strtab_size = usize::try_from(uint(strtab.checked_add(size_field)?, word)?).ok()?;
+    let strtab = elf.get(strtab_offset..strtab_offset.checked_add(strtab_size)?)?;
+
+    for index in 0..shnum {
+        let name = usize::try_from(uint(header(index)?, 4)?).ok()?;
+        let name = strtab.get(name..)?;
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+        if name.starts_with(b".debug_") || name.starts_with(b".zdebug_") {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a little-endian ELF64 file containing only section headers named `sections`,
+    /// followed by the section name string table.
+    fn elf64(sections: &[&str]) -> Vec<u8> {
+        let mut strtab = vec![0];
+        let mut names = Vec::new();
+        for name in sections.iter().chain(&[".shstrtab"]) {
+            names.push(strtab.len() as u32);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+        let shnum = names.len() as u16;
+        let strtab_offset = 64 + 64 * u64::from(shnum);
+
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(0x28, 0);
+        elf.extend_from_slice(&64u64.to_le_bytes());
+        elf.resize(0x3a, 0);
+        elf.extend_from_slice(&64u16.to_le_bytes());
+        elf.extend_from_slice(&shnum.to_le_bytes());
+        elf.extend_from_slice(&(shnum - 1).to_le_bytes());
+        for name in names {
+            let header = elf.len();
+            elf.extend_from_slice(&name.to_le_bytes());
+            elf.resize(header + 0x18, 0);
+            elf.extend_from_slice(&strtab_offset.to_le_bytes());
+            elf.extend_from_slice(&(strtab.len() as u64).to_le_bytes());
+            elf.resize(header + 64, 0);
+        }
+        elf.extend(strtab);
+        elf
+    }
+
+    #[test]
+    fn detect_debug_sections() {
+        assert_eq!(
+            has_debug_sections(&elf64(&[".text", ".debug_info", ".debug_line"])),
+            Some(true)
+        );
+        assert_eq!(
+            has_debug_sections(&elf64(&[".text", ".zdebug_info"])),
+            Some(true)
+        );
+        assert_eq!(
+            has_debug_sections(&elf64(&[".text", ".dynsym", ".gnu_debuglink"])),
+            Some(false)
+        );
+        assert_eq!(has_debug_sections(b"\x7fELF\x02\x01"), None);
+        assert_eq!(has_debug_sections(b"not an elf"), None);
+    }
+}
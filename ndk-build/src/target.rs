@@ -1,7 +1,7 @@
 use crate::error::NdkError;
 use serde::Deserialize;
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
 #[repr(u8)]
 pub enum Target {
     #[serde(rename = "armv7-linux-androideabi")]
@@ -36,6 +36,18 @@ impl Target {
         }
     }
 
+    /// Picks the target of `available` that the device prefers, given its
+    /// `ro.product.cpu.abilist` (see [`Ndk::device_abilist`](crate::ndk::Ndk::device_abilist)),
+    /// which lists the preferred ABI first. The list includes the 32-bit ABIs that a 64-bit
+    /// device runs as well, e.g. `armeabi-v7a` on most `arm64-v8a` devices, and omits them on
+    /// 64-bit-only devices. Unknown ABIs are skipped.
+    pub fn select_for_device(abilist: &[&str], available: &[Target]) -> Option<Target> {
+        abilist
+            .iter()
+            .filter_map(|abi| Self::from_android_abi(abi.trim()).ok())
+            .find(|target| available.contains(target))
+    }
+
     /// Returns the triple used by the rust build tools
     pub fn rust_triple(self) -> &'static str {
         match self {
@@ -77,3 +89,33 @@ impl Target {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_target_for_device() {
+        let arm64 = ["arm64-v8a", "armeabi-v7a", "armeabi"];
+        assert_eq!(
+            Target::select_for_device(&arm64, &[Target::ArmV7a, Target::Arm64V8a]),
+            Some(Target::Arm64V8a)
+        );
+        assert_eq!(
+            Target::select_for_device(&arm64, &[Target::X86_64, Target::ArmV7a]),
+            Some(Target::ArmV7a)
+        );
+        assert_eq!(
+            Target::select_for_device(&["arm64-v8a"], &[Target::ArmV7a]),
+            None
+        );
+
+        // Emulators may translate ARM code, but prefer their native ABI
+        let emulator = ["x86_64", "x86", "arm64-v8a", "armeabi-v7a"];
+        assert_eq!(
+            Target::select_for_device(&emulator, &[Target::Arm64V8a, Target::X86]),
+            Some(Target::X86)
+        );
+        assert_eq!(Target::select_for_device(&[], &[Target::X86]), None);
+    }
+}
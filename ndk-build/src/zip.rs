@@ -0,0 +1,351 @@
+//! Minimal reader for the central directory of zip archives such as APKs, which lists every
+//! entry with its sizes without having to decompress anything, and a rewriter that recompresses
+//! the deflated entries of an archive.
+
+use crate::deflate::{self, crc32};
+use crate::error::NdkError;
+use std::convert::TryInto;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+/// Size of the end of central directory record without its trailing comment
+const END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+/// Size of a central directory header without its name, extra field and comment
+const CENTRAL_DIRECTORY_HEADER_LEN: usize = 46;
+/// Size of a local file header without its name and extra field
+const LOCAL_FILE_HEADER_LEN: usize = 30;
+/// Compression method of entries compressed with DEFLATE
+const METHOD_DEFLATED: u16 = 8;
+/// Flag of entries whose CRC and sizes follow their data in a data descriptor
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+
+/// An entry listed in the central directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ZipEntry {
+    pub name: String,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().unwrap(),
+    ))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+fn invalid(reason: &str) -> NdkError {
+    NdkError::InvalidZip(reason.to_string())
+}
+
+/// Finds the end of central directory record, returning its offset, the offset of the central
+/// directory and the number of entries.
+fn find_central_directory(bytes: &[u8]) -> Result<(usize, usize, u16), NdkError> {
+    // The record is followed by a comment of up to 64KiB, so search backwards for it
+    let eocd = (0..=bytes.len().saturating_sub(END_OF_CENTRAL_DIRECTORY_LEN))
+        .rev()
+        .take(u16::MAX as usize + 1)
+        .find(|&offset| u32_at(bytes, offset) == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| invalid("end of central directory not found"))?;
+    let count = u16_at(bytes, eocd + 10).unwrap();
+    let offset = u32_at(bytes, eocd + 16).unwrap();
+    if count == u16::MAX || offset == u32::MAX {
+        return Err(invalid("zip64 archives are not supported"));
+    }
+    Ok((eocd, offset as usize, count))
+}
+
+/// The central directory header at `offset`, without its name, extra field and comment
+fn central_directory_header(bytes: &[u8], offset: usize) -> Result<&[u8], NdkError> {
+    bytes
+        .get(offset..offset + CENTRAL_DIRECTORY_HEADER_LEN)
+        .filter(|header| u32_at(header, 0) == Some(CENTRAL_DIRECTORY_HEADER))
+        .ok_or_else(|| invalid("truncated central directory"))
+}
+
+/// Lists the entries of a zip archive. Zip64 archives are not supported.
+pub(crate) fn read_entries(bytes: &[u8]) -> Result<Vec<ZipEntry>, NdkError> {
+    let (_, mut offset, count) = find_central_directory(bytes)?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let header = central_directory_header(bytes, offset)?;
+        let compressed_size = u32_at(header, 20).unwrap();
+        let uncompressed_size = u32_at(header, 24).unwrap();
+        let name_len = u16_at(header, 28).unwrap() as usize;
+        let extra_len = u16_at(header, 30).unwrap() as usize;
+        let comment_len = u16_at(header, 32).unwrap() as usize;
+
+        let name_start = offset + CENTRAL_DIRECTORY_HEADER_LEN;
+        let name = bytes
+            .get(name_start..name_start + name_len)
+            .ok_or_else(|| invalid("truncated central directory"))?;
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            compressed_size: compressed_size.into(),
+            uncompressed_size: uncompressed_size.into(),
+        });
+        offset = name_start + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// Rewrites a zip archive with every deflated entry recompressed at DEFLATE `level`, keeping
+/// the original data of entries that do not get smaller. Stored entries, such as uncompressed
+/// native libraries and `resources.arsc`, are copied unchanged. Entries do move within the
+/// archive, so it has to be aligned afterwards.
+pub(crate) fn recompress(bytes: &[u8], level: u32) -> Result<Vec<u8>, NdkError> {
+    let (eocd, mut offset, count) = find_central_directory(bytes)?;
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut central_directory = Vec::new();
+    for _ in 0..count {
+        let header = central_directory_header(bytes, offset)?;
+        let flags = u16_at(header, 8).unwrap();
+        let method = u16_at(header, 10).unwrap();
+        let crc = u32_at(header, 16).unwrap();
+        let compressed_size = u32_at(header, 20).unwrap();
+        let uncompressed_size = u32_at(header, 24).unwrap();
+        let name_len = u16_at(header, 28).unwrap() as usize;
+        let trailer_len =
+            name_len + u16_at(header, 30).unwrap() as usize + u16_at(header, 32).unwrap() as usize;
+        let local_offset = u32_at(header, 42).unwrap() as usize;
+        if compressed_size == u32::MAX || uncompressed_size == u32::MAX {
+            return Err(invalid("zip64 archives are not supported"));
+        }
+        let trailer_start = offset + CENTRAL_DIRECTORY_HEADER_LEN;
+        let trailer = bytes
+            .get(trailer_start..trailer_start + trailer_len)
+            .ok_or_else(|| invalid("truncated central directory"))?;
+        let name = String::from_utf8_lossy(&trailer[..name_len]);
+
+        let local = bytes
+            .get(local_offset..local_offset + LOCAL_FILE_HEADER_LEN)
+            .filter(|local| u32_at(local, 0) == Some(LOCAL_FILE_HEADER))
+            .ok_or_else(|| invalid(&format!("missing local header of `{}`", name)))?;
+        let data_start = local_offset
+            + LOCAL_FILE_HEADER_LEN
+            + u16_at(local, 26).unwrap() as usize
+            + u16_at(local, 28).unwrap() as usize;
+        let data = bytes
+            .get(data_start..data_start + compressed_size as usize)
+            .ok_or_else(|| invalid(&format!("truncated data of `{}`", name)))?;
+
+        let recompressed;
+        let data = if method == METHOD_DEFLATED {
+            let (uncompressed, _) = deflate::decompress(data, uncompressed_size as usize)
+                .filter(|(uncompressed, _)| {
+                    uncompressed.len() == uncompressed_size as usize && crc32(uncompressed) == crc
+                })
+                .ok_or_else(|| invalid(&format!("corrupt data of `{}`", name)))?;
+            recompressed = deflate::compress(&uncompressed, level);
+            if recompressed.len() < data.len() {
+                &recompressed
+            } else {
+                data
+            }
+        } else {
+            data
+        };
+
+        // The CRC and sizes are written to the local header instead of a data descriptor
+        let flags = flags & !FLAG_DATA_DESCRIPTOR;
+        let new_offset = out.len() as u32;
+        out.extend_from_slice(&local[..6]);
+        out.extend_from_slice(&flags.to_le_bytes());
+        out.extend_from_slice(&local[8..14]);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&uncompressed_size.to_le_bytes());
+        out.extend_from_slice(&local[26..]);
+        out.extend_from_slice(&bytes[local_offset + LOCAL_FILE_HEADER_LEN..data_start]);
+        out.extend_from_slice(data);
+
+        central_directory.extend_from_slice(&header[..8]);
+        central_directory.extend_from_slice(&flags.to_le_bytes());
+        central_directory.extend_from_slice(&header[10..20]);
+        central_directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        central_directory.extend_from_slice(&header[24..42]);
+        central_directory.extend_from_slice(&new_offset.to_le_bytes());
+        central_directory.extend_from_slice(trailer);
+        offset = trailer_start + trailer_len;
+    }
+
+    let central_directory_offset = out.len() as u32;
+    out.extend_from_slice(&central_directory);
+    out.extend_from_slice(&bytes[eocd..eocd + 12]);
+    out.extend_from_slice(&(central_directory.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_directory_offset.to_le_bytes());
+    // The comment and its length
+    out.extend_from_slice(&bytes[eocd + 20..]);
+    Ok(out)
+}
+
+/// Writes an archive that only consists of a central directory, which is all that
+/// [`read_entries`] looks at.
+#[cfg(test)]
+pub(crate) fn central_directory(entries: &[(&str, u32, u32)]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    for (name, compressed_size, uncompressed_size) in entries {
+        zip.extend_from_slice(&CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+        zip.extend_from_slice(&[0; 16]);
+        zip.extend_from_slice(&compressed_size.to_le_bytes());
+        zip.extend_from_slice(&uncompressed_size.to_le_bytes());
+        zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&[0; 16]);
+        zip.extend_from_slice(name.as_bytes());
+    }
+    let len = zip.len() as u32;
+    zip.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+    zip.extend_from_slice(&[0; 4]);
+    zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&len.to_le_bytes());
+    zip.extend_from_slice(&0u32.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+    zip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_central_directory() {
+        let mut zip = central_directory(&[("classes.dex", 10, 20), ("res/a.xml", 3, 3)]);
+        assert_eq!(
+            read_entries(&zip).unwrap(),
+            [
+                ZipEntry {
+                    name: "classes.dex".to_string(),
+                    compressed_size: 10,
+                    uncompressed_size: 20,
+                },
+                ZipEntry {
+                    name: "res/a.xml".to_string(),
+                    compressed_size: 3,
+                    uncompressed_size: 3,
+                },
+            ]
+        );
+
+        // A trailing comment
+        let comment_len = zip.len() - 2;
+        zip[comment_len..].copy_from_slice(&4u16.to_le_bytes());
+        zip.extend_from_slice(b"note");
+        assert_eq!(read_entries(&zip).unwrap().len(), 2);
+
+        assert!(read_entries(b"not a zip").is_err());
+        assert!(read_entries(&zip[4..]).is_err());
+    }
+
+    /// Builds an archive of `(name, method, data)` entries, deflated entries at level 1 and
+    /// with a data descriptor
+    fn archive(entries: &[(&str, u16, &[u8])]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut central_directory = Vec::new();
+        for &(name, method, data) in entries {
+            let (flags, compressed) = if method == METHOD_DEFLATED {
+                (FLAG_DATA_DESCRIPTOR, deflate::compress(data, 1))
+            } else {
+                (0, data.to_vec())
+            };
+            let mut header = Vec::new();
+            header.extend_from_slice(&[20, 0]);
+            header.extend_from_slice(&flags.to_le_bytes());
+            header.extend_from_slice(&method.to_le_bytes());
+            header.extend_from_slice(&[0; 4]);
+            header.extend_from_slice(&crc32(data).to_le_bytes());
+            header.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&[0; 2]);
+
+            central_directory.extend_from_slice(&CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+            central_directory.extend_from_slice(&[20, 0]);
+            central_directory.extend_from_slice(&header);
+            central_directory.extend_from_slice(&[0; 10]);
+            central_directory.extend_from_slice(&(zip.len() as u32).to_le_bytes());
+            central_directory.extend_from_slice(name.as_bytes());
+
+            zip.extend_from_slice(&LOCAL_FILE_HEADER.to_le_bytes());
+            zip.extend_from_slice(&header);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(&compressed);
+        }
+        let offset = zip.len() as u32;
+        zip.extend_from_slice(&central_directory);
+        zip.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(central_directory.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&offset.to_le_bytes());
+        zip.extend_from_slice(&0u16.to_le_bytes());
+        zip
+    }
+
+    /// Returns the method and data of the entry at `index` of the central directory
+    fn entry_data(zip: &[u8], index: usize) -> (u16, Vec<u8>) {
+        let (_, mut offset, _) = find_central_directory(zip).unwrap();
+        for _ in 0..index {
+            let header = central_directory_header(zip, offset).unwrap();
+            offset += CENTRAL_DIRECTORY_HEADER_LEN + u16_at(header, 28).unwrap() as usize;
+        }
+        let header = central_directory_header(zip, offset).unwrap();
+        let method = u16_at(header, 10).unwrap();
+        let local = u32_at(header, 42).unwrap() as usize;
+        let start = local + LOCAL_FILE_HEADER_LEN + u16_at(zip, local + 26).unwrap() as usize;
+        // The local header carries the sizes once the data descriptor is dropped
+        assert_eq!(u16_at(zip, local + 6).unwrap() & FLAG_DATA_DESCRIPTOR, 0);
+        assert_eq!(u32_at(zip, local + 18), u32_at(header, 20));
+        let data = &zip[start..start + u32_at(header, 20).unwrap() as usize];
+        if method == METHOD_DEFLATED {
+            let size = u32_at(header, 24).unwrap() as usize;
+            (method, deflate::decompress(data, size).unwrap().0)
+        } else {
+            (method, data.to_vec())
+        }
+    }
+
+    #[test]
+    fn recompress_deflated_entries() {
+        let dex = include_bytes!("apk.rs");
+        let manifest = include_bytes!("manifest.rs");
+        let lib = include_bytes!("zip.rs");
+        let entries: [(&str, u16, &[u8]); 4] = [
+            ("AndroidManifest.xml", METHOD_DEFLATED, manifest),
+            ("classes.dex", METHOD_DEFLATED, dex),
+            ("lib/arm64-v8a/libmain.so", 0, lib),
+            ("resources.arsc", 0, b"table"),
+        ];
+        let zip = archive(&entries);
+        let recompressed = recompress(&zip, 9).unwrap();
+        assert!(recompressed.len() < zip.len());
+
+        let before = read_entries(&zip).unwrap();
+        let after = read_entries(&recompressed).unwrap();
+        assert_eq!(after.len(), entries.len());
+        for (index, (before, after)) in before.iter().zip(&after).enumerate() {
+            assert_eq!(before.name, after.name);
+            assert_eq!(before.uncompressed_size, after.uncompressed_size);
+            assert!(after.compressed_size <= before.compressed_size);
+            let (method, data) = entry_data(&recompressed, index);
+            assert_eq!(method, entries[index].1);
+            assert_eq!(data, entries[index].2);
+        }
+        // Stored entries are not touched
+        assert_eq!(after[2].compressed_size, lib.len() as u64);
+        assert_eq!(after[3].compressed_size, 5);
+
+        // Corrupt deflated data is not silently rewritten
+        let mut corrupt = zip;
+        let start = LOCAL_FILE_HEADER_LEN + "AndroidManifest.xml".len();
+        corrupt[start + 100] ^= 0xff;
+        assert!(recompress(&corrupt, 9).is_err());
+    }
+}